@@ -0,0 +1,171 @@
+//! Async variants of the operations on the observing environment, for
+//! callers running in a tokio runtime (e.g. the sidecar or an HTTP server).
+//!
+//! The git operations and EFD queries are blocking, so they are run with
+//! `tokio::task::spawn_blocking` instead of blocking the runtime.
+
+use crate::{
+    error::ObsEnvError,
+    git_backend::{Git2Backend, GitBackend},
+    manage_obs_env::{run, ManageObsEnvCli},
+    observing_environment::{CloneOutcome, ObservingEnvironment},
+    outcome::ActionOutcome,
+    sasquatch::efd_client::{EfdClient, EfdQuery},
+};
+use serde::de::DeserializeOwned;
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::task;
+
+/// Run `f` in the blocking thread pool of the runtime.
+async fn spawn_blocking<F, R>(f: F) -> Result<R, ObsEnvError>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    task::spawn_blocking(f)
+        .await
+        .map_err(|error| ObsEnvError::ERROR(format!("Blocking task failed: {error}")))
+}
+
+/// Observing environment shared with the blocking tasks running its
+/// operations.
+pub struct AsyncObservingEnvironment<G: GitBackend = Git2Backend> {
+    obs_env: Arc<ObservingEnvironment<G>>,
+}
+
+impl<G: GitBackend> Clone for AsyncObservingEnvironment<G> {
+    fn clone(&self) -> Self {
+        AsyncObservingEnvironment {
+            obs_env: Arc::clone(&self.obs_env),
+        }
+    }
+}
+
+impl<G: GitBackend + Send + Sync + 'static> AsyncObservingEnvironment<G> {
+    pub fn new(obs_env: ObservingEnvironment<G>) -> AsyncObservingEnvironment<G> {
+        AsyncObservingEnvironment {
+            obs_env: Arc::new(obs_env),
+        }
+    }
+
+    pub fn get_obs_env(&self) -> &ObservingEnvironment<G> {
+        &self.obs_env
+    }
+
+    /// See `ObservingEnvironment::clone_repositories`.
+    pub async fn clone_repositories(&self) -> Result<BTreeMap<String, CloneOutcome>, ObsEnvError> {
+        let obs_env = Arc::clone(&self.obs_env);
+        spawn_blocking(move || obs_env.clone_repositories()).await
+    }
+
+    /// See `ObservingEnvironment::reset_base_environment`.
+    pub async fn reset_base_environment(
+        &self,
+        base_env_branch: &str,
+        run_branch: &str,
+    ) -> Result<Result<(), Vec<(String, ObsEnvError)>>, ObsEnvError> {
+        let obs_env = Arc::clone(&self.obs_env);
+        let (base_env_branch, run_branch) = (base_env_branch.to_owned(), run_branch.to_owned());
+        spawn_blocking(move || obs_env.reset_base_environment(&base_env_branch, &run_branch)).await
+    }
+
+    /// See `ObservingEnvironment::checkout_branch`.
+    pub async fn checkout_branch(
+        &self,
+        repo_name: &str,
+        branch_name: &str,
+    ) -> Result<(), ObsEnvError> {
+        let obs_env = Arc::clone(&self.obs_env);
+        let (repo_name, branch_name) = (repo_name.to_owned(), branch_name.to_owned());
+        spawn_blocking(move || obs_env.checkout_branch(&repo_name, &branch_name)).await?
+    }
+
+    /// See `ObservingEnvironment::get_base_env_versions`.
+    pub async fn get_base_env_versions(
+        &self,
+        base_env_branch: &str,
+    ) -> Result<BTreeMap<String, String>, ObsEnvError> {
+        let obs_env = Arc::clone(&self.obs_env);
+        let base_env_branch = base_env_branch.to_owned();
+        spawn_blocking(move || obs_env.get_base_env_versions(&base_env_branch)).await?
+    }
+
+    /// See `ObservingEnvironment::get_current_env_versions`.
+    pub async fn get_current_env_versions(
+        &self,
+    ) -> Result<BTreeMap<String, Result<String, ObsEnvError>>, ObsEnvError> {
+        let obs_env = Arc::clone(&self.obs_env);
+        spawn_blocking(move || obs_env.get_current_env_versions()).await
+    }
+
+    /// See `ObservingEnvironment::create_setup_file`.
+    pub async fn create_setup_file(&self) -> Result<(), ObsEnvError> {
+        let obs_env = Arc::clone(&self.obs_env);
+        spawn_blocking(move || obs_env.create_setup_file()).await?
+    }
+}
+
+/// Query the records of `query` in the EFD instance `efd_name`.
+pub async fn query_efd_records<T>(efd_name: &str, query: EfdQuery) -> Result<Vec<T>, ObsEnvError>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    let efd_name = efd_name.to_owned();
+    spawn_blocking(move || {
+        EfdClient::from_efd_name(&efd_name)
+            .and_then(|efd_client| efd_client.query_records(&query))
+            .map_err(|error| ObsEnvError::ERROR(format!("Failed to query {efd_name} EFD: {error}")))
+    })
+    .await?
+}
+
+/// Run the action of `config`, see `manage_obs_env::run`.
+pub async fn run_async<T>(config: T) -> Result<ActionOutcome, ObsEnvError>
+where
+    T: ManageObsEnvCli + Send + 'static,
+{
+    spawn_blocking(move || run(&config).map_err(|error| ObsEnvError::ERROR(error.to_string())))
+        .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncObservingEnvironment;
+    use crate::{
+        git_backend::FakeGitBackend,
+        observing_environment::{ObservingEnvironment, RepositoryConfig},
+    };
+    use std::{collections::BTreeMap, path::Path};
+
+    #[test]
+    fn test_async_obs_env() {
+        let obs_env = ObservingEnvironment::builder()
+            .with_destination("/obs-env")
+            .with_repositories(BTreeMap::from([(
+                "ts_wep".to_owned(),
+                RepositoryConfig::new("https://github.com/lsst-ts"),
+            )]))
+            .with_git_backend(FakeGitBackend::new().with_remote(
+                "https://github.com/lsst-ts/ts_wep",
+                &["v9.0.0"],
+                &["develop"],
+            ))
+            .build();
+        let obs_env = AsyncObservingEnvironment::new(obs_env);
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let cloned = obs_env.clone_repositories().await.unwrap();
+            assert_eq!(
+                cloned["ts_wep"].get_path(),
+                Some(Path::new("/obs-env/ts_wep"))
+            );
+            obs_env.checkout_branch("ts_wep", "develop").await.unwrap();
+            assert!(obs_env.checkout_branch("ts_wep", "main").await.is_err());
+            let versions = obs_env.get_current_env_versions().await.unwrap();
+            assert_eq!(versions["ts_wep"].as_ref().unwrap(), "develop");
+        });
+    }
+}
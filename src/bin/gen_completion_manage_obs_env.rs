@@ -1,11 +1,22 @@
 use clap::CommandFactory;
 use clap_complete::{generate, Shell};
-use std::io;
+use std::{env, io, process};
 use ts_observing_environment::manage_obs_env::ManageObsEnv;
 
 fn main() {
+    let shell = match env::args().nth(1) {
+        Some(shell) => match shell.parse::<Shell>() {
+            Ok(shell) => shell,
+            Err(error) => {
+                eprintln!("{error}. Supported shells: bash, elvish, fish, powershell, zsh.");
+                process::exit(1);
+            }
+        },
+        None => Shell::Bash,
+    };
+
     let mut command = ManageObsEnv::command();
     let bin_name = command.get_name().to_string();
 
-    generate(Shell::Bash, &mut command, bin_name, &mut io::stdout())
+    generate(shell, &mut command, bin_name, &mut io::stdout())
 }
@@ -1,14 +1,47 @@
-use clap::Parser;
-use simple_logger::SimpleLogger;
 use std::process;
-use ts_observing_environment::manage_obs_env::{run, ManageObsEnv};
+use ts_observing_environment::{
+    cancellation::CancellationToken,
+    logging,
+    manage_obs_env::{run, run_on_schedule, Action, ManageObsEnv, ManageObsEnvCli},
+};
 
 fn main() {
-    SimpleLogger::new().init().unwrap();
+    let args = match ManageObsEnv::parse_with_config() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Failed to load configuration: {e}");
+            process::exit(1);
+        }
+    };
 
-    let args = ManageObsEnv::parse();
+    if let Err(e) = logging::init(
+        args.get_log_file(),
+        args.get_log_file_max_size(),
+        args.get_log_file_keep(),
+    ) {
+        eprintln!("Failed to initialize logging: {:?}", e);
+        process::exit(1);
+    }
+
+    // Outside of --schedule mode, which completes the run in progress on
+    // SIGTERM and SIGINT, these stop Setup and Reset between repositories.
+    let cancellation = CancellationToken::new();
+    if args.get_schedule().is_none()
+        && matches!(args.get_action(), Ok(Action::Setup | Action::Reset))
+    {
+        if let Err(e) = cancellation.cancel_on_signals() {
+            eprintln!("Failed to install the signal handlers: {:?}", e);
+            process::exit(1);
+        }
+    }
+    let args = args.with_cancellation_token(cancellation);
+
+    let result = match args.get_schedule() {
+        Some(schedule) => run_on_schedule(&args, schedule),
+        None => run(&args).map(|_| ()),
+    };
 
-    if let Err(e) = run(&args) {
+    if let Err(e) = result {
         eprintln!("Application error: {:?}", e);
         process::exit(1);
     }
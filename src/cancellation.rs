@@ -0,0 +1,102 @@
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    SigId,
+};
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Token to cancel a long-running operation on the environment (Setup or
+/// Reset) from another thread, a signal handler or an aborted request.
+///
+/// Clones of the token share its state: cancelling any of them cancels
+/// them all. Operations check the token between repositories, so the
+/// repository in progress is completed and the remaining ones are skipped.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    signal_handlers: Arc<SignalHandlers>,
+}
+
+/// Signal handlers installed by `cancel_on_signals`, removed when the last
+/// clone of the token is dropped.
+#[derive(Debug, Default)]
+struct SignalHandlers(Mutex<Vec<SigId>>);
+
+impl Drop for SignalHandlers {
+    fn drop(&mut self) {
+        let handlers = self.0.get_mut().unwrap_or_else(|error| error.into_inner());
+        for handler in handlers.drain(..) {
+            signal_hook::low_level::unregister(handler);
+        }
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancel the operations using the token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Cancel the token when the process receives SIGTERM or SIGINT,
+    /// instead of terminating it. A signal received once the token is
+    /// cancelled terminates the process, so it can still be stopped while
+    /// an operation is stuck on a repository.
+    ///
+    /// The handlers are process-wide and stay installed until the last
+    /// clone of the token is dropped. Calling this again does nothing.
+    pub fn cancel_on_signals(&self) -> io::Result<()> {
+        let mut handlers = self
+            .signal_handlers
+            .0
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if !handlers.is_empty() {
+            return Ok(());
+        }
+        for signal in [SIGTERM, SIGINT] {
+            // Registered first, so it only sees the token cancelled by an
+            // earlier signal.
+            handlers.push(signal_hook::flag::register_conditional_shutdown(
+                signal,
+                128 + signal,
+                Arc::clone(&self.cancelled),
+            )?);
+            handlers.push(signal_hook::flag::register(
+                signal,
+                Arc::clone(&self.cancelled),
+            )?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CancellationToken;
+    use signal_hook::consts::SIGINT;
+
+    #[test]
+    fn test_cancel_on_signals() {
+        let token = CancellationToken::new();
+        token.cancel_on_signals().unwrap();
+        token.cancel_on_signals().unwrap();
+        assert_eq!(token.signal_handlers.0.lock().unwrap().len(), 4);
+        assert!(!token.is_cancelled());
+
+        signal_hook::low_level::raise(SIGINT).unwrap();
+        assert!(token.clone().is_cancelled());
+    }
+}
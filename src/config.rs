@@ -0,0 +1,221 @@
+use crate::{
+    error::ObsEnvError,
+    setup_template::{SetupFileRefresh, SetupMode, SetupShell, SetupTemplate},
+};
+use clap::ValueEnum;
+use std::{env, fs, path::Path, str::FromStr};
+
+/// Environment variable with the path of the configuration file of
+/// manage_obs_env, when --config is not given.
+pub const CONFIG_ENV_VAR: &str = "MANAGE_OBS_ENV_CONFIG";
+
+/// Configuration of manage_obs_env and the observing environment, read from
+/// a TOML file, e.g.:
+///
+/// ```toml
+/// env_path = "/net/obs-env/auto_base_packages"
+/// setup_shells = ["bash", "csh"]
+/// efd_name = "summit_efd"
+/// ```
+///
+/// All the settings are optional, the ones that are not set keep their
+/// defaults. Each setting can be overridden by the MANAGE_OBS_ENV_<SETTING>
+/// environment variable (e.g. MANAGE_OBS_ENV_ENV_PATH), see
+/// `with_env_overrides`, and the settings that are also command line
+/// options by the command line, see `ManageObsEnv::from_matches_with_config`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub env_path: Option<String>,
+    pub base_env_branch_name: Option<String>,
+    pub base_env_def_file: Option<String>,
+    pub base_env_local_file: Option<String>,
+    /// Templates of the setup files, as `[SHELL=]PATH`, see `SetupTemplate`.
+    pub setup_templates: Option<Vec<SetupTemplate>>,
+    pub setup_shells: Option<Vec<SetupShell>>,
+    pub setup_mode: Option<SetupMode>,
+    pub setup_file_backups: Option<usize>,
+    pub setup_file_dir: Option<String>,
+    pub setup_file_name: Option<String>,
+    pub skip_setup_file: Option<bool>,
+    pub setup_file_refresh: Option<SetupFileRefresh>,
+    pub hooks_dir: Option<String>,
+    pub log_file: Option<String>,
+    pub telemetry_spool_dir: Option<String>,
+    pub offline: Option<bool>,
+    pub telemetry_journal: Option<String>,
+    /// EFD instance of the environment, for the actions reading the run
+    /// branch or lock from the EFD.
+    pub efd_name: Option<String>,
+    /// Url to notify of the outcome of the actions modifying the
+    /// environment.
+    pub webhook_url: Option<String>,
+}
+
+/// Parse a boolean setting, accepting "1" and "0" as well.
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" => Ok(true),
+        "0" | "false" => Ok(false),
+        _ => Err(format!("expected true or false, got {value:?}")),
+    }
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("expected a number, got {value:?}"))
+}
+
+/// Set `setting` from the MANAGE_OBS_ENV_<name> environment variable, if
+/// it is set.
+fn override_from_env<T>(
+    setting: &mut Option<T>,
+    name: &str,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<(), ObsEnvError> {
+    let variable = format!("MANAGE_OBS_ENV_{name}");
+    if let Ok(value) = env::var(&variable) {
+        *setting = Some(
+            parse(&value)
+                .map_err(|error| ObsEnvError::ERROR(format!("Invalid {variable}: {error}")))?,
+        );
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Parse the content of a configuration file.
+    pub fn parse(content: &str) -> Result<Config, ObsEnvError> {
+        toml::from_str(content)
+            .map_err(|error| ObsEnvError::ERROR(format!("Invalid configuration: {error}")))
+    }
+
+    /// Read the configuration file at `path`.
+    pub fn read(path: &Path) -> Result<Config, ObsEnvError> {
+        let content = fs::read_to_string(path).map_err(|error| {
+            ObsEnvError::ERROR(format!("Failed to read configuration {path:?}: {error}"))
+        })?;
+        Config::parse(&content).map_err(|error| ObsEnvError::ERROR(format!("{path:?}: {error}")))
+    }
+
+    /// Override the settings with the MANAGE_OBS_ENV_<SETTING> environment
+    /// variables that are set. Lists (setup_shells, setup_templates) are comma
+    /// separated.
+    pub fn with_env_overrides(mut self) -> Result<Config, ObsEnvError> {
+        let string = |value: &str| Ok(value.to_owned());
+        override_from_env(&mut self.env_path, "ENV_PATH", string)?;
+        override_from_env(
+            &mut self.base_env_branch_name,
+            "BASE_ENV_BRANCH_NAME",
+            string,
+        )?;
+        override_from_env(&mut self.base_env_def_file, "BASE_ENV_DEF_FILE", string)?;
+        override_from_env(&mut self.base_env_local_file, "BASE_ENV_LOCAL_FILE", string)?;
+        override_from_env(&mut self.setup_templates, "SETUP_TEMPLATES", |value| {
+            value
+                .split(',')
+                .map(|template| template.trim().parse())
+                .collect()
+        })?;
+        override_from_env(&mut self.setup_shells, "SETUP_SHELLS", |value| {
+            value
+                .split(',')
+                .map(|shell| SetupShell::from_str(shell.trim(), true))
+                .collect()
+        })?;
+        override_from_env(&mut self.setup_mode, "SETUP_MODE", |value| {
+            SetupMode::from_str(value, true)
+        })?;
+        override_from_env(
+            &mut self.setup_file_backups,
+            "SETUP_FILE_BACKUPS",
+            parse_number,
+        )?;
+        override_from_env(&mut self.setup_file_dir, "SETUP_FILE_DIR", string)?;
+        override_from_env(&mut self.setup_file_name, "SETUP_FILE_NAME", string)?;
+        override_from_env(&mut self.skip_setup_file, "SKIP_SETUP_FILE", parse_bool)?;
+        override_from_env(
+            &mut self.setup_file_refresh,
+            "SETUP_FILE_REFRESH",
+            |value| SetupFileRefresh::from_str(value, true),
+        )?;
+        override_from_env(&mut self.hooks_dir, "HOOKS_DIR", string)?;
+        override_from_env(&mut self.log_file, "LOG_FILE", string)?;
+        override_from_env(&mut self.telemetry_spool_dir, "TELEMETRY_SPOOL_DIR", string)?;
+        override_from_env(&mut self.offline, "OFFLINE", parse_bool)?;
+        override_from_env(&mut self.telemetry_journal, "TELEMETRY_JOURNAL", string)?;
+        override_from_env(&mut self.efd_name, "EFD_NAME", string)?;
+        override_from_env(&mut self.webhook_url, "WEBHOOK_URL", string)?;
+        Ok(self)
+    }
+
+    /// Configuration from the file at `path`, if given, overridden by the
+    /// environment variables.
+    pub fn load(path: Option<&Path>) -> Result<Config, ObsEnvError> {
+        match path {
+            Some(path) => Config::read(path),
+            None => Ok(Config::default()),
+        }?
+        .with_env_overrides()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{override_from_env, parse_bool, Config};
+    use crate::setup_template::{SetupMode, SetupShell, SetupTemplate};
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            "env_path = \"/obs-env\"\n\
+            setup_shells = [\"bash\", \"csh\"]\n\
+            setup_mode = \"pythonpath\"\n\
+            setup_templates = [\"csh=setup.csh.j2\"]\n\
+            setup_file_backups = 2\n\
+            efd_name = \"summit_efd\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.env_path.as_deref(), Some("/obs-env"));
+        assert_eq!(
+            config.setup_shells,
+            Some(vec![SetupShell::Bash, SetupShell::Csh])
+        );
+        assert_eq!(config.setup_mode, Some(SetupMode::Pythonpath));
+        assert_eq!(
+            config.setup_templates,
+            Some(vec![SetupTemplate::for_shell(
+                SetupShell::Csh,
+                "setup.csh.j2"
+            )])
+        );
+        assert_eq!(config.setup_file_backups, Some(2));
+        assert_eq!(config.efd_name.as_deref(), Some("summit_efd"));
+        assert_eq!(config.hooks_dir, None);
+
+        assert!(Config::parse("").unwrap() == Config::default());
+        assert!(Config::parse("env-path = \"/obs-env\"").is_err());
+        assert!(Config::parse("setup_mode = \"other\"").is_err());
+    }
+
+    #[test]
+    fn test_override_from_env() {
+        // Only variables set by this test, to not interfere with the others.
+        std::env::set_var("MANAGE_OBS_ENV_TEST_OVERRIDE", "1");
+        std::env::set_var("MANAGE_OBS_ENV_TEST_INVALID_OVERRIDE", "yes");
+
+        let mut setting = Some(false);
+        override_from_env(&mut setting, "TEST_OVERRIDE", parse_bool).unwrap();
+        assert_eq!(setting, Some(true));
+        let mut setting = None;
+        override_from_env(&mut setting, "TEST_UNSET_OVERRIDE", parse_bool).unwrap();
+        assert_eq!(setting, None);
+        let error = override_from_env(&mut setting, "TEST_INVALID_OVERRIDE", parse_bool);
+        assert!(error
+            .unwrap_err()
+            .to_string()
+            .contains("MANAGE_OBS_ENV_TEST_INVALID_OVERRIDE"));
+    }
+}
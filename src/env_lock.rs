@@ -0,0 +1,340 @@
+use crate::{
+    error::ObsEnvError,
+    identity::{get_hostname, get_user},
+};
+use chrono::{TimeZone, Utc};
+use std::{
+    ffi::OsString,
+    fs::{hard_link, read_to_string, remove_file, rename, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const LOCK_FILE_NAME: &str = ".obs_env.lock";
+
+/// Information about the holder of the environment lock.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LockInfo {
+    /// User holding the lock.
+    pub owner: String,
+    /// Host where the process holding the lock runs.
+    pub host: String,
+    /// Id of the process holding the lock.
+    pub pid: u32,
+    /// Time the lock was acquired, in milliseconds since the epoch.
+    pub timestamp: i64,
+    /// Action being executed by the lock holder.
+    pub action: String,
+}
+
+/// Advisory lock on the observing environment.
+///
+/// The lock is a file in the environment directory, created when the lock
+/// is acquired and removed when it is dropped.
+#[derive(Debug)]
+pub struct EnvLock {
+    path: PathBuf,
+    info: LockInfo,
+}
+
+impl LockInfo {
+    fn new(action: &str) -> LockInfo {
+        LockInfo {
+            owner: get_user(),
+            host: get_hostname(),
+            pid: process::id(),
+            timestamp: Utc::now().timestamp_millis(),
+            action: action.to_owned(),
+        }
+    }
+
+    /// Check if `other` describes the same lock holder.
+    fn is_same_holder(&self, other: &LockInfo) -> bool {
+        self.host == other.host && self.pid == other.pid && self.timestamp == other.timestamp
+    }
+
+    /// Check if the lock holder is known to be gone.
+    ///
+    /// This can only be determined when the lock holder runs on this host.
+    fn is_stale(&self) -> bool {
+        self.host == get_hostname() && !Path::new(&format!("/proc/{}", self.pid)).exists()
+    }
+}
+
+impl std::fmt::Display for LockInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let acquired = match Utc.timestamp_millis_opt(self.timestamp) {
+            chrono::LocalResult::Single(time) => time.to_rfc3339(),
+            _ => self.timestamp.to_string(),
+        };
+        write!(
+            f,
+            "{} (pid {} on {}) running {} since {}",
+            self.owner, self.pid, self.host, self.action, acquired
+        )
+    }
+}
+
+impl EnvLock {
+    /// Acquire the lock on the environment at `env_path` to execute
+    /// `action`.
+    ///
+    /// Fails if the environment is already locked, unless the lock was left
+    /// behind by a process on this host that no longer exists.
+    ///
+    /// The lock information is written to a temporary file, hard linked as
+    /// the lock file, so the lock file is never seen partially written,
+    /// even on NFS.
+    pub fn acquire(env_path: &str, action: &str) -> Result<EnvLock, ObsEnvError> {
+        let path = EnvLock::get_path(env_path);
+        let info = LockInfo::new(action);
+
+        let temp_path = write_temp_lock_file(&path, &info)?;
+        let result = EnvLock::link_lock_file(env_path, &path, &temp_path);
+        if let Err(error) = remove_lock_file(&temp_path) {
+            log::warn!("{error}");
+        }
+        result?;
+        log::debug!("Acquired environment lock {path:?}.");
+        Ok(EnvLock { path, info })
+    }
+
+    /// Link `temp_path` as the lock file `path`, taking over stale locks.
+    fn link_lock_file(env_path: &str, path: &Path, temp_path: &Path) -> Result<(), ObsEnvError> {
+        for _ in 0..2 {
+            match hard_link(temp_path, path) {
+                Ok(()) => return Ok(()),
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                    match EnvLock::read(env_path)? {
+                        Some(holder) if holder.is_stale() => {
+                            log::warn!("Removing stale environment lock held by {holder}.");
+                            remove_stale_lock_file(path, &holder)?;
+                        }
+                        Some(holder) => {
+                            return Err(ObsEnvError::LOCK(format!(
+                                "Environment {env_path} is locked by {holder}. \
+                                If you are sure nobody is using it, run the unlock action \
+                                with --force."
+                            )))
+                        }
+                        None => {}
+                    }
+                }
+                Err(error) => {
+                    return Err(ObsEnvError::ERROR(format!(
+                        "Failed to create lock file {path:?}: {error}"
+                    )))
+                }
+            }
+        }
+        Err(ObsEnvError::LOCK(format!(
+            "Failed to acquire lock on environment {env_path}."
+        )))
+    }
+
+    /// Read the current lock holder, if the environment is locked.
+    pub fn read(env_path: &str) -> Result<Option<LockInfo>, ObsEnvError> {
+        let path = EnvLock::get_path(env_path);
+        match read_to_string(&path) {
+            Ok(content) => match serde_json::from_str(&content) {
+                Ok(info) => Ok(Some(info)),
+                Err(error) => Err(ObsEnvError::LOCK(format!(
+                    "Failed to parse lock file {path:?}: {error}. \
+                    Run the unlock action with --force to remove it."
+                ))),
+            },
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(ObsEnvError::ERROR(format!(
+                "Failed to read lock file {path:?}: {error}"
+            ))),
+        }
+    }
+
+    /// Remove the lock from the environment at `env_path`.
+    ///
+    /// Unless `force` is set, only locks held by the current user, or left
+    /// behind by a process on this host that no longer exists, are removed.
+    pub fn release(env_path: &str, force: bool) -> Result<Option<LockInfo>, ObsEnvError> {
+        let path = EnvLock::get_path(env_path);
+        let holder = if force {
+            EnvLock::read(env_path).unwrap_or(None)
+        } else {
+            match EnvLock::read(env_path)? {
+                Some(holder) if holder.owner != get_user() && !holder.is_stale() => {
+                    return Err(ObsEnvError::LOCK(format!(
+                        "Environment {env_path} is locked by {holder}. \
+                        Use --force to remove the lock anyway."
+                    )))
+                }
+                holder => holder,
+            }
+        };
+        if path.exists() {
+            remove_lock_file(&path)?;
+        }
+        Ok(holder)
+    }
+
+    pub fn get_info(&self) -> &LockInfo {
+        &self.info
+    }
+
+    fn get_path(env_path: &str) -> PathBuf {
+        Path::new(env_path).join(LOCK_FILE_NAME)
+    }
+}
+
+impl Drop for EnvLock {
+    fn drop(&mut self) {
+        match remove_lock_file(&self.path) {
+            Ok(()) => log::debug!("Released environment lock {:?}.", self.path),
+            Err(error) => log::error!("{error}"),
+        }
+    }
+}
+
+/// Unique path next to the lock file `path`, for this process.
+fn get_unique_path(path: &Path, kind: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let mut unique_path = OsString::from(path);
+    unique_path.push(format!(
+        ".{kind}.{}.{}.{}",
+        get_hostname(),
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    PathBuf::from(unique_path)
+}
+
+/// Write `info` to a new temporary file next to the lock file `path`,
+/// removing it if writing fails.
+fn write_temp_lock_file(path: &Path, info: &LockInfo) -> Result<PathBuf, ObsEnvError> {
+    let content =
+        serde_json::to_string(info).map_err(|error| ObsEnvError::ERROR(error.to_string()))?;
+    let temp_path = get_unique_path(path, "tmp");
+    let result = File::options()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)
+        .and_then(|mut file| file.write_all(content.as_bytes()));
+    if let Err(error) = result {
+        // The file may not have been created, so failing to remove it is
+        // expected.
+        let _ = remove_file(&temp_path);
+        return Err(ObsEnvError::ERROR(format!(
+            "Failed to write lock file {temp_path:?}: {error}"
+        )));
+    }
+    Ok(temp_path)
+}
+
+/// Remove the lock file `path`, found stale with `holder`.
+///
+/// The lock file is renamed first, so only one process takes over a stale
+/// lock: if the renamed file is not held by `holder` anymore, another
+/// process acquired the lock in the meantime, so it is put back and this
+/// fails.
+fn remove_stale_lock_file(path: &Path, holder: &LockInfo) -> Result<(), ObsEnvError> {
+    let stale_path = get_unique_path(path, "stale");
+    match rename(path, &stale_path) {
+        Ok(()) => {}
+        // Another process removed the stale lock.
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(error) => {
+            return Err(ObsEnvError::ERROR(format!(
+                "Failed to remove lock file {path:?}: {error}"
+            )))
+        }
+    }
+    let renamed_holder = read_to_string(&stale_path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<LockInfo>(&content).ok());
+    match renamed_holder {
+        Some(renamed_holder) if renamed_holder.is_same_holder(holder) => {
+            remove_lock_file(&stale_path)
+        }
+        renamed_holder => {
+            if let Err(error) = hard_link(&stale_path, path) {
+                log::error!("Failed to restore lock file {path:?} from {stale_path:?}: {error}");
+            } else if let Err(error) = remove_lock_file(&stale_path) {
+                log::warn!("{error}");
+            }
+            Err(ObsEnvError::LOCK(match renamed_holder {
+                Some(renamed_holder) => format!(
+                    "Environment lock {path:?} was taken over by {renamed_holder} \
+                    while removing the stale lock held by {holder}."
+                ),
+                None => format!(
+                    "Environment lock {path:?} changed while removing the stale lock \
+                    held by {holder}."
+                ),
+            }))
+        }
+    }
+}
+
+fn remove_lock_file(path: &Path) -> Result<(), ObsEnvError> {
+    remove_file(path).map_err(|error| {
+        ObsEnvError::ERROR(format!("Failed to remove lock file {path:?}: {error}"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{remove_stale_lock_file, EnvLock, LockInfo};
+    use crate::identity::get_hostname;
+    use std::{env, fs, process};
+
+    /// Lock information of a process on this host that no longer exists.
+    fn stale_lock_info(timestamp: i64) -> LockInfo {
+        LockInfo {
+            owner: "nobody".to_owned(),
+            host: get_hostname(),
+            pid: u32::MAX,
+            timestamp,
+            action: "Setup".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_env_lock() {
+        let env_path = env::temp_dir().join(format!("obs_env_lock_test_{}", process::id()));
+        fs::create_dir_all(&env_path).unwrap();
+        let env_path = env_path.to_str().unwrap();
+
+        {
+            let lock = EnvLock::acquire(env_path, "Reset").unwrap();
+            assert_eq!(lock.get_info().pid, process::id());
+
+            let holder = EnvLock::read(env_path).unwrap().unwrap();
+            assert_eq!(holder.action, "Reset");
+
+            assert!(EnvLock::acquire(env_path, "CheckoutBranch").is_err());
+        }
+
+        assert!(EnvLock::read(env_path).unwrap().is_none());
+
+        let lock = EnvLock::acquire(env_path, "Setup").unwrap();
+        std::mem::forget(lock);
+        let holder = EnvLock::release(env_path, true).unwrap().unwrap();
+        assert_eq!(holder.action, "Setup");
+        assert!(EnvLock::read(env_path).unwrap().is_none());
+
+        let lock_path = EnvLock::get_path(env_path);
+        let stale_holder = stale_lock_info(1);
+        fs::write(&lock_path, serde_json::to_string(&stale_holder).unwrap()).unwrap();
+        // The lock file no longer has the holder found stale, it is kept.
+        assert!(remove_stale_lock_file(&lock_path, &stale_lock_info(2)).is_err());
+        assert_eq!(EnvLock::read(env_path).unwrap().unwrap().timestamp, 1);
+        {
+            let lock = EnvLock::acquire(env_path, "Reset").unwrap();
+            assert_eq!(lock.get_info().action, "Reset");
+        }
+
+        // Only the lock file is ever created in the environment.
+        assert_eq!(fs::read_dir(env_path).unwrap().count(), 0);
+        fs::remove_dir_all(env_path).unwrap();
+    }
+}
@@ -4,6 +4,8 @@ use std::{error::Error, fmt, fmt::Display};
 pub enum ObsEnvError {
     ERROR(String),
     GIT(String),
+    LOCK(String),
+    CANCELLED(String),
 }
 
 impl Error for ObsEnvError {}
@@ -18,6 +20,8 @@ impl Display for ObsEnvError {
         match self {
             ObsEnvError::ERROR(err_msg) => write!(f, "ERROR: {}", err_msg),
             ObsEnvError::GIT(err_msg) => write!(f, "GIT: {}", err_msg),
+            ObsEnvError::LOCK(err_msg) => write!(f, "LOCK: {}", err_msg),
+            ObsEnvError::CANCELLED(err_msg) => write!(f, "CANCELLED: {}", err_msg),
         }
     }
 }
@@ -0,0 +1,425 @@
+use crate::metrics::FetchStats;
+use git2::{
+    build::{CheckoutBuilder, RepoBuilder},
+    AutotagOption, BranchType, DescribeOptions, FetchOptions, RemoteCallbacks, Repository,
+    ResetType, Status, StatusOptions,
+};
+use log::{debug, trace};
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Instant,
+};
+
+/// Git operations on the repositories of the environment.
+///
+/// Repositories are given by the path of their working directory, and are
+/// fetched from their "origin" remote. Errors are returned as messages, to
+/// be given context by the caller.
+pub trait GitBackend {
+    /// Is there a repository at `path`?
+    fn is_repository(&self, path: &Path) -> bool;
+
+    /// Clone the repository at `url` into `path`.
+    ///
+    /// `progress` is called with the bytes received so far while the
+    /// repository is transferred.
+    fn clone_repository(
+        &self,
+        url: &str,
+        path: &Path,
+        progress: &dyn Fn(usize),
+    ) -> Result<FetchStats, String>;
+
+    /// Fetch `branch` from origin, or all the tags if None.
+    ///
+    /// `progress` is called as in `clone_repository`.
+    fn fetch(
+        &self,
+        path: &Path,
+        branch: Option<&str>,
+        progress: &dyn Fn(usize),
+    ) -> Result<FetchStats, String>;
+
+    /// Names of the tags matching the glob `pattern`.
+    fn get_tags(&self, path: &Path, pattern: &str) -> Result<Vec<String>, String>;
+
+    /// Checkout `tag` in the local branch `branch_name`, discarding local
+    /// changes.
+    fn checkout_tag(&self, path: &Path, tag: &str, branch_name: &str) -> Result<(), String>;
+
+    /// Checkout the fetched `branch_name` of origin in the local branch of
+    /// the same name, discarding local changes.
+    fn checkout_branch(&self, path: &Path, branch_name: &str) -> Result<(), String>;
+
+    /// Describe HEAD from the tags, or by its commit id if no tag is
+    /// reachable.
+    fn describe(&self, path: &Path) -> Result<String, String>;
+
+    /// Paths with changes that are not committed, including untracked
+    /// files.
+    fn status(&self, path: &Path) -> Result<Vec<String>, String>;
+}
+
+/// Git backend using libgit2.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Git2Backend;
+
+fn open(path: &Path) -> Result<Repository, String> {
+    Repository::open(path).map_err(message)
+}
+
+fn message(error: git2::Error) -> String {
+    error.message().to_owned()
+}
+
+impl GitBackend for Git2Backend {
+    fn is_repository(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn clone_repository(
+        &self,
+        url: &str,
+        path: &Path,
+        progress: &dyn Fn(usize),
+    ) -> Result<FetchStats, String> {
+        let start = Instant::now();
+        let received_bytes = Cell::new(0);
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.transfer_progress(|stats| {
+            received_bytes.set(stats.received_bytes());
+            progress(stats.received_bytes());
+            true
+        });
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(callbacks);
+        RepoBuilder::new()
+            .fetch_options(fetch_options)
+            .clone(url, path)
+            .map_err(message)?;
+        Ok(FetchStats::since(start, received_bytes.get()))
+    }
+
+    fn fetch(
+        &self,
+        path: &Path,
+        branch: Option<&str>,
+        progress: &dyn Fn(usize),
+    ) -> Result<FetchStats, String> {
+        let repository = open(path)?;
+        let start = Instant::now();
+        let mut remote = repository.find_remote("origin").map_err(message)?;
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.transfer_progress(|stats| {
+            progress(stats.received_bytes());
+            true
+        });
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(callbacks);
+        let refspec = match branch {
+            Some(branch) => branch,
+            None => {
+                fetch_options.download_tags(AutotagOption::All);
+                ""
+            }
+        };
+        remote
+            .fetch(&[refspec], Some(&mut fetch_options), None)
+            .map_err(message)?;
+        Ok(FetchStats::since(start, remote.stats().received_bytes()))
+    }
+
+    fn get_tags(&self, path: &Path, pattern: &str) -> Result<Vec<String>, String> {
+        let tags = open(path)?.tag_names(Some(pattern)).map_err(message)?;
+        Ok(tags.iter().flatten().map(|tag| tag.to_owned()).collect())
+    }
+
+    fn checkout_tag(&self, path: &Path, tag: &str, branch_name: &str) -> Result<(), String> {
+        let repository = open(path)?;
+        let spec = format!("refs/tags/{tag}");
+        trace!("Checkout spec {spec}");
+        let object = repository.revparse_single(&spec).map_err(message)?;
+        let commit = object.peel_to_commit().map_err(message)?;
+        repository
+            .branch(branch_name, &commit, true)
+            .map_err(message)?;
+        repository.set_head(&spec).map_err(message)?;
+        let mut checkout_build = CheckoutBuilder::new();
+        repository
+            .reset(&object, ResetType::Hard, Some(checkout_build.force()))
+            .map_err(message)
+    }
+
+    fn checkout_branch(&self, path: &Path, branch_name: &str) -> Result<(), String> {
+        let repository = open(path)?;
+        let remote_branch_name = format!("origin/{branch_name}");
+        let branch = repository
+            .find_branch(&remote_branch_name, BranchType::Remote)
+            .map_err(message)?;
+
+        let branch_reference = branch.into_reference();
+        let commit = branch_reference.peel_to_commit().map_err(message)?;
+
+        trace!("Checking out temporary branch");
+        let temp_branch = repository.branch("temp", &commit, true).map_err(message)?;
+        let Some(temp_refname) = temp_branch.get().name() else {
+            return Err("Temporary branch name is not valid UTF-8".to_owned());
+        };
+        repository.set_head(temp_refname).map_err(message)?;
+
+        trace!("Checking out branch {branch_name}");
+        let local_branch = repository
+            .branch(branch_name, &commit, true)
+            .map_err(message)?;
+        trace!("Branch {branch_name} checked out ok.");
+
+        let Some(upstream_name) = branch_reference.name() else {
+            return Err(format!("Name of {remote_branch_name} is not valid UTF-8"));
+        };
+        debug!("Upstream name: {upstream_name}");
+        let object = repository.revparse_single(upstream_name).map_err(message)?;
+        let mut checkout_build = CheckoutBuilder::new();
+        repository
+            .reset(&object, ResetType::Hard, Some(checkout_build.force()))
+            .map_err(message)?;
+        let Some(refname) = local_branch.get().name() else {
+            return Err(format!("Name of branch {branch_name} is not valid UTF-8"));
+        };
+        repository.set_head(refname).map_err(message)
+    }
+
+    fn describe(&self, path: &Path) -> Result<String, String> {
+        let repository = open(path)?;
+        let mut opts = DescribeOptions::new();
+        let description = match repository.describe(opts.describe_tags()) {
+            Ok(description) => description,
+            Err(_) => repository
+                .describe(opts.show_commit_oid_as_fallback(true))
+                .map_err(message)?,
+        };
+        description.format(None).map_err(message)
+    }
+
+    fn status(&self, path: &Path) -> Result<Vec<String>, String> {
+        let repository = open(path)?;
+        let mut options = StatusOptions::new();
+        options.include_untracked(true);
+        let statuses = repository.statuses(Some(&mut options)).map_err(message)?;
+        Ok(statuses
+            .iter()
+            .filter(|entry| entry.status() != Status::CURRENT)
+            .filter(|entry| !entry.status().contains(Status::IGNORED))
+            .filter_map(|entry| entry.path().map(|path| path.to_owned()))
+            .collect())
+    }
+}
+
+/// Repository of a `FakeGitBackend`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FakeRepository {
+    /// Tags of the repository, as fetched from origin.
+    pub tags: Vec<String>,
+    /// Branches of origin.
+    pub branches: Vec<String>,
+    /// Tag or branch checked out, given as the description of HEAD.
+    pub head: String,
+    /// Paths with changes that are not committed.
+    pub changes: Vec<String>,
+}
+
+/// In-memory git backend, to use `ObservingEnvironment` without real
+/// repositories or network, e.g. in tests.
+///
+/// Remotes are registered, by url, with `with_remote`. Cloning one creates a
+/// repository at the clone path with the tags and branches of the remote,
+/// and its first branch checked out. Fetches do nothing.
+#[derive(Debug, Default)]
+pub struct FakeGitBackend {
+    remotes: BTreeMap<String, FakeRepository>,
+    repositories: Mutex<BTreeMap<PathBuf, FakeRepository>>,
+}
+
+impl FakeGitBackend {
+    pub fn new() -> FakeGitBackend {
+        FakeGitBackend::default()
+    }
+
+    /// Add a remote repository, at `url`, with `tags` and `branches`.
+    pub fn with_remote(mut self, url: &str, tags: &[&str], branches: &[&str]) -> Self {
+        self.remotes.insert(
+            url.to_owned(),
+            FakeRepository {
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+                branches: branches.iter().map(|branch| branch.to_string()).collect(),
+                head: branches
+                    .first()
+                    .map(|branch| branch.to_string())
+                    .unwrap_or_default(),
+                changes: Vec::new(),
+            },
+        );
+        self
+    }
+
+    /// Add a repository that is already cloned at `path`.
+    pub fn with_repository(self, path: &Path, repository: FakeRepository) -> Self {
+        self.update(|repositories| {
+            repositories.insert(path.to_path_buf(), repository);
+        });
+        self
+    }
+
+    /// The repository at `path`, if any.
+    pub fn get_repository(&self, path: &Path) -> Option<FakeRepository> {
+        self.update(|repositories| repositories.get(path).cloned())
+    }
+
+    fn update<T>(&self, update: impl FnOnce(&mut BTreeMap<PathBuf, FakeRepository>) -> T) -> T {
+        match self.repositories.lock() {
+            Ok(mut repositories) => update(&mut repositories),
+            Err(error) => update(&mut error.into_inner()),
+        }
+    }
+
+    fn with_existing<T>(
+        &self,
+        path: &Path,
+        operation: impl FnOnce(&mut FakeRepository) -> Result<T, String>,
+    ) -> Result<T, String> {
+        self.update(|repositories| match repositories.get_mut(path) {
+            Some(repository) => operation(repository),
+            None => Err(format!("could not find repository at {path:?}")),
+        })
+    }
+}
+
+impl GitBackend for FakeGitBackend {
+    fn is_repository(&self, path: &Path) -> bool {
+        self.get_repository(path).is_some()
+    }
+
+    fn clone_repository(
+        &self,
+        url: &str,
+        path: &Path,
+        _progress: &dyn Fn(usize),
+    ) -> Result<FetchStats, String> {
+        let Some(remote) = self.remotes.get(url) else {
+            return Err(format!("remote {url} not found"));
+        };
+        self.update(|repositories| {
+            if repositories.contains_key(path) {
+                Err(format!("{path:?} exists and is not an empty directory"))
+            } else {
+                repositories.insert(path.to_path_buf(), remote.clone());
+                Ok(FetchStats::default())
+            }
+        })
+    }
+
+    fn fetch(
+        &self,
+        path: &Path,
+        branch: Option<&str>,
+        _progress: &dyn Fn(usize),
+    ) -> Result<FetchStats, String> {
+        self.with_existing(path, |repository| match branch {
+            Some(branch) if !repository.branches.iter().any(|name| name == branch) => {
+                Err(format!("couldn't find remote ref {branch}"))
+            }
+            _ => Ok(FetchStats::default()),
+        })
+    }
+
+    fn get_tags(&self, path: &Path, pattern: &str) -> Result<Vec<String>, String> {
+        let matches = |tag: &str| match pattern.strip_suffix('*') {
+            Some(prefix) => tag.starts_with(prefix),
+            None => tag == pattern,
+        };
+        self.with_existing(path, |repository| {
+            Ok(repository
+                .tags
+                .iter()
+                .filter(|tag| matches(tag))
+                .cloned()
+                .collect())
+        })
+    }
+
+    fn checkout_tag(&self, path: &Path, tag: &str, _branch_name: &str) -> Result<(), String> {
+        self.with_existing(path, |repository| {
+            if !repository.tags.iter().any(|name| name == tag) {
+                return Err(format!("revspec 'refs/tags/{tag}' not found"));
+            }
+            repository.head = tag.to_owned();
+            repository.changes.clear();
+            Ok(())
+        })
+    }
+
+    fn checkout_branch(&self, path: &Path, branch_name: &str) -> Result<(), String> {
+        self.with_existing(path, |repository| {
+            if !repository.branches.iter().any(|name| name == branch_name) {
+                return Err(format!(
+                    "cannot locate remote-tracking branch 'origin/{branch_name}'"
+                ));
+            }
+            repository.head = branch_name.to_owned();
+            repository.changes.clear();
+            Ok(())
+        })
+    }
+
+    fn describe(&self, path: &Path) -> Result<String, String> {
+        self.with_existing(path, |repository| Ok(repository.head.to_owned()))
+    }
+
+    fn status(&self, path: &Path) -> Result<Vec<String>, String> {
+        self.with_existing(path, |repository| Ok(repository.changes.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FakeGitBackend, FakeRepository, GitBackend};
+    use std::path::Path;
+
+    #[test]
+    fn test_fake_git_backend() {
+        let git = FakeGitBackend::new().with_remote(
+            "https://github.com/lsst-ts/ts_wep",
+            &["v1.0.0", "v1.1.0"],
+            &["develop", "tickets/DM-1"],
+        );
+        let path = Path::new("/obs-env/ts_wep");
+
+        assert!(!git.is_repository(path));
+        git.clone_repository("https://github.com/lsst-ts/ts_wep", path, &|_| {})
+            .unwrap();
+        assert!(git.is_repository(path));
+        assert!(git
+            .clone_repository("https://github.com/lsst-ts/ts_wep", path, &|_| {})
+            .is_err());
+        assert_eq!(git.describe(path).unwrap(), "develop");
+
+        assert_eq!(git.get_tags(path, "v*").unwrap(), ["v1.0.0", "v1.1.0"]);
+        git.checkout_tag(path, "v1.1.0", "1.1.0").unwrap();
+        assert_eq!(git.describe(path).unwrap(), "v1.1.0");
+        assert!(git.checkout_tag(path, "v2.0.0", "2.0.0").is_err());
+
+        assert!(git.fetch(path, Some("tickets/DM-2"), &|_| {}).is_err());
+        git.checkout_branch(path, "tickets/DM-1").unwrap();
+        assert_eq!(
+            git.get_repository(path),
+            Some(FakeRepository {
+                tags: vec!["v1.0.0".to_owned(), "v1.1.0".to_owned()],
+                branches: vec!["develop".to_owned(), "tickets/DM-1".to_owned()],
+                head: "tickets/DM-1".to_owned(),
+                changes: Vec::new(),
+            })
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use crate::error::ObsEnvError;
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Hook scripts executed before and after actions.
+///
+/// Hooks are executables in the hooks directory named after the stage and
+/// the action they apply to, e.g. `pre-reset` or `post-checkout`, similar to
+/// git hooks. Missing hooks are skipped.
+pub struct Hooks {
+    hooks_dir: PathBuf,
+}
+
+/// Output of a hook script.
+#[derive(Debug)]
+pub struct HookOutput {
+    /// Name of the hook.
+    pub name: String,
+    /// Exit code of the hook, None if it was terminated by a signal.
+    pub code: Option<i32>,
+    /// Captured standard output.
+    pub stdout: String,
+    /// Captured standard error.
+    pub stderr: String,
+}
+
+impl Hooks {
+    pub fn new(hooks_dir: &str) -> Hooks {
+        Hooks {
+            hooks_dir: PathBuf::from(hooks_dir),
+        }
+    }
+
+    /// Run the hook `name`, if it exists, from `working_dir` with the given
+    /// extra environment variables.
+    ///
+    /// Returns None if there is no such hook. A hook that runs but exits with
+    /// a non-zero code is returned as an error, including its output.
+    pub fn run(
+        &self,
+        name: &str,
+        working_dir: &Path,
+        env: &[(&str, &str)],
+    ) -> Result<Option<HookOutput>, ObsEnvError> {
+        let hook = self.hooks_dir.join(name);
+        if !hook.is_file() {
+            log::trace!("No {name} hook in {:?}.", self.hooks_dir);
+            return Ok(None);
+        }
+
+        log::info!("Running {name} hook.");
+        let output = Command::new(&hook)
+            .current_dir(working_dir)
+            .envs(env.iter().copied())
+            .output()
+            .map_err(|error| ObsEnvError::ERROR(format!("Failed to run hook {hook:?}: {error}")))?;
+
+        let hook_output = HookOutput {
+            name: name.to_owned(),
+            code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        };
+        for line in hook_output.stdout.lines() {
+            log::info!("[{name}] {line}");
+        }
+        for line in hook_output.stderr.lines() {
+            log::warn!("[{name}] {line}");
+        }
+
+        if output.status.success() {
+            Ok(Some(hook_output))
+        } else {
+            Err(ObsEnvError::ERROR(format!(
+                "Hook {name} failed with exit code {:?}: {}",
+                hook_output.code,
+                hook_output.stderr.trim()
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Hooks;
+    use std::{env, fs, os::unix::fs::PermissionsExt, process};
+
+    #[test]
+    fn test_hooks() {
+        let hooks_dir = env::temp_dir().join(format!("obs_env_hooks_test_{}", process::id()));
+        fs::create_dir_all(&hooks_dir).unwrap();
+        for (name, content) in [
+            ("post-reset", "#!/bin/sh\necho \"reset $OBS_ENV_ACTION\"\n"),
+            ("pre-reset", "#!/bin/sh\necho 'not allowed' >&2\nexit 3\n"),
+        ] {
+            let hook = hooks_dir.join(name);
+            fs::write(&hook, content).unwrap();
+            fs::set_permissions(&hook, PermissionsExt::from_mode(0o755)).unwrap();
+        }
+
+        let hooks = Hooks::new(hooks_dir.to_str().unwrap());
+        let env = [("OBS_ENV_ACTION", "reset")];
+
+        let output = hooks.run("post-reset", &hooks_dir, &env).unwrap().unwrap();
+        assert_eq!(output.code, Some(0));
+        assert_eq!(output.stdout, "reset reset\n");
+
+        let error = hooks.run("pre-reset", &hooks_dir, &env).unwrap_err();
+        assert!(error.to_string().contains("not allowed"));
+
+        assert!(hooks.run("post-setup", &hooks_dir, &env).unwrap().is_none());
+
+        fs::remove_dir_all(hooks_dir).unwrap();
+    }
+}
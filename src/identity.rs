@@ -0,0 +1,94 @@
+#[cfg(feature = "telemetry")]
+use crate::sasquatch::http::{build_client, send_idempotent};
+#[cfg(feature = "telemetry")]
+use std::error::Error;
+use std::{env, fs};
+
+#[cfg(feature = "telemetry")]
+#[derive(Debug, Deserialize)]
+struct UserInfo {
+    username: String,
+}
+
+/// Get the name of the user running the process.
+///
+/// When running with sudo this is the user that invoked sudo.
+pub fn get_user() -> String {
+    match env::var("SUDO_USER") {
+        Ok(val) => val,
+        Err(_) => match env::var("USER") {
+            Ok(val) => val,
+            Err(_) => "Unknown".to_owned(),
+        },
+    }
+}
+
+/// Get the name of the user the action is run for, when it differs from the
+/// user running the process (e.g. "jovyan" in nublado).
+///
+/// This is `as_user` if given, otherwise the user of the Gafaelfawr token in
+/// ACCESS_TOKEN, if any and built with the telemetry feature.
+pub fn get_acting_user(as_user: Option<&str>) -> Option<String> {
+    match as_user {
+        Some(as_user) => Some(as_user.to_owned()),
+        None => get_token_user(),
+    }
+}
+
+/// Get the user of the Gafaelfawr token in ACCESS_TOKEN, if any.
+#[cfg(feature = "telemetry")]
+fn get_token_user() -> Option<String> {
+    let (Ok(token), Ok(instance_url)) =
+        (env::var("ACCESS_TOKEN"), env::var("EXTERNAL_INSTANCE_URL"))
+    else {
+        return None;
+    };
+    match get_gafaelfawr_user(&instance_url, &token) {
+        Ok(username) => Some(username),
+        Err(error) => {
+            log::warn!("Failed to get the user from Gafaelfawr: {error}");
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "telemetry"))]
+fn get_token_user() -> Option<String> {
+    None
+}
+
+/// Get the name of the user of the Gafaelfawr `token` at `instance_url`.
+#[cfg(feature = "telemetry")]
+fn get_gafaelfawr_user(instance_url: &str, token: &str) -> Result<String, Box<dyn Error>> {
+    let client = build_client()?;
+    let user_info: UserInfo = send_idempotent(
+        client
+            .get(format!(
+                "{}/auth/api/v1/user-info",
+                instance_url.trim_end_matches('/')
+            ))
+            .bearer_auth(token),
+    )?
+    .error_for_status()?
+    .json()?;
+    Ok(user_info.username)
+}
+
+/// Get the identifier of the site (e.g. summit, base or tts) the environment
+/// belongs to, from OBS_ENV_SITE.
+///
+/// Returns an empty string if the site is not configured.
+pub fn get_site() -> String {
+    env::var("OBS_ENV_SITE").unwrap_or_default()
+}
+
+/// Get the name of the host running the process.
+pub fn get_hostname() -> String {
+    match fs::read_to_string("/proc/sys/kernel/hostname") {
+        Ok(hostname) => hostname.trim().to_owned(),
+        Err(_) => match env::var("HOSTNAME") {
+            Ok(val) => val,
+            Err(_) => "Unknown".to_owned(),
+        },
+    }
+}
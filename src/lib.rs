@@ -4,8 +4,26 @@
 
 #[macro_use]
 extern crate serde_derive;
+#[cfg(feature = "sidecar")]
+pub mod async_api;
+pub mod cancellation;
+pub mod config;
+pub mod env_lock;
 pub mod error;
+pub mod git_backend;
+pub mod hooks;
+pub mod identity;
+pub mod logging;
 pub mod manage_obs_env;
+pub mod manifest;
+pub mod metrics;
+pub mod notify;
 pub mod observing_environment;
+pub mod outcome;
+pub mod ownership;
+pub mod progress;
+pub mod report;
 pub mod repos;
 pub mod sasquatch;
+pub mod schedule;
+pub mod setup_template;
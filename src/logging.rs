@@ -0,0 +1,190 @@
+use chrono::Local;
+use log::{LevelFilter, Log, Metadata, Record};
+use simple_logger::SimpleLogger;
+use std::{
+    fs::{create_dir_all, rename, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Logger that writes to the terminal and, optionally, to a rotating log
+/// file.
+pub struct ObsEnvLogger {
+    /// Logger used for the terminal output.
+    terminal: SimpleLogger,
+    /// Optional log file.
+    log_file: Option<Mutex<RotatingFile>>,
+}
+
+/// Log file that is rotated when it grows above a maximum size.
+///
+/// When rotating, `<path>` is renamed to `<path>.1`, `<path>.1` to
+/// `<path>.2` and so on, keeping at most `keep` old files.
+pub struct RotatingFile {
+    /// Path to the active log file.
+    path: PathBuf,
+    /// Maximum size of the active log file, in bytes.
+    max_size: u64,
+    /// Number of rotated files to keep.
+    keep: usize,
+    /// Handle to the active log file.
+    file: File,
+    /// Current size of the active log file, in bytes.
+    size: u64,
+}
+
+impl RotatingFile {
+    pub fn new(path: &str, max_size: u64, keep: usize) -> Result<RotatingFile, std::io::Error> {
+        let path = PathBuf::from(path);
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() && !parent.exists() {
+                create_dir_all(parent)?;
+            }
+        }
+        let file = File::options().append(true).create(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            max_size,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    /// Write a line to the log file, rotating it first if needed.
+    pub fn write_line(&mut self, line: &str) -> Result<(), std::io::Error> {
+        let line_size = line.len() as u64 + 1;
+        if self.size > 0 && self.size + line_size > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{line}")?;
+        self.size += line_size;
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(format!(".{index}"));
+        PathBuf::from(rotated)
+    }
+
+    fn rotate(&mut self) -> Result<(), std::io::Error> {
+        self.file.flush()?;
+        if self.keep > 0 {
+            for index in (1..self.keep).rev() {
+                let source = self.rotated_path(index);
+                if source.exists() {
+                    rename(&source, self.rotated_path(index + 1))?;
+                }
+            }
+            rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = File::options()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Log for ObsEnvLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.terminal.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.terminal.log(record);
+        if let Some(log_file) = &self.log_file {
+            if let Ok(mut log_file) = log_file.lock() {
+                let line = format!(
+                    "{} {:<5} [{}] {}",
+                    Local::now().format("%Y-%m-%dT%H:%M:%S%.3f%:z"),
+                    record.level(),
+                    record.target(),
+                    record.args()
+                );
+                if let Err(error) = log_file.write_line(&line) {
+                    eprintln!(
+                        "Failed to write to log file {:?}: {error}",
+                        log_file.get_path()
+                    );
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.terminal.flush();
+        if let Some(log_file) = &self.log_file {
+            if let Ok(mut log_file) = log_file.lock() {
+                let _ = log_file.file.flush();
+            }
+        }
+    }
+}
+
+/// Initialize logging.
+///
+/// Log messages are always written to the terminal. If `log_file` is given
+/// they are also appended to that file, which is rotated when it grows above
+/// `max_size` bytes, keeping `keep` rotated files.
+pub fn init(
+    log_file: Option<&str>,
+    max_size: u64,
+    keep: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let log_file = match log_file {
+        Some(log_file) => Some(Mutex::new(RotatingFile::new(log_file, max_size, keep)?)),
+        None => None,
+    };
+    log::set_boxed_logger(Box::new(ObsEnvLogger {
+        terminal: SimpleLogger::new(),
+        log_file,
+    }))?;
+    log::set_max_level(LevelFilter::Trace);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RotatingFile;
+    use std::{env, fs, process};
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = env::temp_dir().join(format!("obs_env_log_test_{}", process::id()));
+        let path = dir.join("manage.log");
+
+        let mut log_file = RotatingFile::new(path.to_str().unwrap(), 20, 2).unwrap();
+
+        for index in 0..4 {
+            log_file
+                .write_line(&format!("line number {index}"))
+                .unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line number 3\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("manage.log.1")).unwrap(),
+            "line number 2\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("manage.log.2")).unwrap(),
+            "line number 1\n"
+        );
+        assert!(!dir.join("manage.log.3").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
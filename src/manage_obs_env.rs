@@ -1,18 +1,64 @@
+#[cfg(feature = "telemetry")]
+use crate::sasquatch::{
+    client::SasquatchClient,
+    create_topic::{create_topics, delete_topics, describe_topic, list_topics},
+    http::{get_rest_proxy_url, get_schema_registry_url},
+    log_summary::AvroSchema,
+    schema::RecordSchema,
+    schema_registry::register_schemas,
+    site_comparison::SiteComparison,
+    spool::{TelemetrySpool, SPOOL_DIR_NAME},
+};
 use crate::{
+    cancellation::CancellationToken,
+    config::{Config, CONFIG_ENV_VAR},
+    env_lock::EnvLock,
     error::ObsEnvError,
-    observing_environment::ObservingEnvironment,
+    git_backend::GitBackend,
+    hooks::Hooks,
+    identity::get_acting_user,
+    notify::Notification,
+    observing_environment::{
+        validate_branch_name, validate_version, CloneOutcome, ObservingEnvironment,
+        ObservingEnvironmentBuilder,
+    },
+    outcome::ActionOutcome,
+    ownership::Ownership,
+    progress::ProgressObserver,
+    report::Report,
     repos::Repos,
     sasquatch::{
-        create_topic::create_topics,
-        log_summary::{get_payload, ActionData, AvroSchema, Payload, Summary},
+        drift::Drift,
+        journal::{TelemetryJournal, JOURNAL_FILE_NAME},
+        lock_state::LockState,
+        log_summary::{ActionData, Summary},
+        metrics::ActionMetrics,
         run_branch::RunBranch,
+        sink::{TelemetrySink, TelemetryTracker},
+        topic_config::TopicConfig,
     },
+    schedule::Schedule,
+    setup_template::{SetupFileRefresh, SetupMode, SetupShell, SetupTemplate},
 };
-use clap::Parser;
+use chrono::{DateTime, Local};
+#[cfg(feature = "telemetry")]
+use chrono::{LocalResult, TimeZone, Utc};
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser};
 use log;
-use reqwest;
-use serde::ser::Serialize;
-use std::{collections::BTreeMap, env, error::Error, fmt::Debug};
+use sd_notify::NotifyState;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::{
+    cell::RefCell,
+    env,
+    error::Error,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
 /// Manage observing environment.
 #[derive(Parser, Debug)]
@@ -31,13 +77,165 @@ pub struct ManageObsEnv {
     #[arg(value_enum, long = "repository")]
     repository: Option<Repos>,
     /// Name of the branch or version to checkout when running the "CheckoutBranch"
-    /// or "CheckoutVersion" action.
+    /// or "CheckoutVersion" action. For "CheckoutVersion" this can also be
+    /// "latest" or a range such as "~1.4" (newest 1.4.x release).
     #[arg(long = "branch-name", default_value = "")]
     branch_name: String,
     /// Name of the branch to checkout when running the "Reset"
     /// action.
     #[arg(long = "base-env-branch-name", default_value = "main")]
     base_env_branch_name: String,
+    /// Remove the environment lock even if it is held by someone else when
+    /// running the "Unlock" action, or confirm deleting the topics when
+    /// running the "DeleteTopics" action.
+    #[arg(long = "force")]
+    force: bool,
+    /// Owner and group (user:group) to set on the files touched by actions
+    /// that modify the environment. Group permissions are also made equal to
+    /// the owner permissions.
+    #[arg(long = "chown")]
+    chown: Option<String>,
+    /// File path, in the base environment repository, with the version
+    /// definitions.
+    #[arg(long = "base-env-def-file", default_value = "cycle/cycle.env")]
+    base_env_def_file: String,
+    /// Local file with the version definitions to use instead of the base
+    /// environment repository (e.g. to test an unreleased cycle definition).
+    #[arg(long = "base-env-local-file")]
+    base_env_local_file: Option<String>,
+    /// Minijinja template used to generate the setup file of SHELL instead
+    /// of the default one, e.g. "csh=auto_env_setup.csh.j2". Can be given
+    /// once per shell. Without SHELL, the template is only allowed when
+    /// generating the setup file of a single shell. It can use the
+    /// destination, timestamp, user, repositories (with name, path and
+    /// version) and versions variables.
+    #[arg(long = "setup-template", value_name = "[SHELL=]PATH")]
+    setup_templates: Vec<SetupTemplate>,
+    /// Shells to generate setup files for, comma separated. The setup file
+    /// of each shell is named after it, e.g. "auto_env_setup.csh". lmod
+    /// generates an Lmod modulefile, "auto_env_setup.lua".
+    #[arg(
+        value_enum,
+        long = "setup-shell",
+        value_delimiter = ',',
+        default_value = "bash"
+    )]
+    setup_shells: Vec<SetupShell>,
+    /// How the setup file sets up the repositories: following the setup
+    /// style of each repository, or only with PYTHONPATH for environments
+    /// without EUPS.
+    #[arg(value_enum, long = "setup-mode", default_value = "per-repository")]
+    setup_mode: SetupMode,
+    /// Number of backups of the previous setup files to keep, named after
+    /// the setup file with a timestamp suffix. Use 0 to keep none.
+    #[arg(long = "setup-file-backups", default_value = "5")]
+    setup_file_backups: usize,
+    /// Directory to write the setup files and the manifest to, instead of
+    /// the environment (e.g. a path bind-mounted into nublado).
+    #[arg(long = "setup-file-dir")]
+    setup_file_dir: Option<String>,
+    /// Name of the setup files, without extension, instead of
+    /// "auto_env_setup". The extension of each shell is added to it.
+    #[arg(long = "setup-file-name")]
+    setup_file_name: Option<String>,
+    /// Leave the setup file untouched when running Setup, and after the
+    /// other actions that modify the environment. Also enabled by setting
+    /// MANAGE_OBS_ENV_SKIP_SETUP_FILE to "1" or "true".
+    #[arg(long = "skip-setup-file")]
+    skip_setup_file: bool,
+    /// What to do with the setup file after the actions that modify the
+    /// environment other than Setup (Reset and the checkouts): leave it,
+    /// validate it against the environment, or regenerate it.
+    #[arg(value_enum, long = "setup-file-refresh", default_value = "validate")]
+    setup_file_refresh: SetupFileRefresh,
+    /// Directory with hook scripts to run before and after actions that
+    /// modify the environment (e.g. "pre-reset", "post-checkout").
+    #[arg(long = "hooks-dir")]
+    hooks_dir: Option<String>,
+    /// Write a JSON report with the outcome of the action to this file.
+    #[arg(long = "report-file")]
+    report_file: Option<String>,
+    /// Keep running and execute the action periodically, on the given
+    /// cron-like schedule (e.g. "0 18 * * *" for every day at 18:00).
+    #[arg(long = "schedule")]
+    schedule: Option<String>,
+    /// Also write log messages to this file.
+    #[arg(long = "log-file")]
+    log_file: Option<String>,
+    /// Maximum size of the log file, in bytes, before it is rotated.
+    #[arg(long = "log-file-max-size", default_value = "10485760")]
+    log_file_max_size: u64,
+    /// Number of rotated log files to keep.
+    #[arg(long = "log-file-keep", default_value = "5")]
+    log_file_keep: usize,
+    /// Make the run branch registered by the "RegisterRunBranch" action
+    /// expire after this many hours. By default it does not expire.
+    #[arg(long = "run-branch-ttl-hours")]
+    run_branch_ttl_hours: Option<u64>,
+    /// Number of records to show when running the "RunBranchHistory"
+    /// action.
+    #[arg(long = "history-limit", default_value = "10")]
+    history_limit: usize,
+    /// User the action is run for, recorded in the telemetry and the setup
+    /// file along with the user running the process. Defaults to the user of
+    /// the Gafaelfawr token in ACCESS_TOKEN, if any.
+    #[arg(long = "as-user")]
+    as_user: Option<String>,
+    /// EFD instances of the sites to compare when running the
+    /// "ShowAllSites" action.
+    #[arg(
+        long = "efd-names",
+        value_delimiter = ',',
+        default_value = "summit_efd,base_efd,tucson_teststand_efd"
+    )]
+    efd_names: Vec<String>,
+    /// Directory where telemetry that could not be sent is stored, to be
+    /// retried later. Defaults to ".obs_env_telemetry" in the environment.
+    #[arg(long = "telemetry-spool-dir")]
+    telemetry_spool_dir: Option<String>,
+    /// Number of partitions of the topics created by "CreateTopics".
+    #[arg(long = "topic-partitions", default_value = "1")]
+    topic_partitions: usize,
+    /// Replication factor of the topics created by "CreateTopics". Use 1 on
+    /// single-broker test stands.
+    #[arg(long = "topic-replication-factor", default_value = "3")]
+    topic_replication_factor: usize,
+    /// Retention, in milliseconds, of the topics created by "CreateTopics"
+    /// (-1 for no limit). Uses the broker default if not given.
+    #[arg(long = "topic-retention-ms", allow_hyphen_values = true)]
+    topic_retention_ms: Option<i64>,
+    /// Cleanup policy of the topics created by "CreateTopics". Uses the
+    /// broker default if not given. With "compact", only the latest
+    /// run_branch record of each site and summary record of each environment
+    /// are kept, while all the action records are.
+    #[arg(long = "topic-cleanup-policy", value_parser = ["delete", "compact"])]
+    topic_cleanup_policy: Option<String>,
+    /// Create missing topics, with the "--topic-*" configuration, when
+    /// sending telemetry instead of requiring "CreateTopics" to be run first.
+    #[arg(long = "auto-create-topics")]
+    auto_create_topics: bool,
+    /// Don't send telemetry over the network, append it to the telemetry
+    /// journal instead. Run "SyncTelemetry" to send it once sasquatch is
+    /// reachable.
+    #[arg(long = "offline")]
+    offline: bool,
+    /// Telemetry journal used when running with --offline. Defaults to
+    /// ".obs_env_telemetry.jsonl" in the environment.
+    #[arg(long = "telemetry-journal")]
+    telemetry_journal: Option<String>,
+    /// Configuration file (TOML) with the defaults of the options. Defaults
+    /// to the MANAGE_OBS_ENV_CONFIG environment variable.
+    #[arg(long = "config")]
+    config_file: Option<String>,
+    /// EFD instance of the environment, from the configuration.
+    #[arg(skip)]
+    efd_name: Option<String>,
+    /// Url notified of the outcome of the actions, from the configuration.
+    #[arg(skip)]
+    webhook_url: Option<String>,
+    /// Token cancelling Setup and Reset between repositories.
+    #[arg(skip)]
+    cancellation: CancellationToken,
 }
 pub trait ManageObsEnvCli {
     fn get_action(&self) -> Result<&Action, Box<dyn Error>>;
@@ -47,6 +245,36 @@ pub trait ManageObsEnvCli {
     fn get_version(&self) -> &str;
     fn get_repository_name(&self) -> &str;
     fn get_base_env_source_repo(&self) -> &str;
+    fn get_force(&self) -> bool;
+    fn get_chown(&self) -> Option<&str>;
+    fn get_base_env_def_file(&self) -> &str;
+    fn get_base_env_local_file(&self) -> Option<&str>;
+    fn get_setup_templates(&self) -> &[SetupTemplate];
+    fn get_setup_shells(&self) -> &[SetupShell];
+    fn get_setup_mode(&self) -> SetupMode;
+    fn get_setup_file_backups(&self) -> usize;
+    fn get_setup_file_dir(&self) -> Option<&str>;
+    fn get_setup_file_name(&self) -> Option<&str>;
+    fn get_skip_setup_file(&self) -> bool;
+    fn get_setup_file_refresh(&self) -> SetupFileRefresh;
+    fn get_hooks_dir(&self) -> Option<&str>;
+    fn get_report_file(&self) -> Option<&str>;
+    fn get_schedule(&self) -> Option<&str>;
+    fn get_log_file(&self) -> Option<&str>;
+    fn get_log_file_max_size(&self) -> u64;
+    fn get_log_file_keep(&self) -> usize;
+    fn get_telemetry_spool_dir(&self) -> Option<&str>;
+    fn get_topic_config(&self) -> TopicConfig;
+    fn get_auto_create_topics(&self) -> bool;
+    fn get_offline(&self) -> bool;
+    fn get_telemetry_journal(&self) -> Option<&str>;
+    fn get_history_limit(&self) -> usize;
+    fn get_as_user(&self) -> Option<&str>;
+    fn get_efd_names(&self) -> &[String];
+    fn get_run_branch_ttl(&self) -> Option<Duration>;
+    fn get_efd_name(&self) -> Option<String>;
+    fn get_webhook_url(&self) -> Option<String>;
+    fn get_cancellation_token(&self) -> &CancellationToken;
 }
 
 impl ManageObsEnvCli for ManageObsEnv {
@@ -58,9 +286,28 @@ impl ManageObsEnvCli for ManageObsEnv {
                         "Checkout branch action requires a repository, none given".to_owned(),
                     )))
                 } else {
+                    validate_branch_name(&self.branch_name)?;
+                    Ok(&self.action)
+                }
+            }
+            Action::CheckoutVersion => {
+                if self.repository.is_none() {
+                    Err(Box::new(ObsEnvError::ERROR(
+                        "Checkout version action requires a repository, none given".to_owned(),
+                    )))
+                } else {
+                    validate_version(&self.branch_name)?;
                     Ok(&self.action)
                 }
             }
+            Action::RegisterRunBranch => {
+                validate_branch_name(&self.branch_name)?;
+                Ok(&self.action)
+            }
+            Action::Reset | Action::ShowOriginalVersions | Action::Verify => {
+                validate_branch_name(&self.base_env_branch_name)?;
+                Ok(&self.action)
+            }
             _ => Ok(&self.action),
         }
     }
@@ -86,9 +333,470 @@ impl ManageObsEnvCli for ManageObsEnv {
     fn get_base_env_source_repo(&self) -> &str {
         &self.base_env_branch_name
     }
+    fn get_force(&self) -> bool {
+        self.force
+    }
+    fn get_chown(&self) -> Option<&str> {
+        self.chown.as_deref()
+    }
+    fn get_base_env_def_file(&self) -> &str {
+        &self.base_env_def_file
+    }
+    fn get_base_env_local_file(&self) -> Option<&str> {
+        self.base_env_local_file.as_deref()
+    }
+    fn get_setup_templates(&self) -> &[SetupTemplate] {
+        &self.setup_templates
+    }
+    fn get_setup_shells(&self) -> &[SetupShell] {
+        &self.setup_shells
+    }
+    fn get_setup_mode(&self) -> SetupMode {
+        self.setup_mode
+    }
+    fn get_setup_file_backups(&self) -> usize {
+        self.setup_file_backups
+    }
+    fn get_setup_file_dir(&self) -> Option<&str> {
+        self.setup_file_dir.as_deref()
+    }
+    fn get_setup_file_name(&self) -> Option<&str> {
+        self.setup_file_name.as_deref()
+    }
+    fn get_skip_setup_file(&self) -> bool {
+        self.skip_setup_file
+            || env::var("MANAGE_OBS_ENV_SKIP_SETUP_FILE")
+                .is_ok_and(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+    }
+    fn get_setup_file_refresh(&self) -> SetupFileRefresh {
+        self.setup_file_refresh
+    }
+    fn get_hooks_dir(&self) -> Option<&str> {
+        self.hooks_dir.as_deref()
+    }
+    fn get_report_file(&self) -> Option<&str> {
+        self.report_file.as_deref()
+    }
+    fn get_schedule(&self) -> Option<&str> {
+        self.schedule.as_deref()
+    }
+    fn get_log_file(&self) -> Option<&str> {
+        self.log_file.as_deref()
+    }
+    fn get_log_file_max_size(&self) -> u64 {
+        self.log_file_max_size
+    }
+    fn get_log_file_keep(&self) -> usize {
+        self.log_file_keep
+    }
+    fn get_telemetry_spool_dir(&self) -> Option<&str> {
+        self.telemetry_spool_dir.as_deref()
+    }
+    fn get_history_limit(&self) -> usize {
+        self.history_limit
+    }
+    fn get_as_user(&self) -> Option<&str> {
+        self.as_user.as_deref()
+    }
+    fn get_efd_names(&self) -> &[String] {
+        &self.efd_names
+    }
+    fn get_run_branch_ttl(&self) -> Option<Duration> {
+        self.run_branch_ttl_hours
+            .map(|hours| Duration::from_secs(hours.saturating_mul(3600)))
+    }
+    fn get_topic_config(&self) -> TopicConfig {
+        let mut topic_config = TopicConfig::default()
+            .with_partitions_count(self.topic_partitions)
+            .with_replication_factor(self.topic_replication_factor);
+        if let Some(retention_ms) = self.topic_retention_ms {
+            topic_config = topic_config.with_retention_ms(retention_ms);
+        }
+        if let Some(cleanup_policy) = &self.topic_cleanup_policy {
+            topic_config = topic_config.with_cleanup_policy(cleanup_policy);
+        }
+        topic_config
+    }
+    fn get_auto_create_topics(&self) -> bool {
+        self.auto_create_topics
+    }
+    fn get_offline(&self) -> bool {
+        self.offline
+    }
+    fn get_telemetry_journal(&self) -> Option<&str> {
+        self.telemetry_journal.as_deref()
+    }
+    fn get_efd_name(&self) -> Option<String> {
+        self.efd_name
+            .clone()
+            .or_else(|| env::var("MANAGE_OBS_ENV_EFD_NAME").ok())
+    }
+    fn get_webhook_url(&self) -> Option<String> {
+        self.webhook_url
+            .clone()
+            .or_else(|| env::var("MANAGE_OBS_ENV_WEBHOOK_URL").ok())
+    }
+    fn get_cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+}
+
+/// Set `option` to `value`, if there is one and `is_given` is false.
+fn set_default<T: From<V>, V: Clone>(option: &mut T, value: &Option<V>, is_given: bool) {
+    if let (Some(value), false) = (value, is_given) {
+        *option = value.clone().into();
+    }
+}
+
+impl ManageObsEnv {
+    /// Parse the command line arguments in `matches`, taking the options not
+    /// given from the configuration file (--config or MANAGE_OBS_ENV_CONFIG)
+    /// and the environment, see `Config`.
+    pub fn from_matches_with_config(matches: &ArgMatches) -> Result<ManageObsEnv, Box<dyn Error>> {
+        let mut manage_obs_env = ManageObsEnv::from_arg_matches(matches)?;
+        let config_file = manage_obs_env
+            .config_file
+            .clone()
+            .or_else(|| env::var(CONFIG_ENV_VAR).ok());
+        let config = Config::load(config_file.as_deref().map(Path::new))?;
+        manage_obs_env.apply_config(&config, |id| {
+            matches.value_source(id) == Some(ValueSource::CommandLine)
+        });
+        Ok(manage_obs_env)
+    }
+
+    /// Parse the command line of the process, see `from_matches_with_config`.
+    /// Exits on invalid arguments or when asked for the help.
+    pub fn parse_with_config() -> Result<ManageObsEnv, Box<dyn Error>> {
+        ManageObsEnv::from_matches_with_config(&ManageObsEnv::command().get_matches())
+    }
+
+    /// Use `cancellation` to cancel Setup and Reset, e.g. after calling
+    /// `CancellationToken::cancel_on_signals` on it.
+    pub fn with_cancellation_token(mut self, cancellation: CancellationToken) -> ManageObsEnv {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Apply the settings of `config`, except for the options `is_given`
+    /// reports as given in the command line.
+    fn apply_config(&mut self, config: &Config, is_given: impl Fn(&str) -> bool) {
+        set_default(&mut self.env_path, &config.env_path, is_given("env_path"));
+        set_default(
+            &mut self.base_env_branch_name,
+            &config.base_env_branch_name,
+            is_given("base_env_branch_name"),
+        );
+        set_default(
+            &mut self.base_env_def_file,
+            &config.base_env_def_file,
+            is_given("base_env_def_file"),
+        );
+        set_default(
+            &mut self.base_env_local_file,
+            &config.base_env_local_file,
+            is_given("base_env_local_file"),
+        );
+        set_default(
+            &mut self.setup_templates,
+            &config.setup_templates,
+            is_given("setup_templates"),
+        );
+        set_default(
+            &mut self.setup_shells,
+            &config.setup_shells,
+            is_given("setup_shells"),
+        );
+        set_default(
+            &mut self.setup_mode,
+            &config.setup_mode,
+            is_given("setup_mode"),
+        );
+        set_default(
+            &mut self.setup_file_backups,
+            &config.setup_file_backups,
+            is_given("setup_file_backups"),
+        );
+        set_default(
+            &mut self.setup_file_dir,
+            &config.setup_file_dir,
+            is_given("setup_file_dir"),
+        );
+        set_default(
+            &mut self.setup_file_name,
+            &config.setup_file_name,
+            is_given("setup_file_name"),
+        );
+        set_default(
+            &mut self.skip_setup_file,
+            &config.skip_setup_file,
+            is_given("skip_setup_file"),
+        );
+        set_default(
+            &mut self.setup_file_refresh,
+            &config.setup_file_refresh,
+            is_given("setup_file_refresh"),
+        );
+        set_default(
+            &mut self.hooks_dir,
+            &config.hooks_dir,
+            is_given("hooks_dir"),
+        );
+        set_default(&mut self.log_file, &config.log_file, is_given("log_file"));
+        set_default(
+            &mut self.telemetry_spool_dir,
+            &config.telemetry_spool_dir,
+            is_given("telemetry_spool_dir"),
+        );
+        set_default(&mut self.offline, &config.offline, is_given("offline"));
+        set_default(
+            &mut self.telemetry_journal,
+            &config.telemetry_journal,
+            is_given("telemetry_journal"),
+        );
+        set_default(&mut self.efd_name, &config.efd_name, false);
+        set_default(&mut self.webhook_url, &config.webhook_url, false);
+    }
 }
 
-pub fn run<T>(config: &T) -> Result<(), Box<dyn Error>>
+/// Builder of a `ManageObsEnv`, to run the actions from other Rust services
+/// (e.g. with `run` or `async_api::run_async`) without formatting command
+/// line arguments.
+///
+/// Options that are not set keep the defaults of the command line. The
+/// process level options (schedule and log file) are only available from
+/// the command line.
+#[derive(Debug)]
+pub struct ManageObsEnvBuilder {
+    config: ManageObsEnv,
+}
+
+impl ManageObsEnv {
+    pub fn builder(action: Action) -> ManageObsEnvBuilder {
+        let mut config = ManageObsEnv::parse_from(["manage_obs_env", "--action", "print-config"]);
+        config.action = action;
+        ManageObsEnvBuilder { config }
+    }
+}
+
+impl ManageObsEnvBuilder {
+    pub fn with_action(mut self, action: Action) -> Self {
+        self.config.action = action;
+        self
+    }
+    pub fn with_log_level(mut self, log_level: LogLevel) -> Self {
+        self.config.log_level = log_level;
+        self
+    }
+    pub fn with_env_path(mut self, env_path: &str) -> Self {
+        self.config.env_path = env_path.to_owned();
+        self
+    }
+    /// Set the repository used by the CheckoutBranch and CheckoutVersion
+    /// actions.
+    pub fn with_repository(mut self, repository: Repos) -> Self {
+        self.config.repository = Some(repository);
+        self
+    }
+    pub fn with_branch_name(mut self, branch_name: &str) -> Self {
+        self.config.branch_name = branch_name.to_owned();
+        self
+    }
+    pub fn with_base_env_branch_name(mut self, base_env_branch_name: &str) -> Self {
+        self.config.base_env_branch_name = base_env_branch_name.to_owned();
+        self
+    }
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.config.force = force;
+        self
+    }
+    /// Give the ownership of the environment to `owner` ("user[:group]").
+    pub fn with_chown(mut self, owner: &str) -> Self {
+        self.config.chown = Some(owner.to_owned());
+        self
+    }
+    pub fn with_base_env_def_file(mut self, base_env_def_file: &str) -> Self {
+        self.config.base_env_def_file = base_env_def_file.to_owned();
+        self
+    }
+    pub fn with_base_env_local_file(mut self, base_env_local_file: &str) -> Self {
+        self.config.base_env_local_file = Some(base_env_local_file.to_owned());
+        self
+    }
+    pub fn with_setup_templates(mut self, setup_templates: &[SetupTemplate]) -> Self {
+        self.config.setup_templates = setup_templates.to_vec();
+        self
+    }
+    pub fn with_setup_shells(mut self, setup_shells: &[SetupShell]) -> Self {
+        self.config.setup_shells = setup_shells.to_vec();
+        self
+    }
+    pub fn with_setup_mode(mut self, setup_mode: SetupMode) -> Self {
+        self.config.setup_mode = setup_mode;
+        self
+    }
+    pub fn with_setup_file_backups(mut self, setup_file_backups: usize) -> Self {
+        self.config.setup_file_backups = setup_file_backups;
+        self
+    }
+    pub fn with_setup_file_dir(mut self, setup_file_dir: &str) -> Self {
+        self.config.setup_file_dir = Some(setup_file_dir.to_owned());
+        self
+    }
+    pub fn with_setup_file_name(mut self, setup_file_name: &str) -> Self {
+        self.config.setup_file_name = Some(setup_file_name.to_owned());
+        self
+    }
+    pub fn with_skip_setup_file(mut self, skip_setup_file: bool) -> Self {
+        self.config.skip_setup_file = skip_setup_file;
+        self
+    }
+    pub fn with_setup_file_refresh(mut self, setup_file_refresh: SetupFileRefresh) -> Self {
+        self.config.setup_file_refresh = setup_file_refresh;
+        self
+    }
+    pub fn with_hooks_dir(mut self, hooks_dir: &str) -> Self {
+        self.config.hooks_dir = Some(hooks_dir.to_owned());
+        self
+    }
+    pub fn with_report_file(mut self, report_file: &str) -> Self {
+        self.config.report_file = Some(report_file.to_owned());
+        self
+    }
+    pub fn with_run_branch_ttl_hours(mut self, run_branch_ttl_hours: u64) -> Self {
+        self.config.run_branch_ttl_hours = Some(run_branch_ttl_hours);
+        self
+    }
+    pub fn with_history_limit(mut self, history_limit: usize) -> Self {
+        self.config.history_limit = history_limit;
+        self
+    }
+    /// Run the action on behalf of `as_user`, see `identity::get_acting_user`.
+    pub fn with_as_user(mut self, as_user: &str) -> Self {
+        self.config.as_user = Some(as_user.to_owned());
+        self
+    }
+    pub fn with_efd_names(mut self, efd_names: &[&str]) -> Self {
+        self.config.efd_names = efd_names.iter().map(|name| (*name).to_owned()).collect();
+        self
+    }
+    pub fn with_telemetry_spool_dir(mut self, telemetry_spool_dir: &str) -> Self {
+        self.config.telemetry_spool_dir = Some(telemetry_spool_dir.to_owned());
+        self
+    }
+    pub fn with_auto_create_topics(mut self, auto_create_topics: bool) -> Self {
+        self.config.auto_create_topics = auto_create_topics;
+        self
+    }
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.config.offline = offline;
+        self
+    }
+    pub fn with_telemetry_journal(mut self, telemetry_journal: &str) -> Self {
+        self.config.telemetry_journal = Some(telemetry_journal.to_owned());
+        self
+    }
+    pub fn with_efd_name(mut self, efd_name: &str) -> Self {
+        self.config.efd_name = Some(efd_name.to_owned());
+        self
+    }
+    pub fn with_webhook_url(mut self, webhook_url: &str) -> Self {
+        self.config.webhook_url = Some(webhook_url.to_owned());
+        self
+    }
+    pub fn with_cancellation_token(mut self, cancellation: CancellationToken) -> Self {
+        self.config.cancellation = cancellation;
+        self
+    }
+    /// Apply all the settings set in `config`.
+    pub fn with_config(mut self, config: &Config) -> Self {
+        self.config.apply_config(config, |_| false);
+        self
+    }
+
+    pub fn build(self) -> ManageObsEnv {
+        self.config
+    }
+}
+
+/// Run the action periodically, following a cron-like schedule.
+///
+/// This function returns if the schedule is invalid or has no upcoming times,
+/// or when the process receives SIGTERM or SIGINT. A run in progress when
+/// the signal arrives is completed before returning. Errors from the
+/// individual runs are logged and the next run is awaited.
+///
+/// When running as a systemd service (Type=notify), READY is reported once
+/// the schedule is set up and the watchdog is pinged while waiting for the
+/// next run. WatchdogSec must be longer than a single run of the action.
+pub fn run_on_schedule<T>(config: &T, schedule: &str) -> Result<(), Box<dyn Error>>
+where
+    T: ManageObsEnvCli,
+{
+    let schedule = Schedule::parse(schedule)?;
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        signal_hook::flag::register(signal, Arc::clone(&shutdown))?;
+    }
+
+    notify_systemd(&[NotifyState::Ready]);
+
+    loop {
+        let Some(next_run) = schedule.next_after(&Local::now()) else {
+            log::warn!("No upcoming runs in the schedule, exiting.");
+            notify_systemd(&[NotifyState::Stopping]);
+            return Ok(());
+        };
+        log::info!("Next run scheduled for {next_run}.");
+        notify_systemd(&[NotifyState::Status(&format!(
+            "Next run scheduled for {next_run}."
+        ))]);
+        if !wait_until(&next_run, &shutdown) {
+            log::info!("Received termination signal, exiting.");
+            notify_systemd(&[NotifyState::Stopping]);
+            return Ok(());
+        }
+        notify_systemd(&[NotifyState::Watchdog, NotifyState::Status("Running.")]);
+        if let Err(error) = run(config) {
+            log::error!("Scheduled run failed: {error}");
+        }
+        notify_systemd(&[NotifyState::Watchdog]);
+    }
+}
+
+/// Wait until `time`, returning early if `shutdown` is set.
+///
+/// Returns false if the wait was interrupted by `shutdown`.
+fn wait_until(time: &DateTime<Local>, shutdown: &AtomicBool) -> bool {
+    loop {
+        if shutdown.load(Ordering::Relaxed) {
+            return false;
+        }
+        match (*time - Local::now()).to_std() {
+            Ok(remaining) if !remaining.is_zero() => {
+                notify_systemd(&[NotifyState::Watchdog]);
+                thread::sleep(remaining.min(Duration::from_secs(1)))
+            }
+            _ => return true,
+        }
+    }
+}
+
+/// Send a notification to systemd, if running as a systemd service.
+fn notify_systemd(state: &[NotifyState]) {
+    if let Err(error) = sd_notify::notify(false, state) {
+        log::trace!("Failed to notify systemd: {error}");
+    }
+}
+
+/// Run the action of `config`, sending the telemetry to the sasquatch REST
+/// proxies configured in the environment, or to the telemetry journal when
+/// running offline or built without the telemetry feature.
+///
+/// Errors that stop the action are returned as errors, while failures on
+/// individual repositories are part of the returned outcome.
+pub fn run<T>(config: &T) -> Result<ActionOutcome, Box<dyn Error>>
 where
     T: ManageObsEnvCli,
 {
@@ -100,63 +808,346 @@ where
         LogLevel::Error => log::set_max_level(log::LevelFilter::Error),
     };
 
+    #[cfg(feature = "telemetry")]
+    if !config.get_offline() {
+        let telemetry_spool = TelemetrySpool::new(&match config.get_telemetry_spool_dir() {
+            Some(telemetry_spool_dir) => PathBuf::from(telemetry_spool_dir),
+            None => Path::new(config.get_env_path()).join(SPOOL_DIR_NAME),
+        });
+        let telemetry = SasquatchClient::from_env().with_spool(telemetry_spool);
+        let telemetry = if config.get_auto_create_topics() {
+            telemetry.with_auto_create(config.get_topic_config())
+        } else {
+            telemetry
+        };
+        return run_with_telemetry(config, &telemetry.build()?);
+    }
+
+    let journal = get_telemetry_journal(config);
+    log::info!(
+        "Running offline, telemetry is written to {:?}.",
+        journal.get_path()
+    );
+    run_with_telemetry(config, &journal)
+}
+
+/// Run the action of `config`, sending the telemetry to `telemetry`.
+pub fn run_with_telemetry<T>(
+    config: &T,
+    telemetry: &dyn TelemetrySink,
+) -> Result<ActionOutcome, Box<dyn Error>>
+where
+    T: ManageObsEnvCli,
+{
     log::info!("Running manage obs env...");
 
-    let obs_env = ObservingEnvironment::with_destination(config.get_env_path());
+    let telemetry_tracker = TelemetryTracker::new(telemetry);
+    let telemetry: &dyn TelemetrySink = &telemetry_tracker;
+
+    let acting_user = get_acting_user(config.get_as_user());
+
+    if let Some(base_env_local_file) = config.get_base_env_local_file() {
+        log::info!("Reading base environment versions from {base_env_local_file}.");
+    }
+    let action = config.get_action()?;
+
+    let obs_env = {
+        let builder = ObservingEnvironmentBuilder::from_config(config)
+            .with_cancellation_token(config.get_cancellation_token().clone());
+        match &acting_user {
+            Some(acting_user) => builder.with_acting_user(acting_user),
+            None => builder,
+        }
+        .build()
+    };
+
+    if let Action::Setup = action {
+        log::debug!("Creating path...");
+        obs_env.create_path()?;
+    }
+
+    let ownership = match config.get_chown() {
+        Some(chown) if action.is_mutating() => Some(Ownership::parse(chown)?),
+        _ => None,
+    };
+
+    let env_lock = if action.is_mutating() {
+        log::debug!("Acquiring environment lock...");
+        Some(EnvLock::acquire(
+            config.get_env_path(),
+            &format!("{action:?}"),
+        )?)
+    } else {
+        None
+    };
+
+    let mut report = Report::new(&format!("{action:?}"));
+
+    let hooks = config.get_hooks_dir().map(Hooks::new);
+
+    let mut action_data = ActionData::new(action.get_telemetry_name().unwrap_or_default(), "", "")
+        .with_env_path(config.get_env_path())
+        .with_acting_user(acting_user.as_deref().unwrap_or_default());
+    let start = Instant::now();
+
+    if let Some(env_lock) = &env_lock {
+        send_lock_state(
+            telemetry,
+            LockState::locked(config.get_env_path(), env_lock.get_info()),
+        );
+    }
+
+    let pre_hook_result = run_hook(hooks.as_ref(), "pre", config, action, &mut report);
+    // The environment is only modified once the pre hook succeeded, except
+    // for the environment path created by Setup.
+    let modifies_env = pre_hook_result.is_ok() || matches!(action, Action::Setup);
+    let result = pre_hook_result
+        .and_then(|_| {
+            execute_action(
+                config,
+                action,
+                &obs_env,
+                &mut report,
+                &mut action_data,
+                telemetry,
+            )
+        })
+        .and_then(|_| refresh_setup_file(config, action, &obs_env, &mut report))
+        .and_then(|_| run_hook(hooks.as_ref(), "post", config, action, &mut report));
+
+    // Also after failures or cancellation, as the action may have cloned or
+    // modified some repositories already.
+    let result = match &ownership {
+        Some(ownership) if modifies_env => {
+            match (result, normalize_ownership(config, action, ownership)) {
+                (result, Ok(())) => result,
+                (Ok(_), Err(error)) => Err(error),
+                (Err(error), Err(ownership_error)) => Err(ObsEnvError::ERROR(format!(
+                    "{error} Also failed to normalize ownership: {ownership_error}"
+                ))
+                .into()),
+            }
+        }
+        _ => result,
+    };
+
+    if let Err(error) = &result {
+        report.set_error(&error.to_string());
+    }
+    if action.get_telemetry_name().is_some() {
+        log::debug!("Sending action.");
+        // Failures on individual repositories (e.g. during Reset) don't stop
+        // the action, but the action is still reported as failed.
+        let error = match &result {
+            Err(error) => Some(error.to_string()),
+            Ok(_) if !report.is_success() => Some(format!(
+                "Failed on {}.",
+                report
+                    .get_failures()
+                    .map(|entry| entry.get_repository())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            )),
+            Ok(_) => None,
+        };
+        action_data.set_outcome(error.as_deref(), start.elapsed());
+        if let Err(error) = telemetry.send_action(action_data) {
+            log::error!("{error}");
+        }
+        let metrics = ActionMetrics::new(
+            action.get_telemetry_name().unwrap_or_default(),
+            start.elapsed(),
+            &obs_env.get_metrics().get_repositories(),
+        )
+        .with_env_path(config.get_env_path());
+        if let Err(error) = telemetry.send_metrics(metrics) {
+            log::error!("{error}");
+        }
+    }
+    if let Some(report_file) = config.get_report_file() {
+        log::debug!("Writing report to {report_file}.");
+        if let Err(error) = report.write(report_file) {
+            log::error!("{error}");
+        }
+    }
+    if action.is_mutating() {
+        if let Some(webhook_url) = config.get_webhook_url() {
+            send_notification(
+                &webhook_url,
+                &Notification::from_report(
+                    &format!("{action:?}"),
+                    acting_user.as_deref(),
+                    &report,
+                    result.as_ref().err().map(|error| error.to_string()),
+                ),
+            );
+        }
+    }
+    if let Some(env_lock) = env_lock {
+        let holder = env_lock.get_info().clone();
+        drop(env_lock);
+        send_lock_state(
+            telemetry,
+            LockState::unlocked(config.get_env_path(), Some(&holder)),
+        );
+    }
+
+    result.map(|_| ActionOutcome::new(report, telemetry_tracker.get_status()))
+}
+
+/// Normalize the ownership of the part of the environment modified by
+/// `action`, following --chown.
+fn normalize_ownership<T>(
+    config: &T,
+    action: &Action,
+    ownership: &Ownership,
+) -> Result<(), Box<dyn Error>>
+where
+    T: ManageObsEnvCli,
+{
+    let path = match action {
+        Action::CheckoutBranch | Action::CheckoutVersion | Action::CheckoutRunBranch => {
+            Path::new(config.get_env_path()).join(config.get_repository_name())
+        }
+        _ => Path::new(config.get_env_path()).to_path_buf(),
+    };
+    log::debug!("Normalizing ownership of {path:?}.");
+    let updated = ownership.apply(&path)?;
+    log::debug!("Updated ownership of {updated} paths.");
+    Ok(())
+}
 
-    match config.get_action()? {
+/// Validate or regenerate the setup file, following --setup-file-refresh,
+/// after an action that modified the environment.
+fn refresh_setup_file<T>(
+    config: &T,
+    action: &Action,
+    obs_env: &ObservingEnvironment,
+    report: &mut Report,
+) -> Result<(), Box<dyn Error>>
+where
+    T: ManageObsEnvCli,
+{
+    // Setup generates the setup file itself.
+    if !action.is_mutating() || matches!(action, Action::Setup) || config.get_skip_setup_file() {
+        return Ok(());
+    }
+    match config.get_setup_file_refresh() {
+        SetupFileRefresh::Never => {}
+        SetupFileRefresh::Validate => match obs_env.validate_setup_file() {
+            Ok(problems) => {
+                for (name, problem) in problems.iter() {
+                    let warning =
+                        format!("{name}: {problem}. Run Setup to regenerate the setup file.");
+                    log::warn!("{warning}");
+                    report.add_warning(&warning);
+                }
+            }
+            Err(error) => {
+                log::warn!("{error}");
+                report.add_warning(&error.to_string());
+            }
+        },
+        SetupFileRefresh::Regenerate => {
+            log::info!("Regenerating setup file.");
+            obs_env.create_setup_file()?;
+        }
+    }
+    Ok(())
+}
+
+/// Run the hook for the given stage ("pre" or "post") of the action, if
+/// there is one, recording its outcome in `report`.
+fn run_hook<T>(
+    hooks: Option<&Hooks>,
+    stage: &str,
+    config: &T,
+    action: &Action,
+    report: &mut Report,
+) -> Result<(), Box<dyn Error>>
+where
+    T: ManageObsEnvCli,
+{
+    let (Some(hooks), Some(hook_name)) = (hooks, action.get_hook_name()) else {
+        return Ok(());
+    };
+    let hook_name = format!("{stage}-{hook_name}");
+    let action_name = format!("{action:?}");
+    let env = [
+        ("OBS_ENV_ACTION", action_name.as_str()),
+        ("OBS_ENV_PATH", config.get_env_path()),
+        ("OBS_ENV_REPOSITORY", config.get_repository_name()),
+        ("OBS_ENV_BRANCH_NAME", config.get_branch_name()),
+    ];
+    let result = hooks.run(&hook_name, Path::new(config.get_env_path()), &env);
+    if let Ok(None) = result {
+        return Ok(());
+    }
+    report.record(config.get_repository_name(), &hook_name, &result);
+    result?;
+    Ok(())
+}
+
+/// Execute the action, recording the outcome of the operations on individual
+/// repositories in `report`.
+fn execute_action<T>(
+    config: &T,
+    action: &Action,
+    obs_env: &ObservingEnvironment,
+    report: &mut Report,
+    action_data: &mut ActionData,
+    telemetry: &dyn TelemetrySink,
+) -> Result<(), Box<dyn Error>>
+where
+    T: ManageObsEnvCli,
+{
+    match action {
         Action::Setup => {
             log::info!("Executing Setup...");
 
-            log::debug!("Creating path...");
-            obs_env.create_path()?;
-
             log::debug!("Cloning repositories...");
             let cloned_repos = obs_env.clone_repositories();
             log::info!("The following repositories where cloned: ");
-            for repo in cloned_repos.iter() {
-                match repo {
-                    Ok(repo) => log::info!("{:?}", repo.path()),
-                    Err(error) => log::error!("Failed to clone: {error:?}"),
+            for (name, outcome) in cloned_repos.iter() {
+                match outcome {
+                    CloneOutcome::Cloned(path) => {
+                        log::info!("{path:?}");
+                        report.add_success(name, "clone");
+                    }
+                    CloneOutcome::AlreadyPresent(path) => {
+                        log::debug!("{name} already present at {path:?}.")
+                    }
+                    CloneOutcome::Failed(error) => {
+                        log::error!("{error}");
+                        report.add_failure(name, "clone", &error.to_string());
+                    }
                 }
             }
-            log::info!("Creating setup file.");
-            obs_env.create_setup_file()?;
-            log::debug!("Sending action.");
-            send_action_data("setup", "", "");
+            check_cancelled(obs_env)?;
+            if config.get_skip_setup_file() {
+                log::info!("Skipping setup file.");
+            } else {
+                log::info!("Creating setup file.");
+                obs_env.create_setup_file()?;
+            }
             log::debug!("Sending summary.");
-            let current_versions = obs_env.get_current_env_versions();
-            send_summary_data(&current_versions);
+            send_summary_data(telemetry, obs_env);
         }
         Action::PrintConfig => {
             log::info!("{}", obs_env.summarize());
         }
         Action::Reset => {
             log::info!("Resetting Observing environment...");
-            let run_branch = {
-                if let Ok(efd_name) = env::var("MANAGE_OBS_ENV_EFD_NAME") {
-                    RunBranch::retrieve_from_efd(&efd_name)?
-                        .get_branch_name()
-                        .to_owned()
-                } else {
-                    "".to_owned()
-                }
-            };
-            if let Err(error) =
-                obs_env.reset_base_environment(config.get_base_env_source_repo(), &run_branch)
-            {
-                log::error!("Error resetting {} repositories.", error.len());
-                for err in error {
-                    log::error!("{:?}", err);
-                }
-            } else {
-                log::info!("All repositories set to their base versions.");
-            }
-            log::debug!("Sending action.");
-            send_action_data("reset", "", "");
+            let run_branch = get_run_branch_name(config)?;
+            reset_base_environment(
+                obs_env,
+                config.get_base_env_source_repo(),
+                &run_branch,
+                report,
+            );
+            check_cancelled(obs_env)?;
             log::debug!("Sending summary.");
-            let current_versions = obs_env.get_current_env_versions();
-            send_summary_data(&current_versions);
+            send_summary_data(telemetry, obs_env);
         }
         Action::ShowCurrentVersions => {
             log::info!("Current environment versions:");
@@ -166,9 +1157,36 @@ where
                     Ok(version) => log::info!("{name}: {version}"),
                     Err(error) => log::error!("{name}: {error:?}"),
                 }
+                report.record(name, "describe", version);
+            }
+        }
+        Action::Verify => {
+            let drift = obs_env.get_drift(config.get_base_env_source_repo())?;
+            if drift.is_empty() {
+                log::info!("All repositories at their base versions.");
+            }
+            for (name, (base_version, current_version)) in drift.iter() {
+                log::warn!("{name}: at {current_version}, base version is {base_version}.");
+                report.add_failure(
+                    name,
+                    "verify",
+                    &format!("At {current_version}, base version is {base_version}"),
+                );
+            }
+            let drift = Drift::from_btree_map(&drift).with_env_path(obs_env.get_destination());
+            if let Err(error) = telemetry.send_drift(drift) {
+                log::error!("{error}");
+            }
+        }
+        Action::ValidateSetup => {
+            let problems = obs_env.validate_setup_file()?;
+            if problems.is_empty() {
+                log::info!("Setup file matches the environment.");
+            }
+            for (name, problem) in problems.iter() {
+                log::warn!("{name}: {problem}.");
+                report.add_failure(name, "validate-setup", problem);
             }
-            log::debug!("Sending action.");
-            send_action_data("show-current-versions", "", "");
         }
         Action::ShowOriginalVersions => {
             match obs_env.get_base_env_versions(config.get_base_env_source_repo()) {
@@ -180,114 +1198,301 @@ where
                 }
                 Err(error) => {
                     log::error!("{error:?}");
+                    report.add_warning(&error.to_string());
                 }
             }
-            log::debug!("Sending action.");
-            send_action_data("show-original-versions", "", "");
         }
         Action::CheckoutBranch => {
-            obs_env.checkout_branch(config.get_repository_name(), config.get_branch_name())?;
-            log::debug!("Sending action.");
-            send_action_data(
-                "checkout-branch",
-                config.get_repository_name(),
-                config.get_branch_name(),
-            );
+            action_data.set_repository(config.get_repository_name());
+            action_data.set_branch_name(config.get_branch_name());
+            let result =
+                obs_env.checkout_branch(config.get_repository_name(), config.get_branch_name());
+            report.record(config.get_repository_name(), "checkout-branch", &result);
+            result?;
             log::debug!("Sending summary.");
-            let current_versions = obs_env.get_current_env_versions();
-            send_summary_data(&current_versions);
+            send_summary_data(telemetry, obs_env);
         }
         Action::CheckoutVersion => {
-            obs_env.reset_index_to_version(config.get_repository_name(), config.get_version())?;
-            log::debug!("Sending action.");
-            send_action_data(
-                "checkout-version",
-                config.get_repository_name(),
-                config.get_version(),
-            );
+            action_data.set_repository(config.get_repository_name());
+            action_data.set_branch_name(config.get_version());
+            let result = obs_env
+                .resolve_version(config.get_repository_name(), config.get_version())
+                .and_then(|version| {
+                    obs_env.reset_index_to_version(config.get_repository_name(), &version)?;
+                    Ok(version)
+                });
+            report.record(config.get_repository_name(), "checkout-version", &result);
+            let version = result?;
+            action_data.set_branch_name(&version);
             log::debug!("Sending summary.");
-            let current_versions = obs_env.get_current_env_versions();
-            send_summary_data(&current_versions);
+            send_summary_data(telemetry, obs_env);
         }
+        #[cfg(feature = "telemetry")]
         Action::CreateTopics => {
-            if let Ok(sasquatch_rest_proxy_url) = env::var("SASQUATCH_REST_PROXY_URL") {
-                create_topics(&sasquatch_rest_proxy_url)?
+            if let Some(rest_proxy_url) = get_rest_proxy_url() {
+                // Fail before creating the topics, so they are not left without
+                // their schemas.
+                let Some(schema_registry_url) = get_schema_registry_url() else {
+                    return Err(Box::new(ObsEnvError::ERROR(
+                        "Environment variables SASQUATCH_SCHEMA_REGISTRY_URL and \
+                        SASQUATCH_REST_PROXY_URL not set. One of them is needed to register \
+                        the schemas when the REST proxy is given by \
+                        SASQUATCH_REST_PROXY_BASE_URL."
+                            .to_owned(),
+                    )));
+                };
+                create_topics(&rest_proxy_url, &config.get_topic_config())?;
+                log::info!("Registering schemas with {schema_registry_url}.");
+                register_schemas(&schema_registry_url, &get_telemetry_schemas(obs_env))?;
             } else {
-                log::error!(
+                report_warning(
+                    report,
                     "Environment variable SASQUATCH_REST_PROXY_URL, not set. \
                     This variable defines the url of the sasquatch service and needs \
-                    to be defined for the topics to be registered."
+                    to be defined for the topics to be registered.",
                 );
             }
         }
+        #[cfg(feature = "telemetry")]
+        Action::ListTopics | Action::DescribeTopics | Action::DeleteTopics => {
+            let Some(rest_proxy_url) = get_rest_proxy_url() else {
+                return Err(Box::new(ObsEnvError::ERROR(
+                    "Environment variable SASQUATCH_REST_PROXY_URL, not set. \
+                    This variable defines the url of the sasquatch service and needs \
+                    to be defined to manage the topics."
+                        .to_owned(),
+                )));
+            };
+            match action {
+                Action::DeleteTopics => {
+                    if !config.get_force() {
+                        return Err(Box::new(ObsEnvError::ERROR(
+                            "Deleting the topics removes all their records, use --force to \
+                            confirm."
+                                .to_owned(),
+                        )));
+                    }
+                    let deleted = delete_topics(&rest_proxy_url)?;
+                    log::info!("Deleted {} topics.", deleted.len());
+                }
+                _ => {
+                    for topic in list_topics(&rest_proxy_url)? {
+                        log::info!(
+                            "{}: {} partitions, replication factor {}.",
+                            topic.get_topic_name(),
+                            topic.get_partitions_count(),
+                            topic.get_replication_factor()
+                        );
+                        if let Action::DescribeTopics = action {
+                            for config_value in
+                                describe_topic(&rest_proxy_url, topic.get_topic_name())?
+                            {
+                                let value = config_value.value.unwrap_or_default();
+                                if config_value.is_default {
+                                    log::debug!("    {} = {value} (default)", config_value.name);
+                                } else {
+                                    log::info!("    {} = {value}", config_value.name);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
         Action::RegisterRunBranch => {
-            if let Ok(_) = env::var("SASQUATCH_REST_PROXY_URL") {
+            action_data.set_branch_name(config.get_branch_name());
+            if telemetry.is_configured() {
                 log::info!("Registering run branch.");
-                send_run_branch(&config.get_branch_name());
+                send_run_branch(
+                    telemetry,
+                    config.get_branch_name(),
+                    config.get_run_branch_ttl(),
+                );
             } else {
-                log::error!(
-                    "In order to register the run branch you must setup SASQUATCH_REST_PROXY_URL."
+                report_warning(
+                    report,
+                    "In order to register the run branch you must setup SASQUATCH_REST_PROXY_URL.",
                 );
             }
-            log::debug!("Sending action.");
-            send_action_data("register-run-branch", "", &config.get_branch_name());
         }
         Action::ClearRunBranch => {
-            if let Ok(_) = env::var("SASQUATCH_REST_PROXY_URL") {
+            if telemetry.is_configured() {
                 log::info!("Clearing run branch.");
-                send_run_branch("");
+                send_run_branch(telemetry, "", None);
             } else {
-                log::error!(
-                    "In order to clear the run branch you must setup SASQUATCH_REST_PROXY_URL."
+                report_warning(
+                    report,
+                    "In order to clear the run branch you must setup SASQUATCH_REST_PROXY_URL.",
                 );
             }
-            log::debug!("Sending action.");
-            send_action_data("clear-run-branch", "", "");
         }
+        #[cfg(feature = "telemetry")]
         Action::ListRunBranch => {
-            if let Ok(efd_name) = env::var("MANAGE_OBS_ENV_EFD_NAME") {
+            if let Some(efd_name) = config.get_efd_name() {
                 log::info!("Retrieving run branch from {efd_name} instance of the EFD.");
                 let run_branch = RunBranch::retrieve_from_efd(&efd_name)?;
                 log::info!("Current run branch: {}", run_branch.get_branch_name());
             } else {
-                log::error!(
-                    "In order to list the currently registered run branch you must setup the MANAGE_OBS_ENV_EFD_NAME environment variable with the name of the EFD instance for this environment."
+                report_warning(
+                    report,
+                    "In order to list the currently registered run branch you must setup the MANAGE_OBS_ENV_EFD_NAME environment variable with the name of the EFD instance for this environment.",
                 );
             }
-            log::debug!("Sending action.");
-            send_action_data("list-run-branch", "", "");
         }
+        #[cfg(feature = "telemetry")]
+        Action::RunBranchHistory => {
+            if let Some(efd_name) = config.get_efd_name() {
+                log::info!("Retrieving run branch history from {efd_name} instance of the EFD.");
+                let history =
+                    RunBranch::retrieve_history_from_efd(&efd_name, config.get_history_limit())?;
+                for run_branch in history {
+                    let time = match Utc.timestamp_millis_opt(run_branch.get_timestamp()) {
+                        LocalResult::Single(time) => time.to_rfc3339(),
+                        _ => run_branch.get_timestamp().to_string(),
+                    };
+                    let user = match run_branch.get_user() {
+                        "" => "unknown user",
+                        user => user,
+                    };
+                    match run_branch.get_branch_name() {
+                        "" => log::info!("{time}: run branch cleared by {user}."),
+                        branch_name => log::info!("{time}: {branch_name} registered by {user}."),
+                    }
+                }
+            } else {
+                report_warning(
+                    report,
+                    "In order to show the run branch history you must setup the MANAGE_OBS_ENV_EFD_NAME environment variable with the name of the EFD instance for this environment.",
+                );
+            }
+        }
+        #[cfg(feature = "telemetry")]
+        Action::ShowAllSites => {
+            let comparison = SiteComparison::retrieve_from_efds(config.get_efd_names())?;
+            log::info!("Latest versions by site (* marks differences):");
+            for (line, repository) in comparison
+                .to_table()
+                .into_iter()
+                .zip([""].into_iter().chain(comparison.get_repositories()))
+            {
+                if comparison.is_different(repository) {
+                    log::warn!("{line}");
+                } else {
+                    log::info!("{line}");
+                }
+            }
+        }
+        #[cfg(feature = "telemetry")]
         Action::CheckoutRunBranch => {
-            if let Ok(efd_name) = env::var("MANAGE_OBS_ENV_EFD_NAME") {
+            action_data.set_repository(config.get_repository_name());
+            if let Some(efd_name) = config.get_efd_name() {
                 let run_branch = RunBranch::retrieve_from_efd(&efd_name)?;
-                if run_branch.get_branch_name().len() > 0 {
+                if !run_branch.get_branch_name().is_empty() {
                     log::info!(
                         "Checkout run branch ({}) for {}.",
                         run_branch.get_branch_name(),
                         config.get_repository_name()
                     );
-                    obs_env.checkout_branch(
-                        config.get_repository_name(),
-                        run_branch.get_branch_name(),
-                    )?;
-                    log::debug!("Sending action.");
-                    send_action_data(
-                        "checkout-run-branch",
+                    let result = obs_env.checkout_branch(
                         config.get_repository_name(),
                         run_branch.get_branch_name(),
                     );
+                    action_data.set_branch_name(run_branch.get_branch_name());
+                    report.record(config.get_repository_name(), "checkout-branch", &result);
+                    result?;
                     log::debug!("Sending summary.");
-                    let current_versions = obs_env.get_current_env_versions();
-                    send_summary_data(&current_versions);
+                    send_summary_data(telemetry, obs_env);
                 } else {
-                    log::error!("Currently no run branch registered.");
+                    report_warning(report, "Currently no run branch registered.");
                 }
             } else {
-                log::error!(
-                    "In order to checkout the currently registered run branch you must setup the MANAGE_OBS_ENV_EFD_NAME environment variable with the name of the EFD instance for this environment."
+                report_warning(
+                    report,
+                    "In order to checkout the currently registered run branch you must setup the MANAGE_OBS_ENV_EFD_NAME environment variable with the name of the EFD instance for this environment.",
                 );
             }
         }
+        Action::FlushTelemetry => {
+            // Spooled payloads are retried by the telemetry sender when it
+            // starts, and flushed before exiting.
+            #[cfg(feature = "telemetry")]
+            if let Some(telemetry_spool) = telemetry.get_spool() {
+                log::info!(
+                    "{} telemetry payloads spooled in {:?}.",
+                    telemetry_spool.pending().len(),
+                    telemetry_spool.get_dir()
+                );
+                return Ok(());
+            }
+            log::info!("No telemetry spool configured.");
+        }
+        Action::SyncTelemetry => {
+            if config.get_offline() || cfg!(not(feature = "telemetry")) {
+                return Err(Box::new(ObsEnvError::ERROR(
+                    "Can't sync the telemetry journal when running offline, or when built \
+                    without the telemetry feature."
+                        .to_owned(),
+                )));
+            }
+            let journal = get_telemetry_journal(config);
+            let sent = journal.sync(telemetry)?;
+            log::info!(
+                "Sent {sent} telemetry records from {:?}.",
+                journal.get_path()
+            );
+        }
+        Action::Unlock => match EnvLock::release(config.get_env_path(), config.get_force())? {
+            Some(holder) => {
+                log::info!("Removed environment lock held by {holder}.");
+                send_lock_state(
+                    telemetry,
+                    LockState::unlocked(config.get_env_path(), Some(&holder)),
+                );
+            }
+            None => log::info!("Environment is not locked."),
+        },
+        #[cfg(feature = "telemetry")]
+        Action::ShowLock => {
+            let Some(efd_name) = config.get_efd_name() else {
+                return Err(Box::new(ObsEnvError::ERROR(
+                    "In order to show the environment lock you must setup the \
+                    MANAGE_OBS_ENV_EFD_NAME environment variable with the name of the EFD \
+                    instance for this environment."
+                        .to_owned(),
+                )));
+            };
+            log::info!("Retrieving environment lock from {efd_name} instance of the EFD.");
+            match LockState::retrieve_from_efd(&efd_name, config.get_env_path())? {
+                Some(lock_state) if lock_state.is_locked() => {
+                    let since = match Utc.timestamp_millis_opt(lock_state.get_locked_since()) {
+                        LocalResult::Single(time) => time.to_rfc3339(),
+                        _ => lock_state.get_locked_since().to_string(),
+                    };
+                    log::info!(
+                        "Environment locked by {} on {} running {} since {since}.",
+                        lock_state.get_holder(),
+                        lock_state.get_host(),
+                        lock_state.get_reason()
+                    );
+                }
+                _ => log::info!("Environment is not locked."),
+            }
+        }
+        #[cfg(not(feature = "telemetry"))]
+        Action::CreateTopics
+        | Action::ListTopics
+        | Action::DescribeTopics
+        | Action::DeleteTopics
+        | Action::ListRunBranch
+        | Action::RunBranchHistory
+        | Action::ShowAllSites
+        | Action::CheckoutRunBranch
+        | Action::ShowLock => {
+            return Err(Box::new(ObsEnvError::ERROR(format!(
+                "The {action:?} action needs manage_obs_env to be built with the telemetry \
+                feature, to query the EFD or manage the sasquatch topics."
+            ))));
+        }
     };
     Ok(())
 }
@@ -307,20 +1512,111 @@ pub enum Action {
     ShowCurrentVersions,
     /// Show original versions.
     ShowOriginalVersions,
+    /// Check that all repositories are at their base versions, reporting
+    /// those that are not and sending them to the drift topic.
+    Verify,
+    /// Check that the paths in the setup file exist and match the
+    /// repositories set up by the environment.
+    ValidateSetup,
     /// Checkout a branch in a repository.
     CheckoutBranch,
     /// Checkout a version in a repository.
     CheckoutVersion,
     /// Create topics to log data to sasquatch.
     CreateTopics,
+    /// List the topics used to log data to sasquatch.
+    ListTopics,
+    /// Show the configuration of the topics used to log data to sasquatch.
+    DescribeTopics,
+    /// Delete the topics used to log data to sasquatch, and all their
+    /// records. Requires --force. Run CreateTopics to recreate them.
+    DeleteTopics,
     /// Register run branch.
     RegisterRunBranch,
     /// Clear the run branch.
     ClearRunBranch,
     /// List the currently registered run branch.
     ListRunBranch,
+    /// Show when the last run branches were registered or cleared, and by
+    /// whom. See --history-limit.
+    RunBranchHistory,
+    /// Compare the latest versions reported by several sites, from their EFD
+    /// instances. See --efd-names.
+    ShowAllSites,
     /// Checkout the run branch for a specific repository.
     CheckoutRunBranch,
+    /// Remove the environment lock. Use --force to remove a lock held by
+    /// someone else.
+    Unlock,
+    /// Send the telemetry spooled while sasquatch was unreachable.
+    FlushTelemetry,
+    /// Send the telemetry written to the telemetry journal while running
+    /// with --offline.
+    SyncTelemetry,
+    /// Show who holds the environment lock, according to the lock events
+    /// in the EFD.
+    ShowLock,
+}
+
+impl Action {
+    /// Does the action modify the environment?
+    ///
+    /// Actions that modify the environment must hold the environment lock.
+    pub fn is_mutating(&self) -> bool {
+        matches!(
+            self,
+            Action::Setup
+                | Action::Reset
+                | Action::CheckoutBranch
+                | Action::CheckoutVersion
+                | Action::CheckoutRunBranch
+        )
+    }
+
+    /// Name of the action in the action telemetry, None if the action is not
+    /// recorded.
+    pub fn get_telemetry_name(&self) -> Option<&'static str> {
+        match self {
+            Action::Setup => Some("setup"),
+            Action::Reset => Some("reset"),
+            Action::ShowCurrentVersions => Some("show-current-versions"),
+            Action::ShowOriginalVersions => Some("show-original-versions"),
+            Action::Verify => Some("verify"),
+            Action::ValidateSetup => Some("validate-setup"),
+            Action::CheckoutBranch => Some("checkout-branch"),
+            Action::CheckoutVersion => Some("checkout-version"),
+            Action::RegisterRunBranch => Some("register-run-branch"),
+            Action::ClearRunBranch => Some("clear-run-branch"),
+            Action::ListRunBranch => Some("list-run-branch"),
+            Action::RunBranchHistory => Some("run-branch-history"),
+            Action::ShowAllSites => Some("show-all-sites"),
+            Action::ShowLock => Some("show-lock"),
+            Action::CheckoutRunBranch => Some("checkout-run-branch"),
+            Action::PrintConfig
+            | Action::CreateTopics
+            | Action::ListTopics
+            | Action::DescribeTopics
+            | Action::DeleteTopics
+            | Action::Unlock
+            | Action::FlushTelemetry
+            | Action::SyncTelemetry => None,
+        }
+    }
+
+    /// Name used for the hooks of the action, None if the action has no
+    /// hooks.
+    ///
+    /// All checkout actions share the "checkout" hooks.
+    pub fn get_hook_name(&self) -> Option<&str> {
+        match self {
+            Action::Setup => Some("setup"),
+            Action::Reset => Some("reset"),
+            Action::CheckoutBranch | Action::CheckoutVersion | Action::CheckoutRunBranch => {
+                Some("checkout")
+            }
+            _ => None,
+        }
+    }
 }
 
 #[derive(clap::ValueEnum, Clone, Debug)]
@@ -332,50 +1628,333 @@ pub enum LogLevel {
     Error,
 }
 
-fn send_summary_data(current_versions: &BTreeMap<String, Result<String, ObsEnvError>>) {
-    let log_summary = Summary::from_btree_map(current_versions);
-    let payload = get_payload(log_summary);
-    send_payload(&payload, Summary::get_topic_name());
+/// Outcome of each repository reset, collected from the progress of the
+/// reset.
+#[derive(Default)]
+struct ResetRecorder {
+    finished: RefCell<Vec<(String, Result<(), String>)>>,
 }
 
-fn send_action_data(action: &str, repository: &str, branch_name: &str) {
-    let action = ActionData::new(action, repository, branch_name);
-    let payload = get_payload(action);
-    send_payload(&payload, ActionData::get_topic_name());
-}
-
-fn send_run_branch(branch_name: &str) {
-    let run_branch = RunBranch::new(branch_name);
-    let payload = get_payload(run_branch);
-    send_payload(&payload, RunBranch::get_topic_name());
+impl ProgressObserver for ResetRecorder {
+    fn repo_finished(&self, repo: &str, error: Option<&ObsEnvError>) {
+        let result = match error {
+            Some(error) => Err(error.to_string()),
+            None => Ok(()),
+        };
+        self.finished.borrow_mut().push((repo.to_owned(), result));
+    }
 }
 
-fn send_payload<T: AvroSchema + Debug + Serialize>(payload: &Payload<T>, topic_name: &str) {
-    let client = reqwest::blocking::Client::new();
-    log::debug!("{topic_name}");
-    if let Ok(sasquatch_rest_proxy_url) = env::var("SASQUATCH_REST_PROXY_URL") {
-        if let Ok(res) = client
-            .post(format!(
-                "{sasquatch_rest_proxy_url}/sasquatch-rest-proxy/topics/lsst.obsenv.{topic_name}",
-            ))
-            .header("Content-Type", "application/vnd.kafka.avro.v2+json")
-            .header("Accept", "application/vnd.kafka.v2+json")
-            .json(payload)
-            .send()
-        {
-            if !res.status().is_success() {
-                log::error!("Server replied with error to payload request: {res:?}. {payload:?}");
-            } else {
-                log::trace!("Payload: {payload:?}.");
+/// Reset all the repositories to their base versions (or `run_branch`),
+/// recording the outcome of each repository in `report`.
+fn reset_base_environment<G: GitBackend>(
+    obs_env: &ObservingEnvironment<G>,
+    base_env_branch: &str,
+    run_branch: &str,
+    report: &mut Report,
+) {
+    let recorder = ResetRecorder::default();
+    let result =
+        obs_env.reset_base_environment_with_progress(base_env_branch, run_branch, &recorder);
+    let finished = recorder.finished.into_inner();
+    for (repo, result) in &finished {
+        report.record(repo, "reset", result);
+    }
+    if let Err(error) = result {
+        log::error!("Error resetting {} repositories.", error.len());
+        for (repo, err) in error {
+            log::error!("{repo}: {:?}", err);
+            // Repositories skipped, or not reached because reading the base
+            // versions failed, are not in the progress.
+            if !finished.iter().any(|(name, _)| *name == repo) {
+                report.add_failure(&repo, "reset", &err.to_string());
             }
-        } else {
-            log::error!("Error sending payload.");
         }
     } else {
-        log::error!(
-            "Environment variable SASQUATCH_REST_PROXY_URL, not set. \
-            This variable defines the url of the sasquatch service and needs \
-            to be defined for actions to be registered."
+        log::info!("All repositories set to their base versions.");
+    }
+}
+
+/// Fail the action if it was cancelled, after reporting the repositories
+/// it skipped.
+fn check_cancelled(obs_env: &ObservingEnvironment) -> Result<(), ObsEnvError> {
+    if obs_env.get_cancellation_token().is_cancelled() {
+        Err(ObsEnvError::CANCELLED(
+            "Action cancelled, the remaining repositories were skipped.".to_owned(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Log a problem that prevented the action from doing anything, recording it
+/// as a warning in `report`, as it does not fail the action.
+fn report_warning(report: &mut Report, warning: &str) {
+    log::error!("{warning}");
+    report.add_warning(warning);
+}
+
+/// Telemetry journal used when running offline.
+fn get_telemetry_journal<T>(config: &T) -> TelemetryJournal
+where
+    T: ManageObsEnvCli,
+{
+    TelemetryJournal::new(&match config.get_telemetry_journal() {
+        Some(telemetry_journal) => PathBuf::from(telemetry_journal),
+        None => Path::new(config.get_env_path()).join(JOURNAL_FILE_NAME),
+    })
+}
+
+/// Schemas of all the telemetry records.
+#[cfg(feature = "telemetry")]
+fn get_telemetry_schemas(obs_env: &ObservingEnvironment) -> Vec<RecordSchema> {
+    vec![
+        Summary::from_btree_map(&obs_env.get_current_env_versions()).get_schema(),
+        ActionData::default().get_schema(),
+        RunBranch::default().get_schema(),
+        Drift::default().get_schema(),
+        LockState::default().get_schema(),
+        ActionMetrics::default().get_schema(),
+    ]
+}
+
+/// Name of the run branch registered in the EFD instance of the
+/// environment, empty if there is none or no EFD instance is configured.
+#[cfg(feature = "telemetry")]
+fn get_run_branch_name<T>(config: &T) -> Result<String, Box<dyn Error>>
+where
+    T: ManageObsEnvCli,
+{
+    match config.get_efd_name() {
+        Some(efd_name) => Ok(RunBranch::retrieve_from_efd(&efd_name)?
+            .get_branch_name()
+            .to_owned()),
+        None => Ok(String::new()),
+    }
+}
+
+#[cfg(not(feature = "telemetry"))]
+fn get_run_branch_name<T>(config: &T) -> Result<String, Box<dyn Error>>
+where
+    T: ManageObsEnvCli,
+{
+    if let Some(efd_name) = config.get_efd_name() {
+        log::warn!(
+            "Built without the telemetry feature, ignoring the run branch registered in \
+            {efd_name}."
+        );
+    }
+    Ok(String::new())
+}
+
+/// Post `notification` to the webhook at `webhook_url`.
+#[cfg(feature = "telemetry")]
+fn send_notification(webhook_url: &str, notification: &Notification) {
+    log::debug!("Sending notification.");
+    if let Err(error) = notification.send(webhook_url) {
+        log::error!("Failed to send notification: {error}");
+    }
+}
+
+#[cfg(not(feature = "telemetry"))]
+fn send_notification(_webhook_url: &str, notification: &Notification) {
+    log::warn!(
+        "Built without the telemetry feature, not sending the notification: {}",
+        notification.get_text()
+    );
+}
+
+fn send_summary_data(telemetry: &dyn TelemetrySink, obs_env: &ObservingEnvironment) {
+    let log_summary = Summary::from_btree_map(&obs_env.get_current_env_versions())
+        .with_env_path(obs_env.get_destination());
+    if let Err(error) = telemetry.send_summary(log_summary) {
+        log::error!("{error}");
+    }
+}
+
+fn send_lock_state(telemetry: &dyn TelemetrySink, lock_state: LockState) {
+    if let Err(error) = telemetry.send_lock_state(lock_state) {
+        log::error!("{error}");
+    }
+}
+
+fn send_run_branch(telemetry: &dyn TelemetrySink, branch_name: &str, ttl: Option<Duration>) {
+    let run_branch = match ttl {
+        Some(ttl) => RunBranch::new(branch_name).with_ttl(ttl),
+        None => RunBranch::new(branch_name),
+    };
+    if let Err(error) = telemetry.send_run_branch(run_branch) {
+        log::error!("{error}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        reset_base_environment, run_with_telemetry, Action, ManageObsEnv, ManageObsEnvCli,
+    };
+    use crate::sasquatch::{
+        log_summary::ActionData, metrics::ActionMetrics, run_branch::RunBranch,
+        sink::TelemetryRecorder,
+    };
+    use crate::{
+        config::Config,
+        git_backend::FakeGitBackend,
+        observing_environment::{ObservingEnvironment, RepositoryConfig},
+        report::Report,
+        repos::Repos,
+        setup_template::{SetupMode, SetupShell, SetupTemplate},
+    };
+    use clap::{CommandFactory, Parser};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_builder() {
+        let config = ManageObsEnv::builder(Action::Setup).build();
+        let cli_config = ManageObsEnv::parse_from(["manage_obs_env", "--action", "setup"]);
+        assert_eq!(format!("{config:?}"), format!("{cli_config:?}"));
+
+        let config = ManageObsEnv::builder(Action::Setup)
+            .with_action(Action::CheckoutBranch)
+            .with_env_path("/obs-env")
+            .with_repository(Repos::TsWep)
+            .with_branch_name("tickets/DM-1")
+            .with_setup_shells(&[SetupShell::Bash])
+            .with_as_user("saluser")
+            .with_efd_names(&["usdf_efd"])
+            .with_offline(true)
+            .build();
+        assert!(matches!(config.get_action(), Ok(Action::CheckoutBranch)));
+        assert_eq!(config.get_env_path(), "/obs-env");
+        assert_eq!(config.get_repository_name(), "ts_wep");
+        assert_eq!(config.get_branch_name(), "tickets/DM-1");
+        assert_eq!(config.get_setup_shells(), &[SetupShell::Bash]);
+        assert_eq!(config.get_as_user(), Some("saluser"));
+        assert_eq!(config.get_efd_names(), &["usdf_efd".to_owned()]);
+        assert!(config.get_offline());
+        assert!(!config.get_force());
+
+        let config = ManageObsEnv::builder(Action::CheckoutBranch).build();
+        assert!(config.get_action().is_err());
+    }
+
+    #[test]
+    fn test_from_matches_with_config() {
+        let config_file =
+            std::env::temp_dir().join(format!("obs_env_test_config_{}.toml", std::process::id()));
+        std::fs::write(
+            &config_file,
+            "env_path = \"/config/obs-env\"\n\
+            setup_mode = \"pythonpath\"\n\
+            setup_shells = [\"csh\"]\n\
+            efd_name = \"base_efd\"\n",
         )
+        .unwrap();
+
+        let matches = ManageObsEnv::command().get_matches_from([
+            "manage_obs_env",
+            "--action",
+            "setup",
+            "--config",
+            config_file.to_str().unwrap(),
+            "--setup-shell",
+            "bash",
+            "--setup-template",
+            "bash=setup.sh.j2",
+        ]);
+        let config = ManageObsEnv::from_matches_with_config(&matches);
+
+        std::fs::remove_file(&config_file).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.get_env_path(), "/config/obs-env");
+        assert_eq!(config.get_setup_mode(), SetupMode::Pythonpath);
+        assert_eq!(config.get_setup_shells(), &[SetupShell::Bash]);
+        assert_eq!(
+            config.get_setup_templates(),
+            &[SetupTemplate::for_shell(SetupShell::Bash, "setup.sh.j2")]
+        );
+        assert_eq!(config.get_efd_name().as_deref(), Some("base_efd"));
+        assert_eq!(config.get_base_env_def_file(), "cycle/cycle.env");
+
+        let config = ManageObsEnv::builder(Action::Setup)
+            .with_env_path("/obs-env")
+            .with_config(&Config {
+                env_path: Some("/config/obs-env".to_owned()),
+                ..Default::default()
+            })
+            .build();
+        assert_eq!(config.get_env_path(), "/config/obs-env");
+    }
+
+    #[test]
+    fn test_reset_report() {
+        let local_file =
+            std::env::temp_dir().join(format!("obs_env_test_reset_{}.env", std::process::id()));
+        std::fs::write(&local_file, "ts_wep=1.1.0\ncwfs=2.0.0\n").unwrap();
+        let git = FakeGitBackend::new()
+            .with_remote("https://github.com/lsst-ts/ts_wep", &["v1.1.0"], &["main"])
+            .with_remote("https://github.com/lsst-ts/cwfs", &["v1.0.0"], &["main"]);
+        let obs_env = ObservingEnvironment::builder()
+            .with_destination("/obs-env")
+            .with_repositories(BTreeMap::from_iter([
+                (
+                    "ts_wep".to_owned(),
+                    RepositoryConfig::new("https://github.com/lsst-ts"),
+                ),
+                (
+                    "cwfs".to_owned(),
+                    RepositoryConfig::new("https://github.com/lsst-ts"),
+                ),
+            ]))
+            .with_base_env_local_file(local_file.to_str().unwrap())
+            .with_git_backend(git)
+            .build();
+        obs_env.clone_repositories();
+
+        let mut report = Report::new("Reset");
+        reset_base_environment(&obs_env, "main", "", &mut report);
+        std::fs::remove_file(&local_file).unwrap();
+
+        assert!(!report.is_success());
+        let entries: Vec<(&str, bool)> = report
+            .get_entries()
+            .iter()
+            .map(|entry| (entry.get_repository(), entry.is_success()))
+            .collect();
+        assert_eq!(entries, [("cwfs", false), ("ts_wep", true)]);
+    }
+
+    #[test]
+    fn test_run_with_telemetry() {
+        let env_path = std::env::temp_dir();
+        let config = ManageObsEnv::parse_from([
+            "manage_obs_env",
+            "--action",
+            "register-run-branch",
+            "--branch-name",
+            "tickets/DM-1",
+            "--env-path",
+            env_path.to_str().unwrap(),
+        ]);
+        let recorder = TelemetryRecorder::new();
+
+        let outcome = run_with_telemetry(&config, &recorder).unwrap();
+
+        assert!(outcome.is_success());
+        assert!(outcome.get_warnings().is_empty());
+        assert_eq!(outcome.get_telemetry().get_accepted(), recorder.len());
+        assert!(outcome.get_telemetry().is_success());
+
+        let run_branches = recorder.get_records(RunBranch::get_topic_name());
+        assert_eq!(run_branches.len(), 1);
+        assert_eq!(run_branches[0]["branch_name"], "tickets/DM-1");
+        let actions = recorder.get_records(ActionData::get_topic_name());
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0]["action"], "register-run-branch");
+        assert_eq!(
+            recorder.get_records(ActionMetrics::get_topic_name()).len(),
+            1
+        );
     }
 }
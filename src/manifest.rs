@@ -0,0 +1,139 @@
+use crate::{
+    error::ObsEnvError,
+    setup_template::{write_setup_file, SetupStyle},
+};
+use chrono::{SecondsFormat, Utc};
+use std::{collections::BTreeMap, path::Path};
+
+/// Name of the manifest file, in the environment.
+pub const MANIFEST_FILE_NAME: &str = "obs_env_manifest.json";
+
+/// Machine-readable description of the environment, written alongside the
+/// setup file for the tools that need to know what the environment
+/// contains (e.g. nublado spawner hooks and dashboards).
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Manifest {
+    /// Path of the environment.
+    destination: String,
+    /// Generation time, as an RFC 3339 UTC timestamp.
+    generated_at: String,
+    /// User generating the manifest.
+    user: String,
+    /// Cycle and revision of the base environment, e.g. "c0039.004", if
+    /// known.
+    cycle_revision: Option<String>,
+    /// Repositories of the environment, by name.
+    repositories: BTreeMap<String, ManifestRepository>,
+}
+
+/// Repository of the environment, in the manifest.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ManifestRepository {
+    /// Path of the repository clone.
+    path: String,
+    /// Current version of the repository, None if it can't be described
+    /// (e.g. it is not cloned).
+    version: Option<String>,
+    /// How the repository is set up by the setup file.
+    setup: SetupStyle,
+}
+
+impl Manifest {
+    pub fn new(destination: &str, user: &str) -> Manifest {
+        Manifest {
+            destination: destination.to_owned(),
+            generated_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            user: user.to_owned(),
+            cycle_revision: None,
+            repositories: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_cycle_revision(mut self, cycle_revision: &str) -> Self {
+        self.cycle_revision = Some(cycle_revision.to_owned());
+        self
+    }
+
+    /// Add a repository of the environment.
+    pub fn add_repository(
+        &mut self,
+        name: &str,
+        path: &str,
+        version: Option<&str>,
+        setup: SetupStyle,
+    ) {
+        self.repositories.insert(
+            name.to_owned(),
+            ManifestRepository {
+                path: path.to_owned(),
+                version: version.map(|version| version.to_owned()),
+                setup,
+            },
+        );
+    }
+
+    pub fn get_cycle_revision(&self) -> Option<&str> {
+        self.cycle_revision.as_deref()
+    }
+
+    pub fn get_repositories(&self) -> &BTreeMap<String, ManifestRepository> {
+        &self.repositories
+    }
+
+    /// Write the manifest, as JSON, to `path`, replacing it atomically.
+    pub fn write(&self, path: &Path) -> Result<(), ObsEnvError> {
+        let content = serde_json::to_string_pretty(self).map_err(|error| {
+            ObsEnvError::ERROR(format!("Failed to serialize manifest: {error}"))
+        })?;
+        write_setup_file(path, &(content + "\n"))
+    }
+}
+
+impl ManifestRepository {
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    pub fn get_setup(&self) -> SetupStyle {
+        self.setup
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Manifest;
+    use crate::setup_template::SetupStyle;
+    use std::{env, fs, process};
+
+    #[test]
+    fn test_manifest() {
+        let mut manifest = Manifest::new("/obs-env", "saluser").with_cycle_revision("c0039.004");
+        manifest.add_repository(
+            "ts_wep",
+            "/obs-env/ts_wep",
+            Some("v9.0.0"),
+            SetupStyle::Eups,
+        );
+        manifest.add_repository("cwfs", "/obs-env/cwfs", None, SetupStyle::None);
+
+        let path = env::temp_dir().join(format!("obs_env_manifest_{}.json", process::id()));
+        manifest.write(&path).unwrap();
+        let content: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(content["cycle_revision"], "c0039.004");
+        assert_eq!(content["user"], "saluser");
+        assert!(content["generated_at"].as_str().unwrap().ends_with('Z'));
+        assert_eq!(content["repositories"]["ts_wep"]["version"], "v9.0.0");
+        assert_eq!(content["repositories"]["ts_wep"]["setup"], "eups");
+        assert_eq!(
+            content["repositories"]["cwfs"]["version"],
+            serde_json::Value::Null
+        );
+    }
+}
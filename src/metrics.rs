@@ -0,0 +1,100 @@
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Time spent and data received fetching from a remote.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FetchStats {
+    pub duration: Duration,
+    pub received_bytes: usize,
+}
+
+impl FetchStats {
+    /// Stats of a fetch that started at `start`.
+    pub fn since(start: Instant, received_bytes: usize) -> FetchStats {
+        FetchStats {
+            duration: start.elapsed(),
+            received_bytes,
+        }
+    }
+}
+
+impl std::ops::Add for FetchStats {
+    type Output = FetchStats;
+
+    fn add(self, other: FetchStats) -> FetchStats {
+        FetchStats {
+            duration: self.duration + other.duration,
+            received_bytes: self.received_bytes + other.received_bytes,
+        }
+    }
+}
+
+/// Timing of the git operations on a repository.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RepoMetrics {
+    /// Time spent fetching or cloning, and data received.
+    pub fetch: FetchStats,
+    /// Total time of the operations, including the fetch.
+    pub duration: Duration,
+}
+
+/// Timing of the git operations on each repository of the environment,
+/// accumulated over the operations of an action.
+#[derive(Debug, Default)]
+pub struct GitMetrics {
+    repositories: Mutex<BTreeMap<String, RepoMetrics>>,
+}
+
+impl GitMetrics {
+    /// Record an operation on `repository` that started at `start`,
+    /// including a fetch with `fetch` stats.
+    pub fn record(&self, repository: &str, start: Instant, fetch: FetchStats) {
+        let duration = start.elapsed();
+        match self.repositories.lock() {
+            Ok(mut repositories) => {
+                let metrics = repositories.entry(repository.to_owned()).or_default();
+                metrics.fetch = metrics.fetch + fetch;
+                metrics.duration += duration;
+            }
+            Err(error) => log::warn!("Failed to record metrics of {repository}: {error}"),
+        }
+    }
+
+    /// Metrics of each repository operated on so far.
+    pub fn get_repositories(&self) -> BTreeMap<String, RepoMetrics> {
+        match self.repositories.lock() {
+            Ok(repositories) => repositories.clone(),
+            Err(error) => error.into_inner().clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FetchStats, GitMetrics};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_git_metrics() {
+        let metrics = GitMetrics::default();
+        let fetch = |received_bytes| FetchStats {
+            duration: Duration::from_millis(10),
+            received_bytes,
+        };
+        metrics.record("ts_wep", Instant::now(), fetch(100));
+        metrics.record("ts_wep", Instant::now(), fetch(50));
+        metrics.record("ts_ofc", Instant::now(), FetchStats::default());
+
+        let repositories = metrics.get_repositories();
+        assert_eq!(repositories.len(), 2);
+        assert_eq!(repositories["ts_wep"].fetch.received_bytes, 150);
+        assert_eq!(
+            repositories["ts_wep"].fetch.duration,
+            Duration::from_millis(20)
+        );
+        assert_eq!(repositories["ts_ofc"].fetch, FetchStats::default());
+    }
+}
@@ -0,0 +1,105 @@
+#[cfg(feature = "telemetry")]
+use crate::sasquatch::http::build_client;
+use crate::{identity::get_user, report::Report};
+
+/// Notification about an action, posted to a webhook.
+///
+/// The `text` field makes it directly usable with Slack incoming webhooks,
+/// the other fields are meant for generic HTTP endpoints.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Notification {
+    text: String,
+    action: String,
+    user: String,
+    repositories: Vec<String>,
+    success: bool,
+    error: Option<String>,
+}
+
+impl Notification {
+    /// Build the notification for an action run by `acting_user` (see
+    /// `identity::get_acting_user`), or by the user running the process if
+    /// `None`, from its report.
+    pub fn from_report(
+        action: &str,
+        acting_user: Option<&str>,
+        report: &Report,
+        error: Option<String>,
+    ) -> Notification {
+        let user = acting_user.map_or_else(get_user, str::to_owned);
+        let mut repositories: Vec<String> = report
+            .get_entries()
+            .iter()
+            .map(|entry| entry.get_repository().to_owned())
+            .filter(|repository| !repository.is_empty())
+            .collect();
+        repositories.dedup();
+        let failed: Vec<&str> = report
+            .get_entries()
+            .iter()
+            .filter(|entry| !entry.is_success())
+            .map(|entry| entry.get_repository())
+            .collect();
+        let success = report.is_success();
+
+        let mut text = format!(
+            "{action} on the observing environment by {user} {}",
+            if success { "succeeded" } else { "failed" }
+        );
+        if !repositories.is_empty() {
+            text.push_str(&format!(" ({})", repositories.join(", ")));
+        }
+        if !failed.is_empty() {
+            text.push_str(&format!(". Failed: {}", failed.join(", ")));
+        }
+        if let Some(error) = &error {
+            text.push_str(&format!(". Error: {error}"));
+        }
+
+        Notification {
+            text,
+            action: action.to_owned(),
+            user,
+            repositories,
+            success,
+            error,
+        }
+    }
+
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+
+    /// Post the notification to the webhook, with the timeouts of
+    /// `sasquatch::http::build_client`.
+    #[cfg(feature = "telemetry")]
+    pub fn send(&self, webhook_url: &str) -> Result<(), reqwest::Error> {
+        build_client()?
+            .post(webhook_url)
+            .json(self)
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Notification;
+    use crate::report::Report;
+
+    #[test]
+    fn test_notification_from_report() {
+        let mut report = Report::new("Reset");
+        report.add_success("ts_wep", "reset");
+        report.add_failure("summit_utils", "reset", "Failed to open repository");
+
+        let notification = Notification::from_report("Reset", Some("saluser"), &report, None);
+
+        assert!(!notification.success);
+        assert_eq!(notification.user, "saluser");
+        assert_eq!(notification.repositories, vec!["ts_wep", "summit_utils"]);
+        assert!(notification.get_text().starts_with("Reset"));
+        assert!(notification.get_text().contains("Failed: summit_utils"));
+    }
+}
@@ -0,0 +1,293 @@
+//! Base environment: the versions of the repositories defined in the base
+//! environment source repository (e.g. ts_cycle_build).
+
+use super::{
+    base_env_definition::BaseEnvDefinition, checkout::skipped, versions::Version,
+    ObservingEnvironment,
+};
+use crate::{
+    error::ObsEnvError,
+    git_backend::GitBackend,
+    progress::{NoProgress, Phase, ProgressObserver},
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+impl<G: GitBackend> ObservingEnvironment<G> {
+    /// Reset all repositories to their official version.
+    ///
+    /// On failure, returns the errors paired with the name of the repository
+    /// they refer to, including a `CANCELLED` error for the repositories
+    /// skipped because the cancellation token was cancelled.
+    pub fn reset_base_environment(
+        &self,
+        base_env_branch: &str,
+        run_branch: &str,
+    ) -> Result<(), Vec<(String, ObsEnvError)>> {
+        self.reset_base_environment_with_progress(base_env_branch, run_branch, &NoProgress)
+    }
+
+    /// Reset all repositories to their official version, reporting the
+    /// progress to `progress`.
+    ///
+    /// Each repository is checked out at the run branch, if given and it
+    /// exists in the repository, or reset to its base version otherwise.
+    pub fn reset_base_environment_with_progress(
+        &self,
+        base_env_branch: &str,
+        run_branch: &str,
+        progress: &dyn ProgressObserver,
+    ) -> Result<(), Vec<(String, ObsEnvError)>> {
+        progress.phase_changed(Phase::UpdatingBaseEnv);
+        let obs_env_versions = self
+            .get_base_env_versions(base_env_branch)
+            .map_err(|error| vec![(self.base_env_source_repo.to_owned(), error)])?;
+
+        progress.phase_changed(Phase::Resetting);
+        let reset_result: Vec<(String, ObsEnvError)> = obs_env_versions
+            .into_iter()
+            .filter_map(|(repo, version)| {
+                if self.cancellation.is_cancelled() {
+                    let error = skipped(&repo);
+                    return Some((repo, error));
+                }
+                self.reset_repository_with_progress(&repo, &version, run_branch, progress)
+                    .err()
+                    .map(|err| (repo, err))
+            })
+            .collect();
+
+        if reset_result.is_empty() {
+            Ok(())
+        } else {
+            Err(reset_result)
+        }
+    }
+
+    /// Checkout the run branch of `repo`, if given and it exists in the
+    /// repository, or reset it to its base `version` otherwise.
+    pub(super) fn reset_repository_with_progress(
+        &self,
+        repo: &str,
+        version: &str,
+        run_branch: &str,
+        progress: &dyn ProgressObserver,
+    ) -> Result<(), ObsEnvError> {
+        progress.repo_started(repo);
+        let result = if !run_branch.is_empty()
+            && self
+                .checkout_branch_with_progress(repo, run_branch, progress)
+                .is_ok()
+        {
+            Ok(())
+        } else {
+            self.reset_index_to_version_with_progress(repo, version, progress)
+        };
+        progress.repo_finished(repo, result.as_ref().err());
+        result
+    }
+
+    /// Update the base environment source file, cloning the base environment
+    /// source repository if needed.
+    fn update_base_env_source(&self, base_env_branch: &str) -> Result<(), String> {
+        let base_env_source_path = Path::new(&self.destination).join(&self.base_env_source_repo);
+
+        if !self.git.is_repository(&base_env_source_path) {
+            self.git.clone_repository(
+                &format!("{}/{}", self.base_env_source_org, self.base_env_source_repo),
+                &base_env_source_path,
+                &|_| {},
+            )?;
+        }
+
+        let start = Instant::now();
+        let fetch = self
+            .git
+            .fetch(&base_env_source_path, Some(base_env_branch), &|_| {})?;
+        self.metrics
+            .record(&self.base_env_source_repo, start, fetch);
+
+        self.git
+            .checkout_branch(&base_env_source_path, base_env_branch)
+    }
+
+    /// Get base versions of all the packages.
+    ///
+    /// This method will parse the base_env_def_file (e.g. cycle/cycle.env) to
+    /// get the versions of the base env packages. If a base_env_local_file is
+    /// set, it is parsed instead and the base env source repo is not updated.
+    pub fn get_base_env_versions(
+        &self,
+        base_env_branch: &str,
+    ) -> Result<BTreeMap<String, String>, ObsEnvError> {
+        if self.base_env_local_file.is_none() {
+            self.update_base_env_source(base_env_branch)
+                .map_err(ObsEnvError::ERROR)?;
+        }
+        self.load_base_env_def_file()?
+            .get_versions(self.repositories.keys().map(String::as_str))
+    }
+
+    /// Get the repositories whose current version is not their base version,
+    /// with their base and current versions.
+    pub fn get_drift(
+        &self,
+        base_env_branch: &str,
+    ) -> Result<BTreeMap<String, (String, String)>, ObsEnvError> {
+        let current_versions = self.get_current_env_versions();
+        Ok(self
+            .get_base_env_versions(base_env_branch)?
+            .into_iter()
+            .filter_map(|(repo, version)| {
+                let current_version = match current_versions.get(&repo) {
+                    Some(Ok(current_version)) => current_version.to_owned(),
+                    Some(Err(error)) => error.to_string(),
+                    None => "".to_owned(),
+                };
+                let at_base_version = match (
+                    Version::parse(&version),
+                    Version::from_tag(&current_version),
+                ) {
+                    (Ok(base_version), Some(current_version)) => base_version == current_version,
+                    _ => current_version == version,
+                };
+                (!at_base_version).then_some((repo, (version, current_version)))
+            })
+            .collect())
+    }
+
+    /// Get current cycle/revision.
+    pub fn get_cycle_revision(&self, base_env_branch: &str) -> Result<String, ObsEnvError> {
+        if self.base_env_local_file.is_none() {
+            self.update_base_env_source(base_env_branch)
+                .map_err(|obs_env_err| ObsEnvError::ERROR(obs_env_err.to_string()))?;
+        }
+        self.read_cycle_revision()
+    }
+
+    /// Read the cycle/revision (e.g. "c0039.004") from the CYCLE and rev
+    /// variables of the base_env_def_file, as it is.
+    pub(super) fn read_cycle_revision(&self) -> Result<String, ObsEnvError> {
+        self.load_base_env_def_file()?.get_cycle_revision()
+    }
+
+    /// Read and parse base_env_def_file, logging the problems found.
+    fn load_base_env_def_file(&self) -> Result<BaseEnvDefinition, ObsEnvError> {
+        let base_env_def_path = match &self.base_env_local_file {
+            Some(base_env_local_file) => PathBuf::from(base_env_local_file),
+            None => Path::new(&self.destination)
+                .join(&self.base_env_source_repo)
+                .join(&self.base_env_def_file),
+        };
+        let base_env_def = BaseEnvDefinition::read(&base_env_def_path)?;
+        for problem in base_env_def.get_problems() {
+            log::warn!("{base_env_def_path:?}, {problem}.");
+        }
+        Ok(base_env_def)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::observing_environment::ObservingEnvironment;
+    use once_cell::sync::Lazy;
+    use std::{path::Path, sync::Mutex};
+
+    static REPO_ACCESS: Lazy<Mutex<()>> = Lazy::new(Mutex::default);
+
+    #[test]
+    fn test_update_base_env_source() {
+        let _shared = REPO_ACCESS.lock().unwrap();
+
+        let obs_env = ObservingEnvironment::with_destination(".");
+
+        obs_env.update_base_env_source("main").unwrap();
+
+        assert!(Path::new(&obs_env.destination)
+            .join(obs_env.base_env_source_repo)
+            .exists())
+    }
+
+    #[test]
+    fn test_get_base_env_versions() {
+        let _shared = REPO_ACCESS.lock().unwrap();
+        let obs_env = ObservingEnvironment::with_destination(".");
+
+        let base_env_versions = obs_env.get_base_env_versions("main").unwrap();
+        println!("{:?}", base_env_versions);
+
+        for (repo, _) in obs_env.repositories {
+            println!("{repo}");
+            assert!(base_env_versions.contains_key(&repo));
+        }
+    }
+
+    #[test]
+    fn test_get_base_env_versions_from_local_file() {
+        let local_file =
+            std::env::temp_dir().join(format!("obs_env_test_cycle_{}.env", std::process::id()));
+        std::fs::write(
+            &local_file,
+            "# Comment line\nts_wep=1.2.3\nsummit_utils=w.2024.10\nts_unknown=0.1.0\n",
+        )
+        .unwrap();
+
+        let obs_env = ObservingEnvironment::with_destination("/does/not/exist")
+            .with_base_env_local_file(local_file.to_str().unwrap());
+
+        let base_env_versions = obs_env.get_base_env_versions("main").unwrap();
+
+        std::fs::remove_file(&local_file).unwrap();
+
+        assert_eq!(base_env_versions.len(), 2);
+        assert_eq!(base_env_versions["ts_wep"], "1.2.3");
+        assert_eq!(base_env_versions["summit_utils"], "w.2024.10");
+    }
+
+    #[test]
+    fn test_get_base_env_versions_from_malformed_local_file() {
+        let local_file = std::env::temp_dir().join(format!(
+            "obs_env_test_malformed_cycle_{}.env",
+            std::process::id()
+        ));
+        std::fs::write(&local_file, "ts_wep=1.2.3\nsummit_utils=w.2024.10-1\n").unwrap();
+
+        let obs_env = ObservingEnvironment::with_destination("/does/not/exist")
+            .with_base_env_local_file(local_file.to_str().unwrap());
+
+        let base_env_versions = obs_env.get_base_env_versions("main");
+
+        std::fs::remove_file(&local_file).unwrap();
+
+        let error = base_env_versions.unwrap_err().to_string();
+        assert!(error.contains("line 2: Malformed line"), "{error}");
+    }
+
+    #[test]
+    fn test_get_cycle_revision_from_local_file() {
+        let local_file = std::env::temp_dir().join(format!(
+            "obs_env_test_cycle_revision_{}.env",
+            std::process::id()
+        ));
+        std::fs::write(&local_file, "# Cycle\nCYCLE=c0039\nrev=004\nts_wep=1.2.3\n").unwrap();
+
+        let obs_env = ObservingEnvironment::with_destination("/does/not/exist")
+            .with_base_env_local_file(local_file.to_str().unwrap());
+
+        let cycle_revision = obs_env.get_cycle_revision("main");
+        let manifest = obs_env.get_manifest();
+
+        std::fs::remove_file(&local_file).unwrap();
+
+        assert_eq!(cycle_revision.unwrap(), "c0039.004");
+        assert_eq!(manifest.get_cycle_revision(), Some("c0039.004"));
+        assert_eq!(
+            manifest.get_repositories()["ts_wep"].get_path(),
+            "/does/not/exist/ts_wep"
+        );
+        assert_eq!(manifest.get_repositories()["ts_wep"].get_version(), None);
+    }
+}
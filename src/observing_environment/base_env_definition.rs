@@ -0,0 +1,272 @@
+//! Parser of the base environment definition file (e.g. cycle/cycle.env),
+//! a shell file assigning the versions of the repositories, as well as the
+//! CYCLE and rev variables.
+
+use crate::error::ObsEnvError;
+use regex::Regex;
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+const REPO_VERSION_REGEXP: &str =
+    r#"^(?P<name>[a-zA-Z0-9_]+)=["']?(?P<version>[a-zA-Z0-9._]*)["']?$"#;
+
+/// Problem found parsing a line of the base environment definition.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DefinitionProblem {
+    line: usize,
+    message: String,
+    variable: Option<String>,
+}
+
+impl DefinitionProblem {
+    /// Number of the line with the problem, starting at 1.
+    pub fn get_line(&self) -> usize {
+        self.line
+    }
+
+    /// Variable the line failed to assign, if it could be identified.
+    pub fn get_variable(&self) -> Option<&str> {
+        self.variable.as_deref()
+    }
+
+    pub fn get_message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for DefinitionProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Variables assigned in the base environment definition.
+///
+/// Blank lines and comments are ignored, and an "export" before the
+/// assignment is allowed. As when the file is sourced, a variable assigned
+/// more than once takes the last value. Lines that are not assignments of a
+/// version are skipped and reported as problems, along with the variables
+/// assigned more than once. Reading a variable with a malformed line is an
+/// error, as its value can't be trusted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BaseEnvDefinition {
+    /// File the definition was read from, if any.
+    path: Option<PathBuf>,
+    /// Value of each variable, with the number of the line assigning it.
+    variables: BTreeMap<String, (String, usize)>,
+    problems: Vec<DefinitionProblem>,
+}
+
+impl BaseEnvDefinition {
+    /// Parse the content of a base environment definition file.
+    pub fn parse(content: &str) -> BaseEnvDefinition {
+        // This should never fail because we know REPO_VERSION_REGEXP is valid.
+        let regex = Regex::new(REPO_VERSION_REGEXP).unwrap();
+        let mut definition = BaseEnvDefinition::default();
+        for (index, line) in content.lines().enumerate() {
+            let line_number = index + 1;
+            let assignment = match line.find(" #") {
+                Some(comment_start) => &line[..comment_start],
+                None => line,
+            }
+            .trim();
+            if assignment.is_empty() || assignment.starts_with('#') {
+                continue;
+            }
+            let assignment = assignment
+                .strip_prefix("export ")
+                .map_or(assignment, str::trim_start);
+            let Some(captures) = regex.captures(assignment) else {
+                let variable = assignment
+                    .split(|character: char| character == '=' || character.is_whitespace())
+                    .next()
+                    .filter(|variable| !variable.is_empty());
+                definition.add_problem(
+                    line_number,
+                    format!("Malformed line {line:?}, expected NAME=VERSION"),
+                    variable,
+                );
+                continue;
+            };
+            let (name, version) = (&captures["name"], &captures["version"]);
+            if version.is_empty() {
+                definition.add_problem(line_number, format!("No value for {name}"), Some(name));
+                continue;
+            }
+            if let Some((_, previous_line)) = definition
+                .variables
+                .insert(name.to_owned(), (version.to_owned(), line_number))
+            {
+                definition.add_problem(
+                    line_number,
+                    format!("{name} assigned again, overriding line {previous_line}"),
+                    None,
+                );
+            }
+        }
+        definition
+    }
+
+    /// Read and parse the base environment definition file at `path`.
+    pub fn read(path: &Path) -> Result<BaseEnvDefinition, ObsEnvError> {
+        let content = fs::read_to_string(path)
+            .map_err(|error| ObsEnvError::ERROR(format!("Failed to open {path:?}: {error}")))?;
+        Ok(BaseEnvDefinition {
+            path: Some(path.to_owned()),
+            ..BaseEnvDefinition::parse(&content)
+        })
+    }
+
+    fn add_problem(&mut self, line: usize, message: String, variable: Option<&str>) {
+        self.problems.push(DefinitionProblem {
+            line,
+            message,
+            variable: variable.map(str::to_owned),
+        });
+    }
+
+    /// Fail if a line assigning one of the checked variables is malformed.
+    fn check_variables(&self, is_checked: impl Fn(&str) -> bool) -> Result<(), ObsEnvError> {
+        match self
+            .problems
+            .iter()
+            .find(|problem| problem.get_variable().is_some_and(&is_checked))
+        {
+            Some(problem) => Err(ObsEnvError::ERROR(match &self.path {
+                Some(path) => format!("Invalid base environment definition {path:?}, {problem}."),
+                None => format!("Invalid base environment definition, {problem}."),
+            })),
+            None => Ok(()),
+        }
+    }
+
+    /// Value of the variable `name` (e.g. the version of a repository).
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.variables.get(name).map(|(value, _)| value.as_str())
+    }
+
+    /// Number of the line assigning the variable `name`, starting at 1.
+    pub fn get_line(&self, name: &str) -> Option<usize> {
+        self.variables.get(name).map(|(_, line)| *line)
+    }
+
+    /// Versions of the repositories in `names` defined in the file.
+    ///
+    /// Fails, with the offending line, if the line assigning the version of
+    /// one of the repositories is malformed.
+    pub fn get_versions<'a>(
+        &self,
+        names: impl IntoIterator<Item = &'a str>,
+    ) -> Result<BTreeMap<String, String>, ObsEnvError> {
+        let names: Vec<&str> = names.into_iter().collect();
+        self.check_variables(|variable| names.contains(&variable))?;
+        Ok(names
+            .into_iter()
+            .filter_map(|name| {
+                self.get(name)
+                    .map(|version| (name.to_owned(), version.to_owned()))
+            })
+            .collect())
+    }
+
+    /// Cycle/revision (e.g. "c0039.004") from the CYCLE and rev variables.
+    pub fn get_cycle_revision(&self) -> Result<String, ObsEnvError> {
+        self.check_variables(|variable| variable == "CYCLE" || variable == "rev")?;
+        match (self.get("CYCLE"), self.get("rev")) {
+            (Some(cycle), Some(rev)) => Ok(format!("{cycle}.{rev}")),
+            (Some(cycle), None) => Ok(cycle.to_owned()),
+            (None, _) => Err(ObsEnvError::ERROR(
+                "No CYCLE in the base environment definition".to_owned(),
+            )),
+        }
+    }
+
+    /// Problems found parsing the file, in the order of the lines.
+    pub fn get_problems(&self) -> &[DefinitionProblem] {
+        &self.problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BaseEnvDefinition, REPO_VERSION_REGEXP};
+    use regex::Regex;
+
+    #[test]
+    fn test_repo_version_regexp() {
+        let regexp = Regex::new(REPO_VERSION_REGEXP).unwrap();
+
+        let repo_version = regexp.captures("ts_unit_test=X.Y.ZaN").unwrap();
+
+        assert_eq!(&repo_version["name"], "ts_unit_test");
+        assert_eq!(&repo_version["version"], "X.Y.ZaN");
+    }
+
+    #[test]
+    fn test_parse() {
+        let definition = BaseEnvDefinition::parse(
+            "# Cycle and revision\n\
+            export CYCLE=c0039\n\
+            rev=\"004\"\n\
+            \n\
+            ts_wep=1.2.3 # pinned\n\
+            summit_utils=w.2024.10\n\
+            ts_wep_extra=2.0.0\n",
+        );
+
+        assert!(definition.get_problems().is_empty());
+        assert_eq!(definition.get("ts_wep"), Some("1.2.3"));
+        assert_eq!(definition.get_line("ts_wep"), Some(5));
+        assert_eq!(definition.get("ts_unknown"), None);
+        assert_eq!(definition.get_cycle_revision().unwrap(), "c0039.004");
+        let versions = definition
+            .get_versions(["ts_wep", "summit_utils", "ts_unknown"])
+            .unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions["ts_wep"], "1.2.3");
+        assert_eq!(versions["summit_utils"], "w.2024.10");
+    }
+
+    #[test]
+    fn test_parse_problems() {
+        let definition = BaseEnvDefinition::parse(
+            "ts_wep=1.2.3\n\
+            ts_wep 1.2.4\n\
+            summit_utils=\n\
+            ts_wep=1.2.5\n\
+            ts_config=$VERSION\n",
+        );
+
+        assert_eq!(definition.get("ts_wep"), Some("1.2.5"));
+        assert_eq!(definition.get_line("ts_wep"), Some(4));
+        assert_eq!(definition.get("summit_utils"), None);
+        assert_eq!(definition.get("ts_config"), None);
+        let lines: Vec<usize> = definition
+            .get_problems()
+            .iter()
+            .map(|problem| problem.get_line())
+            .collect();
+        assert_eq!(lines, [2, 3, 4, 5]);
+        assert_eq!(
+            definition.get_problems()[2].to_string(),
+            "line 4: ts_wep assigned again, overriding line 1"
+        );
+        assert!(definition.get_cycle_revision().is_err());
+        assert_eq!(definition.get_problems()[0].get_variable(), Some("ts_wep"));
+        assert_eq!(definition.get_problems()[2].get_variable(), None);
+        assert!(definition.get_versions(["ts_unknown"]).unwrap().is_empty());
+        let error = definition.get_versions(["ts_wep"]).unwrap_err();
+        assert!(error.to_string().contains("line 2: Malformed line"));
+        let error = definition.get_versions(["summit_utils"]).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("line 3: No value for summit_utils"));
+
+        let definition = BaseEnvDefinition::parse("CYCLE=c0039\nrev=0.0-4\n");
+        let error = definition.get_cycle_revision().unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+    }
+}
@@ -0,0 +1,477 @@
+//! Cloning the repositories and checking out their versions.
+
+use super::{
+    versions::{expand_version_to_tag, is_version_range, resolve_version_range},
+    ManagedRepository, ObservingEnvironment,
+};
+use crate::{
+    error::ObsEnvError,
+    git_backend::GitBackend,
+    metrics::FetchStats,
+    progress::{NoProgress, Phase, ProgressObserver},
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+/// Outcome of cloning a repository of the environment.
+#[derive(Debug)]
+pub enum CloneOutcome {
+    /// The repository was cloned at the path.
+    Cloned(PathBuf),
+    /// The repository was already present at the path, it was not cloned.
+    AlreadyPresent(PathBuf),
+    /// Cloning the repository failed, or was skipped because the operation
+    /// was cancelled (with a `CANCELLED` error).
+    Failed(ObsEnvError),
+}
+
+impl CloneOutcome {
+    /// Path of the repository, None if it failed to be cloned.
+    pub fn get_path(&self) -> Option<&Path> {
+        match self {
+            CloneOutcome::Cloned(path) | CloneOutcome::AlreadyPresent(path) => Some(path),
+            CloneOutcome::Failed(_) => None,
+        }
+    }
+
+    /// Error cloning the repository, None if it did not fail.
+    pub fn get_error(&self) -> Option<&ObsEnvError> {
+        match self {
+            CloneOutcome::Failed(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl<G: GitBackend> ObservingEnvironment<G> {
+    /// Clone repositories into the environment path.
+    ///
+    /// Returns the outcome for each repository of the environment, keyed by
+    /// repository name. Repositories skipped because the cancellation token
+    /// was cancelled failed with a `CANCELLED` error.
+    pub fn clone_repositories(&self) -> BTreeMap<String, CloneOutcome> {
+        self.clone_repositories_with_progress(&NoProgress)
+    }
+
+    /// Clone repositories into the environment path, reporting the progress
+    /// to `progress`.
+    pub fn clone_repositories_with_progress(
+        &self,
+        progress: &dyn ProgressObserver,
+    ) -> BTreeMap<String, CloneOutcome> {
+        progress.phase_changed(Phase::Cloning);
+        self.get_repositories()
+            .map(|repository| {
+                (
+                    repository.get_name().to_owned(),
+                    self.clone_repository_with_progress(&repository, progress),
+                )
+            })
+            .collect()
+    }
+
+    /// Clone `repository` into the environment path, unless it is already
+    /// present or the operation was cancelled.
+    pub(super) fn clone_repository_with_progress(
+        &self,
+        repository: &ManagedRepository<'_>,
+        progress: &dyn ProgressObserver,
+    ) -> CloneOutcome {
+        let repo_name = repository.get_name();
+        let path = repository.get_path();
+        if self.git.is_repository(&path) {
+            return CloneOutcome::AlreadyPresent(path);
+        }
+        if self.cancellation.is_cancelled() {
+            return CloneOutcome::Failed(skipped(repo_name));
+        }
+        log::debug!("Cloning: {repo_name}");
+        progress.repo_started(repo_name);
+        let start = Instant::now();
+        let result = self
+            .git
+            .clone_repository(&repository.get_url(), &path, &|received_bytes| {
+                progress.bytes_transferred(repo_name, received_bytes)
+            });
+        self.metrics.record(
+            repo_name,
+            start,
+            result.clone().unwrap_or(FetchStats::since(start, 0)),
+        );
+        let outcome = match result {
+            Ok(_) => CloneOutcome::Cloned(path),
+            Err(error) => CloneOutcome::Failed(ObsEnvError::GIT(format!(
+                "Failed to clone {repo_name}: {error}"
+            ))),
+        };
+        progress.repo_finished(repo_name, outcome.get_error());
+        outcome
+    }
+
+    /// Checkout branch on specified repository.
+    pub fn checkout_branch(&self, repo_name: &str, branch_name: &str) -> Result<(), ObsEnvError> {
+        self.checkout_branch_with_progress(repo_name, branch_name, &NoProgress)
+    }
+
+    pub(super) fn checkout_branch_with_progress(
+        &self,
+        repo_name: &str,
+        branch_name: &str,
+        progress: &dyn ProgressObserver,
+    ) -> Result<(), ObsEnvError> {
+        if self.repositories.contains_key(repo_name) {
+            let start = Instant::now();
+            let path = Path::new(&self.destination).join(repo_name);
+            match self.fetch_and_checkout_branch(&path, branch_name, &|received_bytes| {
+                progress.bytes_transferred(repo_name, received_bytes)
+            }) {
+                Ok(fetch) => {
+                    self.metrics.record(repo_name, start, fetch);
+                    Ok(())
+                }
+                Err(error) => Err(ObsEnvError::GIT(format!(
+                    "Failed to checkout branch {branch_name}: {error}"
+                ))),
+            }
+        } else {
+            Err(ObsEnvError::ERROR(format!(
+                "Repository {repo_name} not in the list of managed repositories."
+            )))
+        }
+    }
+
+    /// Checkout `branch_name` from origin, returning the stats of the fetch.
+    fn fetch_and_checkout_branch(
+        &self,
+        path: &Path,
+        branch_name: &str,
+        progress: &dyn Fn(usize),
+    ) -> Result<FetchStats, String> {
+        let fetch = self.git.fetch(path, Some(branch_name), progress)?;
+        self.git.checkout_branch(path, branch_name)?;
+        Ok(fetch)
+    }
+
+    /// Get current package versions.
+    pub fn get_current_env_versions(&self) -> BTreeMap<String, Result<String, ObsEnvError>> {
+        self.repositories
+            .keys()
+            .map(|repo_name| (repo_name.to_owned(), self.get_current_version(repo_name)))
+            .collect()
+    }
+
+    pub(super) fn get_current_version(&self, repo_name: &str) -> Result<String, ObsEnvError> {
+        self.git
+            .describe(&Path::new(&self.destination).join(repo_name))
+            .map_err(|error| ObsEnvError::GIT(format!("Error describing {repo_name}: {error}")))
+    }
+
+    /// Reset repo index to the provided version.
+    ///
+    /// The version string must have the following format <X>.<Y>.<Z><RT><RN>,
+    /// where:
+    ///     X, is the major version number.
+    ///     Y, is the minor version number.
+    ///     Z, is the patch version number.
+    ///     RT, is the type of the release. This should be empty if this is an
+    ///         official release or:
+    ///         a, alpha release.
+    ///         b, beta release.
+    ///         rc, release candidate.
+    ///     RN, is the major version number. If RT is provided than a release
+    ///         type number can also be provided.
+    ///
+    /// Example valid release strings:
+    ///     0.1.0
+    ///     1.2.3
+    ///     1.0.0a, alpha release with no release number.
+    ///     1.0.0a1, alpha release with release number 1.
+    ///     1.0.0b5, beta release with release number 5.
+    ///     1.0.0rc3, release candidate with release number 3.
+    pub fn reset_index_to_version(&self, repo: &str, version: &str) -> Result<(), ObsEnvError> {
+        self.reset_index_to_version_with_progress(repo, version, &NoProgress)
+    }
+
+    pub(super) fn reset_index_to_version_with_progress(
+        &self,
+        repo: &str,
+        version: &str,
+        progress: &dyn ProgressObserver,
+    ) -> Result<(), ObsEnvError> {
+        log::debug!("Resetting {repo} to {version}");
+        let start = Instant::now();
+        let path = Path::new(&self.destination).join(repo);
+        if !self.git.is_repository(&path) {
+            return Err(ObsEnvError::GIT(format!(
+                "Failed to open repository: {repo}"
+            )));
+        }
+        let tag = expand_version_to_tag(version);
+
+        match self.checkout_tag_or_branch(&path, &tag, version, &|received_bytes| {
+            progress.bytes_transferred(repo, received_bytes)
+        }) {
+            Ok(fetch) => {
+                self.metrics.record(repo, start, fetch);
+                Ok(())
+            }
+            Err(error) => Err(ObsEnvError::GIT(format!(
+                "Could not checkout tag or branch for {repo}@{tag}[{version}]: {error}"
+            ))),
+        }
+    }
+
+    /// Resolve a version specification into a version.
+    ///
+    /// Besides a plain version or branch name, which is returned unchanged,
+    /// the specification can be:
+    ///     latest, the newest release.
+    ///     ~X, the newest release with major version X.
+    ///     ~X.Y, the newest release with major version X and minor version Y.
+    ///
+    /// Only releases (tags in the vX.Y.Z format) are considered, pre-releases
+    /// are ignored. The repository tags are fetched before resolving.
+    pub fn resolve_version(&self, repo: &str, version_spec: &str) -> Result<String, ObsEnvError> {
+        if !is_version_range(version_spec) {
+            return Ok(version_spec.to_owned());
+        }
+        let path = Path::new(&self.destination).join(repo);
+        if !self.git.is_repository(&path) {
+            return Err(ObsEnvError::GIT(format!(
+                "Failed to open repository: {repo}"
+            )));
+        }
+
+        let start = Instant::now();
+        let fetch = self.git.fetch(&path, None, &|_| {}).map_err(|error| {
+            ObsEnvError::GIT(format!("Failed to fetch tags for {repo}: {error}"))
+        })?;
+        self.metrics.record(repo, start, fetch);
+
+        let tags = self.git.get_tags(&path, "v*").map_err(|error| {
+            ObsEnvError::GIT(format!("Failed to list tags for {repo}: {error}"))
+        })?;
+
+        match resolve_version_range(tags.iter().map(|tag| tag.as_str()), version_spec) {
+            Some(version) => {
+                log::info!("Resolved {repo}@{version_spec} to {version}.");
+                Ok(version)
+            }
+            None => Err(ObsEnvError::ERROR(format!(
+                "No release of {repo} matches {version_spec}."
+            ))),
+        }
+    }
+
+    /// Checkout the tag, or the branch if there is no such tag, returning
+    /// the stats of the fetches.
+    fn checkout_tag_or_branch(
+        &self,
+        path: &Path,
+        tag: &str,
+        version: &str,
+        progress: &dyn Fn(usize),
+    ) -> Result<FetchStats, String> {
+        log::trace!("Fetching...");
+        let fetch = self.git.fetch(path, None, progress)?;
+
+        // Try to find the tag first
+        if self.git.get_tags(path, tag)?.iter().any(|name| name == tag) {
+            self.git.checkout_tag(path, tag, version)?;
+            Ok(fetch)
+        } else {
+            // Fallback to try finding a branch
+            log::trace!("Failed to check tag, trying it as a branch: {version}");
+            Ok(fetch + self.fetch_and_checkout_branch(path, version, progress)?)
+        }
+    }
+}
+
+/// Error for a repository skipped because the operation was cancelled.
+pub(super) fn skipped(repo_name: &str) -> ObsEnvError {
+    ObsEnvError::CANCELLED(format!("Skipped {repo_name}, the operation was cancelled."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CloneOutcome;
+    use crate::{
+        cancellation::CancellationToken,
+        error::ObsEnvError,
+        git_backend::{FakeGitBackend, GitBackend},
+        observing_environment::{ObservingEnvironment, RepositoryConfig},
+        progress::{Phase, ProgressObserver},
+    };
+    use std::{collections::BTreeMap, path::Path, sync::Mutex};
+
+    #[test]
+    fn test_fake_git_backend() {
+        let local_file =
+            std::env::temp_dir().join(format!("obs_env_test_fake_git_{}.env", std::process::id()));
+        std::fs::write(&local_file, "ts_wep=1.1.0\ncwfs=develop\n").unwrap();
+        let git = FakeGitBackend::new()
+            .with_remote(
+                "https://github.com/lsst-ts/ts_wep",
+                &["v1.0.0", "v1.1.0", "v2.0.0"],
+                &["develop", "tickets/DM-1"],
+            )
+            .with_remote("https://github.com/lsst-ts/cwfs", &[], &["develop"]);
+        let obs_env = ObservingEnvironment::builder()
+            .with_destination("/obs-env")
+            .with_repositories(BTreeMap::from_iter([
+                (
+                    "ts_wep".to_owned(),
+                    RepositoryConfig::new("https://github.com/lsst-ts"),
+                ),
+                (
+                    "cwfs".to_owned(),
+                    RepositoryConfig::new("https://github.com/lsst-ts"),
+                ),
+            ]))
+            .with_base_env_local_file(local_file.to_str().unwrap())
+            .with_git_backend(git)
+            .build();
+
+        let cloned = obs_env.clone_repositories();
+        assert_eq!(cloned.len(), 2);
+        assert!(matches!(
+            &cloned["ts_wep"],
+            CloneOutcome::Cloned(path) if path == Path::new("/obs-env/ts_wep")
+        ));
+        assert!(obs_env
+            .clone_repositories()
+            .values()
+            .all(|outcome| matches!(outcome, CloneOutcome::AlreadyPresent(_))));
+
+        let reset = obs_env.reset_base_environment("main", "tickets/DM-1");
+        std::fs::remove_file(&local_file).unwrap();
+        reset.unwrap();
+        let versions = obs_env.get_current_env_versions();
+        assert_eq!(versions["ts_wep"].as_ref().unwrap(), "tickets/DM-1");
+        assert_eq!(versions["cwfs"].as_ref().unwrap(), "develop");
+
+        obs_env.reset_index_to_version("ts_wep", "1.1.0").unwrap();
+        assert_eq!(obs_env.get_current_version("ts_wep").unwrap(), "v1.1.0");
+        assert!(obs_env.checkout_branch("ts_wep", "tickets/DM-2").is_err());
+        assert_eq!(obs_env.resolve_version("ts_wep", "~1").unwrap(), "1.1.0");
+        assert_eq!(obs_env.get_metrics().get_repositories().len(), 2);
+    }
+
+    #[derive(Default)]
+    struct ProgressRecorder {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl ProgressObserver for ProgressRecorder {
+        fn phase_changed(&self, phase: Phase) {
+            self.events.lock().unwrap().push(format!("{phase:?}"));
+        }
+        fn repo_started(&self, repo: &str) {
+            self.events.lock().unwrap().push(format!("start {repo}"));
+        }
+        fn repo_finished(&self, repo: &str, error: Option<&ObsEnvError>) {
+            let result = if error.is_some() { "failed" } else { "ok" };
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("finish {repo} {result}"));
+        }
+    }
+
+    #[test]
+    fn test_progress_observer() {
+        let local_file =
+            std::env::temp_dir().join(format!("obs_env_test_progress_{}.env", std::process::id()));
+        std::fs::write(&local_file, "ts_wep=1.1.0\ncwfs=2.0.0\n").unwrap();
+        let git = FakeGitBackend::new()
+            .with_remote("https://github.com/lsst-ts/ts_wep", &["v1.1.0"], &["main"])
+            .with_remote("https://github.com/lsst-ts/cwfs", &["v1.0.0"], &["main"]);
+        let obs_env = ObservingEnvironment::builder()
+            .with_destination("/obs-env")
+            .with_repositories(BTreeMap::from_iter([
+                (
+                    "ts_wep".to_owned(),
+                    RepositoryConfig::new("https://github.com/lsst-ts"),
+                ),
+                (
+                    "cwfs".to_owned(),
+                    RepositoryConfig::new("https://github.com/lsst-ts"),
+                ),
+            ]))
+            .with_base_env_local_file(local_file.to_str().unwrap())
+            .with_git_backend(git)
+            .build();
+
+        let progress = ProgressRecorder::default();
+        obs_env.clone_repositories_with_progress(&progress);
+        let reset = obs_env.reset_base_environment_with_progress("main", "", &progress);
+        std::fs::remove_file(&local_file).unwrap();
+
+        assert_eq!(reset.unwrap_err().len(), 1);
+        assert_eq!(
+            *progress.events.lock().unwrap(),
+            [
+                "Cloning",
+                "start cwfs",
+                "finish cwfs ok",
+                "start ts_wep",
+                "finish ts_wep ok",
+                "UpdatingBaseEnv",
+                "Resetting",
+                "start cwfs",
+                "finish cwfs failed",
+                "start ts_wep",
+                "finish ts_wep ok",
+            ]
+        );
+    }
+
+    struct CancelAfterFirst(CancellationToken);
+
+    impl ProgressObserver for CancelAfterFirst {
+        fn repo_finished(&self, _repo: &str, _error: Option<&ObsEnvError>) {
+            self.0.cancel();
+        }
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let cancellation = CancellationToken::new();
+        let obs_env = ObservingEnvironment::builder()
+            .with_destination("/obs-env")
+            .with_repositories(BTreeMap::from_iter(["cwfs", "ts_wep", "ts_xml"].map(
+                |name| {
+                    (
+                        name.to_owned(),
+                        RepositoryConfig::new("https://github.com/lsst-ts"),
+                    )
+                },
+            )))
+            .with_cancellation_token(cancellation.clone())
+            .with_git_backend(
+                FakeGitBackend::new()
+                    .with_remote("https://github.com/lsst-ts/cwfs", &[], &["main"])
+                    .with_remote("https://github.com/lsst-ts/ts_wep", &[], &["main"]),
+            )
+            .build();
+
+        let cloned =
+            obs_env.clone_repositories_with_progress(&CancelAfterFirst(cancellation.clone()));
+        assert!(matches!(cloned["cwfs"], CloneOutcome::Cloned(_)));
+        assert!(matches!(
+            cloned["ts_wep"],
+            CloneOutcome::Failed(ObsEnvError::CANCELLED(_))
+        ));
+        assert!(matches!(
+            cloned["ts_xml"],
+            CloneOutcome::Failed(ObsEnvError::CANCELLED(_))
+        ));
+        assert!(obs_env.get_cancellation_token().is_cancelled());
+        assert!(!obs_env
+            .get_git_backend()
+            .is_repository(Path::new("/obs-env/ts_wep")));
+    }
+}
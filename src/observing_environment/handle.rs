@@ -0,0 +1,299 @@
+//! Handle sharing the observing environment between threads, e.g. to clone
+//! or reset the repositories in parallel or serve requests.
+
+use super::{checkout::skipped, CloneOutcome, ObservingEnvironment};
+use crate::{
+    error::ObsEnvError,
+    git_backend::{Git2Backend, GitBackend},
+    progress::{NoProgress, Phase, ProgressObserver},
+};
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard},
+};
+
+/// Observing environment with a lock per repository, meant to be shared in
+/// an `Arc`.
+///
+/// Operations on different repositories run concurrently, while operations
+/// on the same repository are serialized. The operations on the whole
+/// environment lock one repository at a time, except for creating the setup
+/// file, which locks all of them, in the order of the repository names, to
+/// describe a consistent environment. The locks are not reentrant, see
+/// `with_repository`.
+pub struct ObsEnvHandle<G: GitBackend = Git2Backend> {
+    obs_env: ObservingEnvironment<G>,
+    /// Lock of each repository, including the base environment source
+    /// repository.
+    repo_locks: BTreeMap<String, Mutex<()>>,
+    /// Lock of the setup files, taken before the repository locks.
+    setup_file_lock: Mutex<()>,
+}
+
+/// Lock `lock`, ignoring the poisoning: the locks guard the repositories
+/// on disk, not data that a panic could leave inconsistent.
+fn lock(lock: &Mutex<()>) -> MutexGuard<'_, ()> {
+    lock.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+impl<G: GitBackend> ObsEnvHandle<G> {
+    pub fn new(obs_env: ObservingEnvironment<G>) -> ObsEnvHandle<G> {
+        let repo_locks = obs_env
+            .repositories
+            .keys()
+            .chain([&obs_env.base_env_source_repo])
+            .map(|repo_name| (repo_name.to_owned(), Mutex::new(())))
+            .collect();
+        ObsEnvHandle {
+            obs_env,
+            repo_locks,
+            setup_file_lock: Mutex::new(()),
+        }
+    }
+
+    /// Observing environment of the handle. Its operations do not take the
+    /// locks of the handle.
+    pub fn get_obs_env(&self) -> &ObservingEnvironment<G> {
+        &self.obs_env
+    }
+
+    /// Lock the repository `repo_name` until the guard is dropped, blocking
+    /// until the operations in progress on it are finished.
+    fn lock_repository(&self, repo_name: &str) -> Result<MutexGuard<'_, ()>, ObsEnvError> {
+        match self.repo_locks.get(repo_name) {
+            Some(repo_lock) => Ok(lock(repo_lock)),
+            None => Err(ObsEnvError::ERROR(format!(
+                "Repository {repo_name} not in the list of managed repositories."
+            ))),
+        }
+    }
+
+    /// Run `f` with the repository `repo_name` locked.
+    ///
+    /// `f` must not use the handle (e.g. through a clone of its `Arc`): the
+    /// operations of the handle on the same repository, or on the whole
+    /// environment, would wait for the lock held while running `f` forever.
+    pub fn with_repository<T>(
+        &self,
+        repo_name: &str,
+        f: impl FnOnce(&ObservingEnvironment<G>) -> Result<T, ObsEnvError>,
+    ) -> Result<T, ObsEnvError> {
+        let _guard = self.lock_repository(repo_name)?;
+        f(&self.obs_env)
+    }
+
+    /// See `ObservingEnvironment::clone_repositories`.
+    pub fn clone_repositories(&self) -> BTreeMap<String, CloneOutcome> {
+        self.clone_repositories_with_progress(&NoProgress)
+    }
+
+    /// See `ObservingEnvironment::clone_repositories_with_progress`.
+    pub fn clone_repositories_with_progress(
+        &self,
+        progress: &dyn ProgressObserver,
+    ) -> BTreeMap<String, CloneOutcome> {
+        progress.phase_changed(Phase::Cloning);
+        self.obs_env
+            .get_repositories()
+            .map(|repository| {
+                let repo_name = repository.get_name();
+                let outcome = match self.lock_repository(repo_name) {
+                    Ok(_guard) => self
+                        .obs_env
+                        .clone_repository_with_progress(&repository, progress),
+                    Err(error) => CloneOutcome::Failed(error),
+                };
+                (repo_name.to_owned(), outcome)
+            })
+            .collect()
+    }
+
+    /// See `ObservingEnvironment::reset_base_environment`.
+    pub fn reset_base_environment(
+        &self,
+        base_env_branch: &str,
+        run_branch: &str,
+    ) -> Result<(), Vec<(String, ObsEnvError)>> {
+        self.reset_base_environment_with_progress(base_env_branch, run_branch, &NoProgress)
+    }
+
+    /// See `ObservingEnvironment::reset_base_environment_with_progress`.
+    pub fn reset_base_environment_with_progress(
+        &self,
+        base_env_branch: &str,
+        run_branch: &str,
+        progress: &dyn ProgressObserver,
+    ) -> Result<(), Vec<(String, ObsEnvError)>> {
+        progress.phase_changed(Phase::UpdatingBaseEnv);
+        let obs_env_versions = self
+            .get_base_env_versions(base_env_branch)
+            .map_err(|error| vec![(self.obs_env.base_env_source_repo.to_owned(), error)])?;
+
+        progress.phase_changed(Phase::Resetting);
+        let reset_result: Vec<(String, ObsEnvError)> = obs_env_versions
+            .into_iter()
+            .filter_map(|(repo, version)| {
+                if self.obs_env.cancellation.is_cancelled() {
+                    let error = skipped(&repo);
+                    return Some((repo, error));
+                }
+                self.with_repository(&repo, |obs_env| {
+                    obs_env.reset_repository_with_progress(&repo, &version, run_branch, progress)
+                })
+                .err()
+                .map(|err| (repo, err))
+            })
+            .collect();
+
+        if reset_result.is_empty() {
+            Ok(())
+        } else {
+            Err(reset_result)
+        }
+    }
+
+    /// See `ObservingEnvironment::checkout_branch`.
+    pub fn checkout_branch(&self, repo_name: &str, branch_name: &str) -> Result<(), ObsEnvError> {
+        self.with_repository(repo_name, |obs_env| {
+            obs_env.checkout_branch(repo_name, branch_name)
+        })
+    }
+
+    /// See `ObservingEnvironment::reset_index_to_version`.
+    pub fn reset_index_to_version(&self, repo: &str, version: &str) -> Result<(), ObsEnvError> {
+        self.with_repository(repo, |obs_env| {
+            obs_env.reset_index_to_version(repo, version)
+        })
+    }
+
+    /// See `ObservingEnvironment::resolve_version`.
+    pub fn resolve_version(&self, repo: &str, version_spec: &str) -> Result<String, ObsEnvError> {
+        self.with_repository(repo, |obs_env| obs_env.resolve_version(repo, version_spec))
+    }
+
+    /// Current version of the repository `repo_name`.
+    pub fn get_current_version(&self, repo_name: &str) -> Result<String, ObsEnvError> {
+        self.with_repository(repo_name, |obs_env| obs_env.get_current_version(repo_name))
+    }
+
+    /// See `ObservingEnvironment::get_current_env_versions`.
+    pub fn get_current_env_versions(&self) -> BTreeMap<String, Result<String, ObsEnvError>> {
+        self.obs_env
+            .repositories
+            .keys()
+            .map(|repo_name| (repo_name.to_owned(), self.get_current_version(repo_name)))
+            .collect()
+    }
+
+    /// See `ObservingEnvironment::get_base_env_versions`.
+    pub fn get_base_env_versions(
+        &self,
+        base_env_branch: &str,
+    ) -> Result<BTreeMap<String, String>, ObsEnvError> {
+        self.with_repository(&self.obs_env.base_env_source_repo, |obs_env| {
+            obs_env.get_base_env_versions(base_env_branch)
+        })
+    }
+
+    /// See `ObservingEnvironment::get_cycle_revision`.
+    pub fn get_cycle_revision(&self, base_env_branch: &str) -> Result<String, ObsEnvError> {
+        self.with_repository(&self.obs_env.base_env_source_repo, |obs_env| {
+            obs_env.get_cycle_revision(base_env_branch)
+        })
+    }
+
+    /// See `ObservingEnvironment::create_setup_file`.
+    pub fn create_setup_file(&self) -> Result<(), ObsEnvError> {
+        let _setup_file_guard = lock(&self.setup_file_lock);
+        let _repo_guards: Vec<MutexGuard<'_, ()>> = self.repo_locks.values().map(lock).collect();
+        self.obs_env.create_setup_file()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ObsEnvHandle;
+    use crate::{
+        git_backend::FakeGitBackend,
+        observing_environment::{ObservingEnvironment, RepositoryConfig},
+    };
+    use std::{
+        collections::BTreeMap,
+        sync::{mpsc, Arc, Barrier, Mutex},
+        thread,
+    };
+
+    #[test]
+    fn test_obs_env_handle() {
+        let git = FakeGitBackend::new()
+            .with_remote(
+                "https://github.com/lsst-ts/ts_wep",
+                &["v1.0.0"],
+                &["develop"],
+            )
+            .with_remote("https://github.com/lsst-ts/cwfs", &["v2.0.0"], &["develop"]);
+        let obs_env = ObservingEnvironment::builder()
+            .with_destination("/obs-env")
+            .with_repositories(BTreeMap::from_iter([
+                (
+                    "ts_wep".to_owned(),
+                    RepositoryConfig::new("https://github.com/lsst-ts"),
+                ),
+                (
+                    "cwfs".to_owned(),
+                    RepositoryConfig::new("https://github.com/lsst-ts"),
+                ),
+            ]))
+            .with_git_backend(git)
+            .build();
+        let handle = Arc::new(ObsEnvHandle::new(obs_env));
+        assert!(handle
+            .clone_repositories()
+            .values()
+            .all(|outcome| outcome.get_error().is_none()));
+        assert!(handle.checkout_branch("ts_unknown", "develop").is_err());
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let (locked_sender, locked) = mpsc::channel();
+        let (release, released) = mpsc::channel();
+        let holder = {
+            let (handle, events) = (Arc::clone(&handle), Arc::clone(&events));
+            thread::spawn(move || {
+                handle.with_repository("ts_wep", |_| {
+                    locked_sender.send(()).unwrap();
+                    released.recv().unwrap();
+                    events.lock().unwrap().push("holder");
+                    Ok(())
+                })
+            })
+        };
+        locked.recv().unwrap();
+        // The other repository is not blocked by the lock on ts_wep.
+        handle.reset_index_to_version("cwfs", "2.0.0").unwrap();
+
+        let waiting = Arc::new(Barrier::new(2));
+        let waiter = {
+            let (handle, events, waiting) = (
+                Arc::clone(&handle),
+                Arc::clone(&events),
+                Arc::clone(&waiting),
+            );
+            thread::spawn(move || {
+                waiting.wait();
+                handle.with_repository("ts_wep", |obs_env| {
+                    events.lock().unwrap().push("waiter");
+                    obs_env.checkout_branch("ts_wep", "develop")
+                })
+            })
+        };
+        waiting.wait();
+        release.send(()).unwrap();
+        holder.join().unwrap().unwrap();
+        waiter.join().unwrap().unwrap();
+        assert_eq!(*events.lock().unwrap(), ["holder", "waiter"]);
+
+        let versions = handle.get_current_env_versions();
+        assert_eq!(versions["ts_wep"].as_ref().unwrap(), "develop");
+        assert_eq!(versions["cwfs"].as_ref().unwrap(), "v2.0.0");
+    }
+}
@@ -0,0 +1,653 @@
+pub mod base_env;
+pub mod base_env_definition;
+pub mod checkout;
+pub mod handle;
+pub mod setup_file;
+pub mod versions;
+
+pub use base_env_definition::BaseEnvDefinition;
+pub use checkout::CloneOutcome;
+pub use handle::ObsEnvHandle;
+pub use versions::{validate_branch_name, validate_version, PreRelease, Version};
+
+use crate::{
+    cancellation::CancellationToken,
+    config::Config,
+    git_backend::{Git2Backend, GitBackend},
+    manage_obs_env::ManageObsEnvCli,
+    metrics::GitMetrics,
+    setup_template::{
+        SetupMode, SetupShell, SetupStyle, SetupTemplate, DEFAULT_SETUP_FILE_BACKUPS,
+    },
+};
+use std::{
+    collections::BTreeMap,
+    fs::create_dir,
+    path::{Path, PathBuf},
+};
+
+/// Configuration of a repository of the observing environment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RepositoryConfig {
+    /// Url of the organization the repository belongs to.
+    pub org: String,
+    /// How the repository is set up by the setup file.
+    pub setup: SetupStyle,
+    /// Name of the EUPS product set up, if different from the repository
+    /// name.
+    pub product: Option<String>,
+    /// Position of the repository in the setup file, which decides its
+    /// precedence in EUPS and PYTHONPATH. Repositories without one are set
+    /// up after the others, in the order of their names.
+    pub setup_order: Option<usize>,
+}
+
+impl RepositoryConfig {
+    pub fn new(org: &str) -> RepositoryConfig {
+        RepositoryConfig {
+            org: org.to_owned(),
+            setup: SetupStyle::None,
+            product: None,
+            setup_order: None,
+        }
+    }
+
+    /// Set up the repository with EUPS in the setup file.
+    pub fn with_setup(self) -> Self {
+        self.with_setup_style(SetupStyle::Eups)
+    }
+
+    /// Set how the repository is set up in the setup file.
+    pub fn with_setup_style(mut self, setup: SetupStyle) -> Self {
+        self.setup = setup;
+        self
+    }
+
+    /// Set the name of the EUPS product set up.
+    pub fn with_product(mut self, product: &str) -> Self {
+        self.product = Some(product.to_owned());
+        self
+    }
+
+    /// Set the position of the repository in the setup file.
+    pub fn with_setup_order(mut self, setup_order: usize) -> Self {
+        self.setup_order = Some(setup_order);
+        self
+    }
+}
+
+/// Repository managed by an `ObservingEnvironment`, see
+/// `ObservingEnvironment::get_repositories`.
+#[derive(Clone, Copy, Debug)]
+pub struct ManagedRepository<'a> {
+    name: &'a str,
+    config: &'a RepositoryConfig,
+    destination: &'a str,
+    setup: SetupStyle,
+}
+
+impl<'a> ManagedRepository<'a> {
+    pub fn get_name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Url the repository is cloned from.
+    pub fn get_url(&self) -> String {
+        format!("{}/{}", self.config.org.trim_end_matches('/'), self.name)
+    }
+
+    /// Path of the clone of the repository in the environment.
+    pub fn get_path(&self) -> PathBuf {
+        Path::new(self.destination).join(self.name)
+    }
+
+    /// How the repository is set up by the setup file, with the setup mode
+    /// of the environment applied.
+    pub fn get_setup(&self) -> SetupStyle {
+        self.setup
+    }
+
+    /// Name of the EUPS product set up.
+    pub fn get_product(&self) -> &'a str {
+        self.config.product.as_deref().unwrap_or(self.name)
+    }
+
+    pub fn get_config(&self) -> &'a RepositoryConfig {
+        self.config
+    }
+}
+
+pub struct ObservingEnvironment<G: GitBackend = Git2Backend> {
+    /// List of repositories that belong to the observing environment.
+    repositories: BTreeMap<String, RepositoryConfig>,
+    /// Organzation url for the base env sourve repository
+    base_env_source_org: String,
+    /// Repository with the base environment version definitions
+    base_env_source_repo: String,
+    /// File path in the base environment version definitions repository
+    /// with the version information
+    base_env_def_file: String,
+    /// Optional local file with the version information, used instead of
+    /// the base environment version definitions repository.
+    base_env_local_file: Option<String>,
+    /// Location where the repositories should be placed in the host.
+    destination: String,
+    /// User the environment is managed for, if different from the user
+    /// running the process.
+    acting_user: Option<String>,
+    /// Minijinja templates used to generate the setup files instead of the
+    /// default ones.
+    setup_templates: Vec<SetupTemplate>,
+    /// Shells to generate setup files for.
+    setup_shells: Vec<SetupShell>,
+    /// How the setup file sets up the repositories.
+    setup_mode: SetupMode,
+    /// Number of backups of the previous setup files to keep.
+    setup_file_backups: usize,
+    /// Directory of the setup files and the manifest, if not the
+    /// destination.
+    setup_file_dir: Option<String>,
+    /// Name of the setup files, without extension, if not the default one.
+    setup_file_name: Option<String>,
+    /// Timing of the git operations on the repositories.
+    metrics: GitMetrics,
+    /// Token checked between repositories to stop the clone and reset.
+    cancellation: CancellationToken,
+    /// Backend running the git operations.
+    git: G,
+}
+
+impl Default for ObservingEnvironment {
+    fn default() -> ObservingEnvironment {
+        ObservingEnvironment {
+            repositories: BTreeMap::from_iter([
+                (
+                    "atmospec".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst/"),
+                ),
+                (
+                    "cwfs".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst-ts/")
+                        .with_setup()
+                        .with_setup_order(9),
+                ),
+                (
+                    "Spectractor".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst-dm/"),
+                ),
+                (
+                    "summit_extras".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst-sitcom/")
+                        .with_setup()
+                        .with_setup_order(1),
+                ),
+                (
+                    "summit_utils".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst-sitcom/")
+                        .with_setup()
+                        .with_setup_order(0),
+                ),
+                (
+                    "ts_config_mttcs".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst-ts/"),
+                ),
+                (
+                    "ts_config_attcs".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst-ts/"),
+                ),
+                (
+                    "ts_config_ocs".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst-ts/"),
+                ),
+                (
+                    "ts_config_scheduler".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst-ts/"),
+                ),
+                (
+                    "ts_auxtel_standardscripts".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst-ts/")
+                        .with_setup()
+                        .with_setup_order(2),
+                ),
+                (
+                    "ts_maintel_standardscripts".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst-ts/")
+                        .with_setup()
+                        .with_setup_order(3),
+                ),
+                (
+                    "ts_standardscripts".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst-ts/")
+                        .with_setup()
+                        .with_setup_order(4),
+                ),
+                (
+                    "ts_externalscripts".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst-ts/")
+                        .with_setup()
+                        .with_setup_order(5),
+                ),
+                (
+                    "ts_observatory_control".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst-ts/")
+                        .with_setup()
+                        .with_setup_order(6),
+                ),
+                (
+                    "ts_observing_utilities".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst-ts/")
+                        .with_setup()
+                        .with_setup_order(7),
+                ),
+                (
+                    "ts_wep".to_owned(),
+                    RepositoryConfig::new(r"https://github.com/lsst-ts/")
+                        .with_setup()
+                        .with_setup_order(8),
+                ),
+            ]),
+            base_env_source_org: r"https://github.com/lsst-ts/".to_owned(),
+            base_env_source_repo: "ts_cycle_build".to_owned(),
+            base_env_def_file: "cycle/cycle.env".to_owned(),
+            base_env_local_file: None,
+            acting_user: None,
+            setup_templates: Vec::new(),
+            setup_shells: vec![SetupShell::Bash],
+            setup_mode: SetupMode::default(),
+            setup_file_backups: DEFAULT_SETUP_FILE_BACKUPS,
+            setup_file_dir: None,
+            setup_file_name: None,
+            metrics: GitMetrics::default(),
+            cancellation: CancellationToken::default(),
+            git: Git2Backend,
+            destination: "/obs-env".to_owned(),
+        }
+    }
+}
+
+/// Builder of an `ObservingEnvironment`.
+///
+/// Fields that are not set keep the values of the default environment.
+pub struct ObservingEnvironmentBuilder<G: GitBackend = Git2Backend> {
+    obs_env: ObservingEnvironment<G>,
+}
+
+impl Default for ObservingEnvironmentBuilder {
+    fn default() -> Self {
+        ObservingEnvironmentBuilder {
+            obs_env: ObservingEnvironment::default(),
+        }
+    }
+}
+
+impl ObservingEnvironmentBuilder {
+    /// Builder configured from the command line options of manage_obs_env.
+    ///
+    /// The acting user is not set, as finding it may require querying
+    /// Gafaelfawr; see `identity::get_acting_user`.
+    pub fn from_config<T: ManageObsEnvCli>(config: &T) -> Self {
+        let builder = ObservingEnvironment::builder()
+            .with_destination(config.get_env_path())
+            .with_base_env_def_file(config.get_base_env_def_file())
+            .with_setup_shells(config.get_setup_shells())
+            .with_setup_templates(config.get_setup_templates())
+            .with_setup_mode(config.get_setup_mode())
+            .with_setup_file_backups(config.get_setup_file_backups());
+        let builder = match config.get_base_env_local_file() {
+            Some(base_env_local_file) => builder.with_base_env_local_file(base_env_local_file),
+            None => builder,
+        };
+        let builder = match config.get_setup_file_dir() {
+            Some(setup_file_dir) => builder.with_setup_file_dir(setup_file_dir),
+            None => builder,
+        };
+        match config.get_setup_file_name() {
+            Some(setup_file_name) => builder.with_setup_file_name(setup_file_name),
+            None => builder,
+        }
+    }
+}
+
+impl<G: GitBackend> ObservingEnvironmentBuilder<G> {
+    /// Run the git operations with `git` instead of libgit2.
+    pub fn with_git_backend<H: GitBackend>(self, git: H) -> ObservingEnvironmentBuilder<H> {
+        ObservingEnvironmentBuilder {
+            obs_env: self.obs_env.with_git_backend(git),
+        }
+    }
+
+    /// Set the location where the repositories are placed in the host.
+    pub fn with_destination(mut self, destination: &str) -> Self {
+        self.obs_env.destination = destination.to_owned();
+        self
+    }
+    /// Replace the repositories of the environment.
+    pub fn with_repositories(mut self, repositories: BTreeMap<String, RepositoryConfig>) -> Self {
+        self.obs_env.repositories = repositories;
+        self
+    }
+    /// Add a repository to the environment, or replace the configuration of
+    /// one.
+    pub fn with_repository(mut self, name: &str, config: RepositoryConfig) -> Self {
+        self.obs_env = self.obs_env.with_repository(name, config);
+        self
+    }
+    /// Set the url of the organization of the base environment version
+    /// definitions repository.
+    pub fn with_base_env_source_org(mut self, base_env_source_org: &str) -> Self {
+        self.obs_env.base_env_source_org = base_env_source_org.to_owned();
+        self
+    }
+    /// Set the repository with the base environment version definitions.
+    pub fn with_base_env_source_repo(mut self, base_env_source_repo: &str) -> Self {
+        self.obs_env.base_env_source_repo = base_env_source_repo.to_owned();
+        self
+    }
+    pub fn with_base_env_def_file(mut self, base_env_def_file: &str) -> Self {
+        self.obs_env = self.obs_env.with_base_env_def_file(base_env_def_file);
+        self
+    }
+    pub fn with_base_env_local_file(mut self, base_env_local_file: &str) -> Self {
+        self.obs_env = self.obs_env.with_base_env_local_file(base_env_local_file);
+        self
+    }
+    pub fn with_acting_user(mut self, acting_user: &str) -> Self {
+        self.obs_env = self.obs_env.with_acting_user(acting_user);
+        self
+    }
+    pub fn with_setup_templates(mut self, setup_templates: &[SetupTemplate]) -> Self {
+        self.obs_env = self.obs_env.with_setup_templates(setup_templates);
+        self
+    }
+    pub fn with_setup_shells(mut self, setup_shells: &[SetupShell]) -> Self {
+        self.obs_env = self.obs_env.with_setup_shells(setup_shells);
+        self
+    }
+    pub fn with_setup_mode(mut self, setup_mode: SetupMode) -> Self {
+        self.obs_env = self.obs_env.with_setup_mode(setup_mode);
+        self
+    }
+    pub fn with_setup_file_backups(mut self, setup_file_backups: usize) -> Self {
+        self.obs_env = self.obs_env.with_setup_file_backups(setup_file_backups);
+        self
+    }
+    pub fn with_setup_file_dir(mut self, setup_file_dir: &str) -> Self {
+        self.obs_env = self.obs_env.with_setup_file_dir(setup_file_dir);
+        self
+    }
+    pub fn with_setup_file_name(mut self, setup_file_name: &str) -> Self {
+        self.obs_env = self.obs_env.with_setup_file_name(setup_file_name);
+        self
+    }
+    pub fn with_cancellation_token(mut self, cancellation: CancellationToken) -> Self {
+        self.obs_env = self.obs_env.with_cancellation_token(cancellation);
+        self
+    }
+    /// Apply the settings of the environment set in `config`.
+    pub fn with_config(mut self, config: &Config) -> Self {
+        if let Some(env_path) = &config.env_path {
+            self = self.with_destination(env_path);
+        }
+        if let Some(base_env_def_file) = &config.base_env_def_file {
+            self = self.with_base_env_def_file(base_env_def_file);
+        }
+        if let Some(base_env_local_file) = &config.base_env_local_file {
+            self = self.with_base_env_local_file(base_env_local_file);
+        }
+        if let Some(setup_templates) = &config.setup_templates {
+            self = self.with_setup_templates(setup_templates);
+        }
+        if let Some(setup_shells) = &config.setup_shells {
+            self = self.with_setup_shells(setup_shells);
+        }
+        if let Some(setup_mode) = config.setup_mode {
+            self = self.with_setup_mode(setup_mode);
+        }
+        if let Some(setup_file_backups) = config.setup_file_backups {
+            self = self.with_setup_file_backups(setup_file_backups);
+        }
+        if let Some(setup_file_dir) = &config.setup_file_dir {
+            self = self.with_setup_file_dir(setup_file_dir);
+        }
+        if let Some(setup_file_name) = &config.setup_file_name {
+            self = self.with_setup_file_name(setup_file_name);
+        }
+        self
+    }
+
+    pub fn build(self) -> ObservingEnvironment<G> {
+        self.obs_env
+    }
+}
+
+impl ObservingEnvironment {
+    pub fn builder() -> ObservingEnvironmentBuilder {
+        ObservingEnvironmentBuilder::default()
+    }
+
+    pub fn with_destination(dest: &str) -> ObservingEnvironment {
+        ObservingEnvironment {
+            destination: dest.to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
+impl<G: GitBackend> ObservingEnvironment<G> {
+    /// Run the git operations with `git` instead of libgit2, e.g. with a
+    /// `FakeGitBackend` in tests.
+    pub fn with_git_backend<H: GitBackend>(self, git: H) -> ObservingEnvironment<H> {
+        ObservingEnvironment {
+            repositories: self.repositories,
+            base_env_source_org: self.base_env_source_org,
+            base_env_source_repo: self.base_env_source_repo,
+            base_env_def_file: self.base_env_def_file,
+            base_env_local_file: self.base_env_local_file,
+            destination: self.destination,
+            acting_user: self.acting_user,
+            setup_templates: self.setup_templates,
+            setup_shells: self.setup_shells,
+            setup_mode: self.setup_mode,
+            setup_file_backups: self.setup_file_backups,
+            setup_file_dir: self.setup_file_dir,
+            setup_file_name: self.setup_file_name,
+            metrics: self.metrics,
+            cancellation: self.cancellation,
+            git,
+        }
+    }
+
+    pub fn get_git_backend(&self) -> &G {
+        &self.git
+    }
+
+    /// Set the file path, in the base environment version definitions
+    /// repository, with the version information.
+    pub fn with_base_env_def_file(mut self, base_env_def_file: &str) -> Self {
+        self.base_env_def_file = base_env_def_file.to_owned();
+        self
+    }
+
+    /// Read the version information from a local file instead of the base
+    /// environment version definitions repository.
+    pub fn with_base_env_local_file(mut self, base_env_local_file: &str) -> Self {
+        self.base_env_local_file = Some(base_env_local_file.to_owned());
+        self
+    }
+
+    /// Set the user the environment is managed for, recorded in the setup
+    /// file.
+    pub fn with_acting_user(mut self, acting_user: &str) -> Self {
+        self.acting_user = Some(acting_user.to_owned());
+        self
+    }
+
+    /// Generate the setup files from `setup_templates` instead of the
+    /// default templates. When several templates are given for a shell, the
+    /// last one is used.
+    ///
+    /// See `SetupContext` for the variables available to the templates.
+    pub fn with_setup_templates(mut self, setup_templates: &[SetupTemplate]) -> Self {
+        self.setup_templates = setup_templates.to_vec();
+        self
+    }
+
+    /// Generate setup files for `setup_shells` instead of only bash.
+    pub fn with_setup_shells(mut self, setup_shells: &[SetupShell]) -> Self {
+        self.setup_shells = setup_shells.to_vec();
+        self
+    }
+
+    /// Set how the setup file sets up the repositories, e.g. only with
+    /// PYTHONPATH in environments without EUPS.
+    pub fn with_setup_mode(mut self, setup_mode: SetupMode) -> Self {
+        self.setup_mode = setup_mode;
+        self
+    }
+
+    /// Keep `setup_file_backups` backups of the previous setup files (0 to
+    /// keep none).
+    pub fn with_setup_file_backups(mut self, setup_file_backups: usize) -> Self {
+        self.setup_file_backups = setup_file_backups;
+        self
+    }
+
+    /// Write the setup files and the manifest to `setup_file_dir` instead of
+    /// the destination, e.g. a directory bind-mounted into nublado.
+    pub fn with_setup_file_dir(mut self, setup_file_dir: &str) -> Self {
+        self.setup_file_dir = Some(setup_file_dir.to_owned());
+        self
+    }
+
+    /// Name the setup files `setup_file_name`, followed by the extension of
+    /// each shell, instead of "auto_env_setup".
+    pub fn with_setup_file_name(mut self, setup_file_name: &str) -> Self {
+        self.setup_file_name = Some(setup_file_name.to_owned());
+        self
+    }
+
+    /// Stop cloning and resetting the repositories when `cancellation` is
+    /// cancelled. The repository in progress is completed, and the
+    /// remaining ones are reported as skipped.
+    pub fn with_cancellation_token(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    pub fn get_cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// Add a repository to the environment, or replace the configuration of
+    /// one.
+    pub fn with_repository(mut self, name: &str, config: RepositoryConfig) -> Self {
+        self.repositories.insert(name.to_owned(), config);
+        self
+    }
+
+    /// Configuration of the repository `name`, None if it is not managed.
+    pub fn get_repository_config(&self, name: &str) -> Option<&RepositoryConfig> {
+        self.repositories.get(name)
+    }
+
+    /// Repositories managed by the environment, sorted by name.
+    pub fn get_repositories(&self) -> impl Iterator<Item = ManagedRepository<'_>> {
+        self.repositories
+            .iter()
+            .map(|(name, config)| self.get_managed_repository(name, config))
+    }
+
+    /// The repository `name`, None if it is not managed.
+    pub fn get_repository(&self, name: &str) -> Option<ManagedRepository<'_>> {
+        self.repositories
+            .get_key_value(name)
+            .map(|(name, config)| self.get_managed_repository(name, config))
+    }
+
+    fn get_managed_repository<'a>(
+        &'a self,
+        name: &'a str,
+        config: &'a RepositoryConfig,
+    ) -> ManagedRepository<'a> {
+        ManagedRepository {
+            name,
+            config,
+            destination: &self.destination,
+            setup: self.setup_mode.get_style(config.setup),
+        }
+    }
+
+    /// Timing of the git operations done so far on the repositories.
+    pub fn get_metrics(&self) -> &GitMetrics {
+        &self.metrics
+    }
+
+    /// Location of the environment.
+    pub fn get_destination(&self) -> &str {
+        &self.destination
+    }
+
+    pub fn summarize(&self) -> String {
+        format!(
+            "Obs. Env. Path: {}.\nNumber of repositories: {}",
+            self.destination,
+            self.repositories.len()
+        )
+    }
+    /// Check if destination directory exists.
+    pub fn create_path(&self) -> Result<(), std::io::Error> {
+        let destination = Path::new(&self.destination);
+
+        if !destination.exists() {
+            create_dir(&self.destination)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ObservingEnvironment, RepositoryConfig};
+    use crate::setup_template::{SetupShell, SetupStyle};
+    use std::{collections::BTreeMap, path::Path};
+
+    #[test]
+    fn test_builder() {
+        let obs_env = ObservingEnvironment::builder()
+            .with_destination("/obs-env")
+            .with_repositories(BTreeMap::from_iter([(
+                "ts_wep".to_owned(),
+                RepositoryConfig::new("https://github.com/lsst-ts/").with_setup(),
+            )]))
+            .with_repository("cwfs", RepositoryConfig::new("https://github.com/lsst-ts/"))
+            .with_base_env_source_org("https://github.com/lsst-sitcom/")
+            .with_base_env_source_repo("cycle_build")
+            .with_setup_file_name("obs_env")
+            .build();
+
+        assert_eq!(obs_env.get_destination(), "/obs-env");
+        assert_eq!(
+            obs_env.summarize(),
+            "Obs. Env. Path: /obs-env.\nNumber of repositories: 2"
+        );
+        assert!(obs_env.get_repository_config("Spectractor").is_none());
+        assert_eq!(
+            obs_env
+                .get_repositories()
+                .map(|repository| repository.get_name())
+                .collect::<Vec<_>>(),
+            ["cwfs", "ts_wep"]
+        );
+        let ts_wep = obs_env.get_repository("ts_wep").unwrap();
+        assert_eq!(ts_wep.get_url(), "https://github.com/lsst-ts/ts_wep");
+        assert_eq!(ts_wep.get_path(), Path::new("/obs-env/ts_wep"));
+        assert_eq!(ts_wep.get_setup(), SetupStyle::Eups);
+        assert_eq!(ts_wep.get_product(), "ts_wep");
+        assert!(obs_env.get_repository("Spectractor").is_none());
+        assert_eq!(
+            obs_env.get_setup_file_path(SetupShell::Bash),
+            Path::new("/obs-env/obs_env.sh")
+        );
+    }
+}
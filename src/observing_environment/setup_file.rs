@@ -0,0 +1,478 @@
+//! Setup files and manifest of the environment.
+
+use super::{ObservingEnvironment, RepositoryConfig};
+use crate::{
+    error::ObsEnvError,
+    git_backend::GitBackend,
+    identity::get_user,
+    manifest::{Manifest, MANIFEST_FILE_NAME},
+    setup_template::{
+        backup_setup_file, parse_setup_file, render_setup_file, write_setup_file, SetupContext,
+        SetupRepository, SetupShell, SetupStyle, SetupTemplate,
+    },
+};
+use chrono::Local;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+impl<G: GitBackend> ObservingEnvironment<G> {
+    /// Generate the setup file of each shell, and the manifest of the
+    /// environment.
+    ///
+    /// All the setup files are rendered before writing any of them, so an
+    /// invalid template leaves the previous setup files in place.
+    pub fn create_setup_file(&self) -> Result<(), ObsEnvError> {
+        let context = self.get_setup_context();
+        let mut contents = Vec::new();
+        for shell in &self.setup_shells {
+            let template = match self.get_setup_template(*shell)? {
+                Some(setup_template) => Some(read_to_string(setup_template).map_err(|error| {
+                    ObsEnvError::ERROR(format!(
+                        "Failed to read setup file template {setup_template}: {error}"
+                    ))
+                })?),
+                None => None,
+            };
+            contents.push((
+                *shell,
+                render_setup_file(template.as_deref(), *shell, &context)?,
+            ));
+        }
+        for (shell, content) in contents {
+            let path = self.get_setup_file_path(shell);
+            let destination = path.as_path();
+
+            // An unchanged setup file is not backed up again.
+            if read_to_string(destination).is_ok_and(|previous| previous != content) {
+                if let Some(backup) = backup_setup_file(destination, self.setup_file_backups)? {
+                    log::info!("Previous setup file saved to {backup:?}.");
+                }
+            }
+            write_setup_file(destination, &content)?;
+        }
+        self.get_manifest()
+            .write(&self.get_setup_file_dir().join(MANIFEST_FILE_NAME))
+    }
+
+    /// Path of the template of the setup file of `shell`, None for the
+    /// default template.
+    ///
+    /// Fails if the only template is given without a shell, while setup
+    /// files are generated for several shells.
+    fn get_setup_template(&self, shell: SetupShell) -> Result<Option<&str>, ObsEnvError> {
+        let find_template = |template_shell: Option<SetupShell>| {
+            self.setup_templates
+                .iter()
+                .rev()
+                .find(|setup_template| setup_template.get_shell() == template_shell)
+        };
+        match find_template(Some(shell)).or_else(|| find_template(None)) {
+            Some(setup_template)
+                if setup_template.get_shell().is_none() && self.setup_shells.len() > 1 =>
+            {
+                Err(ObsEnvError::ERROR(format!(
+                    "Setup file template {setup_template} has no shell, while setup files are \
+                    generated for {} shells. Give the shell of each template, e.g. \
+                    csh={setup_template}.",
+                    self.setup_shells.len()
+                )))
+            }
+            setup_template => Ok(setup_template.map(SetupTemplate::get_path)),
+        }
+    }
+
+    /// Directory of the setup files and the manifest.
+    pub fn get_setup_file_dir(&self) -> &Path {
+        Path::new(self.setup_file_dir.as_deref().unwrap_or(&self.destination))
+    }
+
+    /// Path of the setup file of `shell`.
+    pub fn get_setup_file_path(&self, shell: SetupShell) -> PathBuf {
+        match &self.setup_file_name {
+            Some(setup_file_name) => self
+                .get_setup_file_dir()
+                .join(format!("{setup_file_name}.{}", shell.get_extension())),
+            None => self.get_setup_file_dir().join(shell.get_file_name()),
+        }
+    }
+
+    /// Manifest of the environment, with the current versions of all the
+    /// repositories.
+    pub fn get_manifest(&self) -> Manifest {
+        let manifest = Manifest::new(&self.destination, &self.get_generating_user());
+        let mut manifest = match self.read_cycle_revision() {
+            Ok(cycle_revision) => manifest.with_cycle_revision(&cycle_revision),
+            Err(error) => {
+                log::debug!("Cycle revision not in the manifest: {error}");
+                manifest
+            }
+        };
+        let versions = self.get_current_env_versions();
+        for repository in self.get_repositories() {
+            manifest.add_repository(
+                repository.get_name(),
+                &repository.get_path().to_string_lossy(),
+                versions
+                    .get(repository.get_name())
+                    .and_then(|version| version.as_ref().ok())
+                    .map(|version| version.as_str()),
+                repository.get_setup(),
+            );
+        }
+        manifest
+    }
+
+    /// Check the setup file of each shell against the environment.
+    ///
+    /// Returns the problems found, paired with the repository they refer
+    /// to: paths in the setup file that do not exist, entries for
+    /// repositories that are not set up by the environment, and
+    /// repositories that should be set up but are not in the file. With
+    /// several shells, the problems start with the name of their file.
+    pub fn validate_setup_file(&self) -> Result<Vec<(String, String)>, ObsEnvError> {
+        let mut problems = Vec::new();
+        for shell in &self.setup_shells {
+            let path = self.get_setup_file_path(*shell);
+            let shell_problems = self.validate_shell_setup_file(*shell, &path)?;
+            if self.setup_shells.len() > 1 {
+                let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                problems.extend(
+                    shell_problems
+                        .into_iter()
+                        .map(|(name, problem)| (name, format!("{file_name}: {problem}"))),
+                );
+            } else {
+                problems.extend(shell_problems);
+            }
+        }
+        Ok(problems)
+    }
+
+    /// Check the setup file of `shell`, at `path`, against the environment.
+    fn validate_shell_setup_file(
+        &self,
+        shell: SetupShell,
+        path: &Path,
+    ) -> Result<Vec<(String, String)>, ObsEnvError> {
+        let content = read_to_string(path).map_err(|error| {
+            ObsEnvError::ERROR(format!("Failed to read setup file {path:?}: {error}"))
+        })?;
+        let mut problems = Vec::new();
+        let mut set_up = BTreeSet::new();
+        let mut paths = BTreeSet::new();
+        for entry in parse_setup_file(shell, &content) {
+            // The csh setup files set PYTHONPATH on two lines, depending on
+            // whether it is already set.
+            if !paths.insert(entry.path.to_owned()) {
+                continue;
+            }
+            let entry_path = Path::new(&entry.path);
+            let name = entry_path
+                .strip_prefix(&self.destination)
+                .ok()
+                .and_then(|relative_path| relative_path.iter().next())
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| entry.path.to_owned());
+            if !entry_path.exists() {
+                problems.push((
+                    name.to_owned(),
+                    format!("Line {}: {} does not exist", entry.line, entry.path),
+                ));
+            }
+            match self.repositories.get(&name) {
+                Some(config) if self.setup_mode.get_style(config.setup) != SetupStyle::None => {
+                    set_up.insert(name);
+                }
+                _ => problems.push((
+                    name,
+                    format!(
+                        "Line {}: stale entry, {} is not set up by the environment",
+                        entry.line, entry.path
+                    ),
+                )),
+            }
+        }
+        for (name, config) in &self.repositories {
+            if self.setup_mode.get_style(config.setup) != SetupStyle::None && !set_up.contains(name)
+            {
+                problems.push((name.to_owned(), "Missing from the setup file".to_owned()));
+            }
+        }
+        Ok(problems)
+    }
+
+    /// User generating the setup file, and the user it is generated for if
+    /// different.
+    fn get_generating_user(&self) -> String {
+        match &self.acting_user {
+            Some(acting_user) => format!("{} on behalf of {acting_user}", get_user()),
+            None => get_user(),
+        }
+    }
+
+    /// Variables of the setup file template.
+    fn get_setup_context(&self) -> SetupContext {
+        let versions: BTreeMap<String, String> = self
+            .get_current_env_versions()
+            .into_iter()
+            .map(|(name, version)| (name, version.unwrap_or_default()))
+            .collect();
+        let mut repositories: Vec<(&String, &RepositoryConfig)> = self
+            .repositories
+            .iter()
+            .filter(|(_, config)| config.setup != SetupStyle::None)
+            .collect();
+        repositories.sort_by_key(|(_, config)| config.setup_order.unwrap_or(usize::MAX));
+        let repositories = repositories
+            .into_iter()
+            .map(|(name, config)| SetupRepository {
+                name: name.to_owned(),
+                style: self.setup_mode.get_style(config.setup),
+                product: config.product.clone().unwrap_or_else(|| name.to_owned()),
+                path: format!("{}/{name}", self.destination),
+                version: versions.get(name).cloned().unwrap_or_default(),
+            })
+            .collect();
+        SetupContext {
+            destination: self.destination.to_owned(),
+            timestamp: Local::now().naive_utc().to_string(),
+            user: self.get_generating_user(),
+            repositories,
+            versions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        observing_environment::{ObservingEnvironment, RepositoryConfig},
+        setup_template::{SetupMode, SetupShell, SetupStyle, SetupTemplate},
+    };
+    use std::{fs, path::Path};
+
+    #[test]
+    fn test_setup_repositories() {
+        let obs_env = ObservingEnvironment::with_destination("/obs-env")
+            .with_repository(
+                "ts_wep",
+                RepositoryConfig::new("https://github.com/lsst-ts/")
+                    .with_setup()
+                    .with_product("wep"),
+            )
+            .with_repository("cwfs", RepositoryConfig::new("https://github.com/lsst-ts/"));
+
+        let context = obs_env.get_setup_context();
+        let names: Vec<&str> = context
+            .repositories
+            .iter()
+            .map(|repository| repository.name.as_str())
+            .collect();
+
+        assert!(names.contains(&"ts_wep"));
+        assert!(names.contains(&"summit_utils"));
+        assert!(!names.contains(&"cwfs"));
+        assert!(!names.contains(&"Spectractor"));
+        let ts_wep =
+            &context.repositories[names.iter().position(|name| *name == "ts_wep").unwrap()];
+        assert_eq!(ts_wep.product, "wep");
+        assert_eq!(ts_wep.path, "/obs-env/ts_wep");
+        assert_eq!(ts_wep.style, SetupStyle::Eups);
+
+        let context = obs_env
+            .with_setup_mode(SetupMode::Pythonpath)
+            .get_setup_context();
+        assert!(context
+            .repositories
+            .iter()
+            .all(|repository| repository.style == SetupStyle::Pythonpath));
+    }
+
+    #[test]
+    fn test_setup_file_order() {
+        let destination =
+            std::env::temp_dir().join(format!("obs_env_test_order_{}", std::process::id()));
+        fs::create_dir_all(&destination).unwrap();
+        let obs_env = ObservingEnvironment::with_destination(destination.to_str().unwrap())
+            .with_repository(
+                "ts_extra",
+                RepositoryConfig::new("https://github.com/lsst-ts/").with_setup(),
+            );
+
+        let result = obs_env.create_setup_file();
+        let setup_file = fs::read_to_string(destination.join("auto_env_setup.sh"));
+        fs::remove_dir_all(&destination).unwrap();
+
+        result.unwrap();
+        let setup_file = setup_file.unwrap();
+        let products: Vec<&str> = setup_file
+            .lines()
+            .filter_map(|line| line.strip_prefix("setup -j "))
+            .filter_map(|line| line.split_whitespace().next())
+            .collect();
+        assert_eq!(
+            products,
+            [
+                "summit_utils",
+                "summit_extras",
+                "ts_auxtel_standardscripts",
+                "ts_maintel_standardscripts",
+                "ts_standardscripts",
+                "ts_externalscripts",
+                "ts_observatory_control",
+                "ts_observing_utilities",
+                "ts_wep",
+                "cwfs",
+                "ts_extra",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_setup_file_path() {
+        let obs_env = ObservingEnvironment::with_destination("/obs-env");
+        assert_eq!(
+            obs_env.get_setup_file_path(SetupShell::Bash),
+            Path::new("/obs-env/auto_env_setup.sh")
+        );
+
+        let obs_env = obs_env
+            .with_setup_file_dir("/nublado/setup")
+            .with_setup_file_name("obs_env");
+        assert_eq!(
+            obs_env.get_setup_file_path(SetupShell::Csh),
+            Path::new("/nublado/setup/obs_env.csh")
+        );
+        assert_eq!(obs_env.get_setup_file_dir(), Path::new("/nublado/setup"));
+    }
+
+    #[test]
+    fn test_validate_setup_file() {
+        let destination =
+            std::env::temp_dir().join(format!("obs_env_test_validate_{}", std::process::id()));
+        std::fs::create_dir_all(destination.join("ts_wep")).unwrap();
+        std::fs::write(
+            destination.join("auto_env_setup.sh"),
+            format!(
+                "setup -j wep -r {0}/ts_wep\nsetup -j cwfs -r {0}/cwfs\n",
+                destination.display()
+            ),
+        )
+        .unwrap();
+        let obs_env = ObservingEnvironment::with_destination(destination.to_str().unwrap())
+            .with_repository(
+                "ts_wep",
+                RepositoryConfig::new("https://github.com/lsst-ts/").with_setup(),
+            )
+            .with_repository("cwfs", RepositoryConfig::new("https://github.com/lsst-ts/"));
+
+        let problems = obs_env.validate_setup_file().unwrap();
+        std::fs::remove_dir_all(&destination).unwrap();
+
+        let get_problems = |repository: &str| -> Vec<&str> {
+            problems
+                .iter()
+                .filter(|(name, _)| name == repository)
+                .map(|(_, problem)| problem.as_str())
+                .collect()
+        };
+        assert!(get_problems("ts_wep").is_empty());
+        let cwfs = get_problems("cwfs");
+        assert_eq!(cwfs.len(), 2);
+        assert!(cwfs[0].starts_with("Line 2: ") && cwfs[0].ends_with("does not exist"));
+        assert!(cwfs[1].contains("stale entry"));
+        assert_eq!(
+            get_problems("summit_utils"),
+            ["Missing from the setup file"]
+        );
+    }
+
+    #[test]
+    fn test_validate_setup_files() {
+        let destination = std::env::temp_dir().join(format!(
+            "obs_env_test_validate_shells_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&destination).unwrap();
+        let obs_env = ObservingEnvironment::with_destination(destination.to_str().unwrap())
+            .with_setup_shells(&[SetupShell::Csh, SetupShell::Lmod])
+            .with_setup_mode(SetupMode::Pythonpath);
+        obs_env.create_setup_file().unwrap();
+        fs::remove_file(destination.join("auto_env_setup.lua")).unwrap();
+        let missing_lmod = obs_env.validate_setup_file();
+        obs_env.create_setup_file().unwrap();
+        let problems = obs_env.validate_setup_file();
+        fs::remove_dir_all(&destination).unwrap();
+
+        assert!(missing_lmod
+            .unwrap_err()
+            .to_string()
+            .contains("auto_env_setup.lua"));
+        let problems = problems.unwrap();
+        // The repositories are not cloned, the missing paths are the only
+        // problems, once per shell.
+        assert_eq!(problems.len(), 20);
+        assert!(problems
+            .iter()
+            .all(|(_, problem)| problem.ends_with("does not exist")));
+        let summit_utils: Vec<&str> = problems
+            .iter()
+            .filter(|(name, _)| name == "summit_utils")
+            .map(|(_, problem)| problem.split(':').next().unwrap())
+            .collect();
+        assert_eq!(summit_utils, ["auto_env_setup.csh", "auto_env_setup.lua"]);
+    }
+
+    #[test]
+    fn test_create_setup_file_templates() {
+        let destination =
+            std::env::temp_dir().join(format!("obs_env_test_templates_{}", std::process::id()));
+        fs::create_dir_all(&destination).unwrap();
+        let bash_template = destination.join("setup.sh.j2");
+        let csh_template = destination.join("setup.csh.j2");
+        fs::write(&bash_template, "# bash {{ destination }}\n").unwrap();
+        fs::write(&csh_template, "# csh {{ destination }}\n").unwrap();
+        let (bash_template, csh_template) = (
+            bash_template.to_str().unwrap(),
+            csh_template.to_str().unwrap(),
+        );
+        let create_setup_file = |setup_templates: &[SetupTemplate]| {
+            ObservingEnvironment::with_destination(destination.to_str().unwrap())
+                .with_setup_shells(&[SetupShell::Bash, SetupShell::Csh])
+                .with_setup_templates(setup_templates)
+                .create_setup_file()
+        };
+
+        let without_shell = create_setup_file(&[
+            SetupTemplate::new(bash_template),
+            SetupTemplate::for_shell(SetupShell::Csh, csh_template),
+        ]);
+        let no_setup_file = fs::read_to_string(destination.join("auto_env_setup.csh"));
+        let with_shells = create_setup_file(&[
+            SetupTemplate::for_shell(SetupShell::Bash, bash_template),
+            SetupTemplate::for_shell(SetupShell::Csh, csh_template),
+        ]);
+        let bash_setup = fs::read_to_string(destination.join("auto_env_setup.sh"));
+        let csh_setup = fs::read_to_string(destination.join("auto_env_setup.csh"));
+        fs::remove_dir_all(&destination).unwrap();
+
+        // The template without a shell would be used for bash.
+        assert!(without_shell
+            .unwrap_err()
+            .to_string()
+            .contains("has no shell"));
+        assert!(no_setup_file.is_err());
+        with_shells.unwrap();
+        assert_eq!(
+            bash_setup.unwrap(),
+            format!("# bash {}\n", destination.display())
+        );
+        assert_eq!(
+            csh_setup.unwrap(),
+            format!("# csh {}\n", destination.display())
+        );
+    }
+}
@@ -0,0 +1,430 @@
+//! Parsing, validation and resolution of the versions of the repositories.
+
+use crate::error::ObsEnvError;
+use regex::Regex;
+use std::{cmp::Ordering, fmt};
+
+const VERSION_RANGE: &str = r"^~(?P<major>[0-9]+)(\.(?P<minor>[0-9]+))?$";
+const VERSION_LIKE: &str = r"^[0-9]+\.";
+
+/// Type of a pre-release, in release order.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum PreRelease {
+    Alpha,
+    Beta,
+    ReleaseCandidate,
+}
+
+impl PreRelease {
+    /// Suffix of the pre-release in the version form (e.g. "rc" in 1.2.3rc1).
+    pub fn get_version_suffix(&self) -> &'static str {
+        match self {
+            PreRelease::Alpha => "a",
+            PreRelease::Beta => "b",
+            PreRelease::ReleaseCandidate => "rc",
+        }
+    }
+
+    /// Name of the pre-release in the tag form (e.g. "rc" in v1.2.3.rc.1).
+    pub fn get_tag_name(&self) -> &'static str {
+        match self {
+            PreRelease::Alpha => "alpha",
+            PreRelease::Beta => "beta",
+            PreRelease::ReleaseCandidate => "rc",
+        }
+    }
+}
+
+/// Version of a repository, following the format adopted by TSSW.
+///
+/// Versions are written <X>.<Y>.<Z><RT><RN> (e.g. 1.2.3 or 1.2.3rc1), see
+/// `ObservingEnvironment::reset_index_to_version`, and tagged
+/// v<X>.<Y>.<Z>.<release type>.<RN> (e.g. v1.2.3 or v1.2.3.rc.1).
+///
+/// Pre-releases are ordered before the release with the same number, so
+/// 1.2.3a1 < 1.2.3b1 < 1.2.3rc1 < 1.2.3.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Version {
+    major: u64,
+    minor: u64,
+    patch: u64,
+    /// Type and number, if any, of the pre-release.
+    pre_release: Option<(PreRelease, Option<u64>)>,
+}
+
+/// Parse the digits of a version number.
+fn parse_number(number: &str) -> Option<u64> {
+    if !number.is_empty() && number.bytes().all(|byte| byte.is_ascii_digit()) {
+        number.parse().ok()
+    } else {
+        None
+    }
+}
+
+impl Version {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Version {
+        Version {
+            major,
+            minor,
+            patch,
+            pre_release: None,
+        }
+    }
+
+    pub fn with_pre_release(mut self, pre_release: PreRelease, number: Option<u64>) -> Version {
+        self.pre_release = Some((pre_release, number));
+        self
+    }
+
+    /// Parse X.Y.Z into the version numbers and the rest of the string.
+    fn parse_numbers(version: &str) -> Option<(Version, &str)> {
+        let mut numbers = version.splitn(3, '.');
+        let major = parse_number(numbers.next()?)?;
+        let minor = parse_number(numbers.next()?)?;
+        let rest = numbers.next()?;
+        let patch_end = rest
+            .find(|character: char| !character.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let patch = parse_number(&rest[..patch_end])?;
+        Some((Version::new(major, minor, patch), &rest[patch_end..]))
+    }
+
+    /// Parse a version in the version form (e.g. 1.2.3rc1).
+    pub fn parse(version: &str) -> Result<Version, ObsEnvError> {
+        let invalid = || {
+            ObsEnvError::ERROR(format!(
+                "Invalid version {version:?}, expected X.Y.Z optionally followed by a \
+                release type (a, b or rc) and number, e.g. 1.2.3 or 1.2.3rc1."
+            ))
+        };
+        let (parsed, suffix) = Version::parse_numbers(version).ok_or_else(invalid)?;
+        if suffix.is_empty() {
+            return Ok(parsed);
+        }
+        [
+            PreRelease::Alpha,
+            PreRelease::Beta,
+            PreRelease::ReleaseCandidate,
+        ]
+        .into_iter()
+        .find_map(|pre_release| {
+            let number = suffix.strip_prefix(pre_release.get_version_suffix())?;
+            match number {
+                "" => Some(parsed.clone().with_pre_release(pre_release, None)),
+                number => Some(
+                    parsed
+                        .clone()
+                        .with_pre_release(pre_release, Some(parse_number(number)?)),
+                ),
+            }
+        })
+        .ok_or_else(invalid)
+    }
+
+    /// Parse a version in the tag form (e.g. v1.2.3.rc.1), returning None
+    /// if the tag is not a version (e.g. w.2024.10 or a branch name).
+    pub fn from_tag(tag: &str) -> Option<Version> {
+        let (parsed, suffix) = Version::parse_numbers(tag.strip_prefix('v')?)?;
+        if suffix.is_empty() {
+            return Some(parsed);
+        }
+        let suffix = suffix.strip_prefix('.')?;
+        [
+            PreRelease::Alpha,
+            PreRelease::Beta,
+            PreRelease::ReleaseCandidate,
+        ]
+        .into_iter()
+        .find_map(
+            |pre_release| match suffix.strip_prefix(pre_release.get_tag_name())? {
+                "" => Some(parsed.clone().with_pre_release(pre_release, None)),
+                number => {
+                    Some(parsed.clone().with_pre_release(
+                        pre_release,
+                        Some(parse_number(number.strip_prefix('.')?)?),
+                    ))
+                }
+            },
+        )
+    }
+
+    pub fn get_major(&self) -> u64 {
+        self.major
+    }
+
+    pub fn get_minor(&self) -> u64 {
+        self.minor
+    }
+
+    pub fn get_patch(&self) -> u64 {
+        self.patch
+    }
+
+    pub fn get_pre_release(&self) -> Option<(PreRelease, Option<u64>)> {
+        self.pre_release
+    }
+
+    pub fn is_pre_release(&self) -> bool {
+        self.pre_release.is_some()
+    }
+
+    /// Tag of the version (e.g. v1.2.3.rc.1).
+    pub fn to_tag(&self) -> String {
+        let mut tag = format!("v{}.{}.{}", self.major, self.minor, self.patch);
+        if let Some((pre_release, number)) = self.pre_release {
+            tag.push('.');
+            tag.push_str(pre_release.get_tag_name());
+            if let Some(number) = number {
+                tag.push_str(&format!(".{number}"));
+            }
+        }
+        tag
+    }
+}
+
+impl fmt::Display for Version {
+    /// Write the version in the version form (e.g. 1.2.3rc1).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some((pre_release, number)) = self.pre_release {
+            write!(f, "{}", pre_release.get_version_suffix())?;
+            if let Some(number) = number {
+                write!(f, "{number}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (&self.pre_release, &other.pre_release) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(pre_release), Some(other_pre_release)) => pre_release.cmp(other_pre_release),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Expands version string into a tag, following the format adopted by
+/// TSSW. Strings that are not versions (e.g. branch names) are returned
+/// unchanged.
+pub(super) fn expand_version_to_tag(version: &str) -> String {
+    match Version::parse(version) {
+        Ok(version) => version.to_tag(),
+        Err(_) => version.to_owned(),
+    }
+}
+
+/// Validate a branch name against the git reference name rules.
+pub fn validate_branch_name(branch_name: &str) -> Result<(), ObsEnvError> {
+    if branch_name.is_empty() {
+        Err(ObsEnvError::ERROR(
+            "No branch name given, use --branch-name.".to_owned(),
+        ))
+    } else if git2::Reference::is_valid_name(&format!("refs/heads/{branch_name}")) {
+        Ok(())
+    } else {
+        Err(ObsEnvError::ERROR(format!(
+            "Invalid branch name {branch_name:?}: branch names cannot contain spaces, \
+            \"..\", \"~\", \"^\", \":\", \"?\", \"*\", \"[\", \"\\\", start with \"-\" \
+            or end with \"/\", \".\" or \".lock\"."
+        )))
+    }
+}
+
+/// Validate a version specification for the CheckoutVersion action.
+///
+/// The specification can be a version (see
+/// `ObservingEnvironment::reset_index_to_version` for the format), a version
+/// range (see `ObservingEnvironment::resolve_version`) or a branch name.
+pub fn validate_version(version: &str) -> Result<(), ObsEnvError> {
+    if version.is_empty() {
+        Err(ObsEnvError::ERROR(
+            "No version given, use --branch-name.".to_owned(),
+        ))
+    } else if version == "latest" {
+        Ok(())
+    } else if version.starts_with('~') {
+        if Regex::new(VERSION_RANGE).unwrap().is_match(version) {
+            Ok(())
+        } else {
+            Err(ObsEnvError::ERROR(format!(
+                "Invalid version range {version:?}, expected ~X or ~X.Y (e.g. ~1.4)."
+            )))
+        }
+    } else if Regex::new(VERSION_LIKE).unwrap().is_match(version) {
+        Version::parse(version).map(|_| ())
+    } else {
+        validate_branch_name(version)
+    }
+}
+
+/// Is the version specification a range, to be resolved with
+/// `ObservingEnvironment::resolve_version`?
+pub(super) fn is_version_range(version_spec: &str) -> bool {
+    version_spec == "latest" || version_spec.starts_with('~')
+}
+
+/// Select the newest release tag matching the version range, returning it as
+/// a version (without the leading v).
+pub(super) fn resolve_version_range<'a>(
+    tags: impl Iterator<Item = &'a str>,
+    version_spec: &str,
+) -> Option<String> {
+    let range_regex = Regex::new(VERSION_RANGE).unwrap();
+
+    let (major, minor) = if version_spec == "latest" {
+        (None, None)
+    } else {
+        let range = range_regex.captures(version_spec)?;
+        (
+            range["major"].parse::<u64>().ok(),
+            range
+                .name("minor")
+                .and_then(|minor| minor.as_str().parse::<u64>().ok()),
+        )
+    };
+
+    tags.filter_map(Version::from_tag)
+        .filter(|version| {
+            !version.is_pre_release()
+                && major.is_none_or(|major| major == version.get_major())
+                && minor.is_none_or(|minor| minor == version.get_minor())
+        })
+        .max()
+        .map(|version| version.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        expand_version_to_tag, is_version_range, resolve_version_range, validate_branch_name,
+        validate_version, PreRelease, Version,
+    };
+
+    #[test]
+    fn test_expand_version_to_tag() {
+        assert_eq!(expand_version_to_tag("1.0.0"), "v1.0.0");
+        assert_eq!(expand_version_to_tag("1.0.0a1"), "v1.0.0.alpha.1");
+        assert_eq!(expand_version_to_tag("1.0.0b1"), "v1.0.0.beta.1");
+        assert_eq!(expand_version_to_tag("1.0.0rc1"), "v1.0.0.rc.1");
+        assert_eq!(expand_version_to_tag("w.2023.13"), "w.2023.13");
+        assert_eq!(expand_version_to_tag("develop"), "develop");
+    }
+
+    #[test]
+    fn test_version() {
+        let version = Version::parse("1.20.3rc2").unwrap();
+        assert_eq!(
+            version,
+            Version::new(1, 20, 3).with_pre_release(PreRelease::ReleaseCandidate, Some(2))
+        );
+        assert_eq!(version.to_string(), "1.20.3rc2");
+        assert_eq!(version.to_tag(), "v1.20.3.rc.2");
+        assert_eq!(Version::from_tag("v1.20.3.rc.2"), Some(version));
+        assert_eq!(Version::parse("1.0.0a").unwrap().to_tag(), "v1.0.0.alpha");
+        assert_eq!(
+            Version::from_tag("v1.0.0.alpha"),
+            Some(Version::new(1, 0, 0).with_pre_release(PreRelease::Alpha, None))
+        );
+        assert_eq!(Version::from_tag("v1.0.0-3-gabcdef"), None);
+        assert_eq!(Version::from_tag("w.2023.13"), None);
+        assert!(Version::parse("1.2.3c1").is_err());
+        assert!(Version::parse("1.2.+3").is_err());
+
+        let mut versions: Vec<Version> = ["1.2.3", "1.2.3rc1", "1.2.3a1", "1.10.0", "1.2.3b1"]
+            .into_iter()
+            .map(|version| Version::parse(version).unwrap())
+            .collect();
+        versions.sort();
+        let versions: Vec<String> = versions.iter().map(Version::to_string).collect();
+        assert_eq!(
+            versions,
+            ["1.2.3a1", "1.2.3b1", "1.2.3rc1", "1.2.3", "1.10.0"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_version_range() {
+        let tags = [
+            "v1.3.9",
+            "v1.4.0",
+            "v1.4.2",
+            "v1.4.10",
+            "v1.4.11.rc.1",
+            "v1.5.0.alpha.1",
+            "v2.0.0",
+            "w.2024.10",
+        ];
+
+        assert!(is_version_range("latest"));
+        assert!(is_version_range("~1.4"));
+        assert!(!is_version_range("1.4.2"));
+        assert!(!is_version_range("tickets/DM-12345"));
+
+        assert_eq!(
+            resolve_version_range(tags.into_iter(), "latest"),
+            Some("2.0.0".to_owned())
+        );
+        assert_eq!(
+            resolve_version_range(tags.into_iter(), "~1.4"),
+            Some("1.4.10".to_owned())
+        );
+        assert_eq!(
+            resolve_version_range(tags.into_iter(), "~1"),
+            Some("1.4.10".to_owned())
+        );
+        assert_eq!(resolve_version_range(tags.into_iter(), "~1.6"), None);
+        assert_eq!(resolve_version_range(tags.into_iter(), "~1.x"), None);
+    }
+
+    #[test]
+    fn test_validate_branch_name() {
+        assert!(validate_branch_name("main").is_ok());
+        assert!(validate_branch_name("tickets/DM-12345").is_ok());
+        assert!(validate_branch_name("").is_err());
+        assert!(validate_branch_name("tickets/DM 12345").is_err());
+        assert!(validate_branch_name("tickets/DM-12345/").is_err());
+        assert!(validate_branch_name("a..b").is_err());
+        assert!(validate_branch_name("branch.lock").is_err());
+    }
+
+    #[test]
+    fn test_validate_version() {
+        assert!(validate_version("1.2.3").is_ok());
+        assert!(validate_version("1.2.3a").is_ok());
+        assert!(validate_version("1.2.3rc2").is_ok());
+        assert!(validate_version("latest").is_ok());
+        assert!(validate_version("~1.4").is_ok());
+        assert!(validate_version("develop").is_ok());
+        assert!(validate_version("w.2023.13").is_ok());
+        assert!(validate_version("").is_err());
+        assert!(validate_version("1.2").is_err());
+        assert!(validate_version("v1.2.3").is_ok());
+        assert!(validate_version("1.2.3-rc1").is_err());
+        assert!(validate_version("~1.x").is_err());
+    }
+
+    #[test]
+    fn test_is_valid_version() {
+        assert!(Version::parse("1.2.3").is_ok());
+        assert!(Version::parse("10.200.300").is_ok());
+        assert!(Version::parse("1.20.3a1").is_ok());
+        assert!(Version::parse("1.20.3b1").is_ok());
+        assert!(Version::parse("1.20.3rc1").is_ok());
+        assert!(Version::parse("w.2023.13").is_err());
+        assert!(Version::parse("main").is_err());
+        assert!(Version::parse("develop").is_err());
+        assert!(Version::parse("ticket/DM-12345").is_err());
+    }
+}
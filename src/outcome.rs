@@ -0,0 +1,44 @@
+use crate::{
+    report::{Report, ReportEntry},
+    sasquatch::sink::TelemetryStatus,
+};
+
+/// Outcome of an action that ran to completion.
+///
+/// Failures on individual repositories don't stop the action (e.g. Reset
+/// continues with the other repositories), so callers should check
+/// `is_success` or the failures instead of relying on `run` returning Ok.
+#[derive(Debug)]
+pub struct ActionOutcome {
+    report: Report,
+    telemetry: TelemetryStatus,
+}
+
+impl ActionOutcome {
+    pub fn new(report: Report, telemetry: TelemetryStatus) -> ActionOutcome {
+        ActionOutcome { report, telemetry }
+    }
+
+    /// Did all the operations on individual repositories succeed?
+    pub fn is_success(&self) -> bool {
+        self.report.is_success()
+    }
+
+    /// Outcome of the operations on individual repositories.
+    pub fn get_report(&self) -> &Report {
+        &self.report
+    }
+
+    /// Failed operations on individual repositories.
+    pub fn get_failures(&self) -> impl Iterator<Item = &ReportEntry> {
+        self.report.get_failures()
+    }
+
+    pub fn get_warnings(&self) -> &[String] {
+        self.report.get_warnings()
+    }
+
+    pub fn get_telemetry(&self) -> &TelemetryStatus {
+        &self.telemetry
+    }
+}
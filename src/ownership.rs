@@ -0,0 +1,179 @@
+use crate::error::ObsEnvError;
+use nix::unistd::{Group, User};
+use std::{
+    fs::{read_dir, set_permissions, symlink_metadata},
+    os::unix::fs::{lchown, PermissionsExt},
+    path::Path,
+};
+
+/// Ownership to apply to files in the environment.
+///
+/// Besides setting the owner and group, the group permissions are made equal
+/// to the owner permissions and directories get the setgid bit, so files
+/// created later on inherit the group.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ownership {
+    uid: Option<u32>,
+    gid: Option<u32>,
+}
+
+impl Ownership {
+    /// Parse an ownership specification in the format `user:group`, `user`
+    /// or `:group`, where user and group are either names or numeric ids.
+    pub fn parse(spec: &str) -> Result<Ownership, ObsEnvError> {
+        let (user, group) = match spec.split_once(':') {
+            Some((user, group)) => (user, group),
+            None => (spec, ""),
+        };
+
+        let uid = if user.is_empty() {
+            None
+        } else if let Ok(uid) = user.parse::<u32>() {
+            Some(uid)
+        } else {
+            match User::from_name(user) {
+                Ok(Some(user)) => Some(user.uid.as_raw()),
+                Ok(None) => return Err(ObsEnvError::ERROR(format!("Unknown user {user}."))),
+                Err(error) => {
+                    return Err(ObsEnvError::ERROR(format!(
+                        "Failed to look up user {user}: {error}"
+                    )))
+                }
+            }
+        };
+
+        let gid = if group.is_empty() {
+            None
+        } else if let Ok(gid) = group.parse::<u32>() {
+            Some(gid)
+        } else {
+            match Group::from_name(group) {
+                Ok(Some(group)) => Some(group.gid.as_raw()),
+                Ok(None) => return Err(ObsEnvError::ERROR(format!("Unknown group {group}."))),
+                Err(error) => {
+                    return Err(ObsEnvError::ERROR(format!(
+                        "Failed to look up group {group}: {error}"
+                    )))
+                }
+            }
+        };
+
+        if uid.is_none() && gid.is_none() {
+            Err(ObsEnvError::ERROR(format!(
+                "Invalid ownership specification {spec:?}, expected user:group."
+            )))
+        } else {
+            Ok(Ownership { uid, gid })
+        }
+    }
+
+    /// Apply ownership and permissions to `path` and, if it is a directory,
+    /// everything under it.
+    ///
+    /// Symbolic links are not followed. Returns the number of updated paths.
+    pub fn apply(&self, path: &Path) -> Result<usize, ObsEnvError> {
+        let metadata = symlink_metadata(path).map_err(|error| {
+            ObsEnvError::ERROR(format!("Failed to read metadata of {path:?}: {error}"))
+        })?;
+
+        lchown(path, self.uid, self.gid).map_err(|error| {
+            ObsEnvError::ERROR(format!("Failed to change ownership of {path:?}: {error}"))
+        })?;
+
+        if metadata.file_type().is_symlink() {
+            return Ok(1);
+        }
+
+        let mode = metadata.permissions().mode();
+        let user_bits = (mode & 0o700) >> 3;
+        let mut new_mode = (mode & !0o070) | user_bits;
+        if metadata.is_dir() {
+            new_mode |= 0o2000;
+        }
+        if new_mode != mode {
+            set_permissions(path, PermissionsExt::from_mode(new_mode)).map_err(|error| {
+                ObsEnvError::ERROR(format!("Failed to change permissions of {path:?}: {error}"))
+            })?;
+        }
+
+        let mut updated = 1;
+        if metadata.is_dir() {
+            let entries = read_dir(path).map_err(|error| {
+                ObsEnvError::ERROR(format!("Failed to read directory {path:?}: {error}"))
+            })?;
+            for entry in entries {
+                match entry {
+                    Ok(entry) => updated += self.apply(&entry.path())?,
+                    Err(error) => {
+                        return Err(ObsEnvError::ERROR(format!(
+                            "Failed to read directory {path:?}: {error}"
+                        )))
+                    }
+                }
+            }
+        }
+        Ok(updated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ownership;
+    use nix::unistd::{getgid, getuid};
+    use std::{
+        env, fs,
+        os::unix::fs::{MetadataExt, PermissionsExt},
+        process,
+    };
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Ownership::parse("1000:2000").unwrap(),
+            Ownership {
+                uid: Some(1000),
+                gid: Some(2000)
+            }
+        );
+        assert_eq!(
+            Ownership::parse(":2000").unwrap(),
+            Ownership {
+                uid: None,
+                gid: Some(2000)
+            }
+        );
+        assert_eq!(
+            Ownership::parse("1000").unwrap(),
+            Ownership {
+                uid: Some(1000),
+                gid: None
+            }
+        );
+        assert!(Ownership::parse(":").is_err());
+        assert!(Ownership::parse("no_such_user_obs_env:").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let dir = env::temp_dir().join(format!("obs_env_ownership_test_{}", process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub").join("file"), "content").unwrap();
+        fs::set_permissions(
+            dir.join("sub").join("file"),
+            PermissionsExt::from_mode(0o640),
+        )
+        .unwrap();
+
+        let ownership = Ownership::parse(&format!("{}:{}", getuid(), getgid())).unwrap();
+
+        assert_eq!(ownership.apply(&dir).unwrap(), 3);
+
+        let metadata = fs::metadata(dir.join("sub").join("file")).unwrap();
+        assert_eq!(metadata.mode() & 0o7777, 0o660);
+        assert_eq!(metadata.uid(), getuid().as_raw());
+        let metadata = fs::metadata(dir.join("sub")).unwrap();
+        assert_eq!(metadata.mode() & 0o2000, 0o2000);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,38 @@
+use crate::error::ObsEnvError;
+
+/// Phase of a long-running operation on the environment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    /// Cloning the repositories that are not yet in the environment.
+    Cloning,
+    /// Updating the base environment source to read the base versions.
+    UpdatingBaseEnv,
+    /// Checking out the run branch or base version of each repository.
+    Resetting,
+}
+
+/// Observer of the progress of the long-running operations (clone and
+/// reset), e.g. to render it in a TUI or report it from a server.
+///
+/// All the methods do nothing by default, so observers only implement the
+/// events they are interested in.
+pub trait ProgressObserver {
+    /// The operation moved to `phase`.
+    fn phase_changed(&self, _phase: Phase) {}
+
+    /// Started working on the repository `repo`.
+    fn repo_started(&self, _repo: &str) {}
+
+    /// Received `received_bytes` so far fetching or cloning `repo`.
+    fn bytes_transferred(&self, _repo: &str, _received_bytes: usize) {}
+
+    /// Finished working on the repository `repo`, with the error if it
+    /// failed.
+    fn repo_finished(&self, _repo: &str, _error: Option<&ObsEnvError>) {}
+}
+
+/// Observer ignoring all the events.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoProgress;
+
+impl ProgressObserver for NoProgress {}
@@ -0,0 +1,161 @@
+use crate::error::ObsEnvError;
+use chrono::Utc;
+use std::{fmt::Display, fs::write};
+
+/// Machine-readable report of the outcome of an action.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Report {
+    /// Action executed.
+    action: String,
+    /// Time the action started, in milliseconds since the epoch.
+    timestamp: i64,
+    /// Did the action, and all the operations on individual repositories,
+    /// succeed?
+    success: bool,
+    /// Error that stopped the action, if any.
+    error: Option<String>,
+    /// Operations executed on individual repositories.
+    entries: Vec<ReportEntry>,
+    /// Problems that did not fail the action, e.g. an outdated setup file.
+    #[serde(default)]
+    warnings: Vec<String>,
+}
+
+/// Outcome of an operation on a single repository.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ReportEntry {
+    repository: String,
+    operation: String,
+    success: bool,
+    error: Option<String>,
+}
+
+impl Report {
+    pub fn new(action: &str) -> Report {
+        Report {
+            action: action.to_owned(),
+            timestamp: Utc::now().timestamp_millis(),
+            success: true,
+            error: None,
+            entries: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Record a successful operation on a repository.
+    pub fn add_success(&mut self, repository: &str, operation: &str) {
+        self.entries.push(ReportEntry {
+            repository: repository.to_owned(),
+            operation: operation.to_owned(),
+            success: true,
+            error: None,
+        });
+    }
+
+    /// Record a failed operation on a repository.
+    pub fn add_failure(&mut self, repository: &str, operation: &str, error: &str) {
+        self.success = false;
+        self.entries.push(ReportEntry {
+            repository: repository.to_owned(),
+            operation: operation.to_owned(),
+            success: false,
+            error: Some(error.to_owned()),
+        });
+    }
+
+    /// Record the outcome of an operation on a repository.
+    pub fn record<T, E: Display>(
+        &mut self,
+        repository: &str,
+        operation: &str,
+        result: &Result<T, E>,
+    ) {
+        match result {
+            Ok(_) => self.add_success(repository, operation),
+            Err(error) => self.add_failure(repository, operation, &error.to_string()),
+        }
+    }
+
+    /// Record a problem that does not fail the action.
+    pub fn add_warning(&mut self, warning: &str) {
+        self.warnings.push(warning.to_owned());
+    }
+
+    /// Record the error that stopped the action.
+    pub fn set_error(&mut self, error: &str) {
+        self.success = false;
+        self.error = Some(error.to_owned());
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.success
+    }
+
+    pub fn get_entries(&self) -> &[ReportEntry] {
+        &self.entries
+    }
+
+    /// Failed operations on individual repositories.
+    pub fn get_failures(&self) -> impl Iterator<Item = &ReportEntry> {
+        self.entries.iter().filter(|entry| !entry.is_success())
+    }
+
+    pub fn get_warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Write the report, as JSON, to `path`.
+    pub fn write(&self, path: &str) -> Result<(), ObsEnvError> {
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|error| ObsEnvError::ERROR(format!("Failed to serialize report: {error}")))?;
+        write(path, content).map_err(|error| {
+            ObsEnvError::ERROR(format!("Failed to write report file {path}: {error}"))
+        })
+    }
+}
+
+impl ReportEntry {
+    pub fn get_repository(&self) -> &str {
+        &self.repository
+    }
+
+    pub fn get_operation(&self) -> &str {
+        &self.operation
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.success
+    }
+
+    pub fn get_error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Report;
+
+    #[test]
+    fn test_report() {
+        let mut report = Report::new("Reset");
+        report.add_success("ts_wep", "reset");
+        assert!(report.is_success());
+
+        report.add_warning("Setup file is outdated");
+        assert!(report.is_success());
+
+        report.add_failure("summit_utils", "reset", "Failed to open repository");
+        assert!(!report.is_success());
+        assert_eq!(report.get_failures().count(), 1);
+
+        let content: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&report).unwrap()).unwrap();
+        assert_eq!(content["action"], "Reset");
+        assert_eq!(content["success"], false);
+        assert_eq!(content["entries"][1]["repository"], "summit_utils");
+        assert_eq!(content["entries"][1]["error"], "Failed to open repository");
+        assert_eq!(content["entries"][0]["error"], serde_json::Value::Null);
+        assert_eq!(content["warnings"][0], "Setup file is outdated");
+    }
+}
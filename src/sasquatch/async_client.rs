@@ -0,0 +1,132 @@
+use std::{env, error::Error, fmt::Debug};
+
+use super::{
+    http::{get_connect_timeout, get_rest_proxy_urls, get_timeout},
+    log_summary::{get_payload, AvroSchema},
+    schema::get_namespace,
+    sender::{prepare_payload, QueuedPayload},
+};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    Client,
+};
+use serde::ser::Serialize;
+
+/// Client sending telemetry records to sasquatch from async code, e.g. a
+/// sidecar or server sharing a tokio runtime with the actions.
+///
+/// Unlike `SasquatchClient`, records are sent when `send_record` is
+/// awaited, without a background thread, spool or topic creation: the
+/// topics must exist (see the CreateTopics action).
+#[derive(Clone, Debug)]
+pub struct AsyncSasquatchClient {
+    client: Client,
+    rest_proxy_urls: Vec<String>,
+    namespace: String,
+}
+
+impl AsyncSasquatchClient {
+    /// Client sending to the sasquatch REST proxies at `rest_proxy_urls`
+    /// (including their base path), in the topics of `namespace`, with the
+    /// token sent as bearer authorization, if any.
+    pub fn new(
+        rest_proxy_urls: Vec<String>,
+        namespace: &str,
+        token: Option<&str>,
+    ) -> Result<AsyncSasquatchClient, Box<dyn Error>> {
+        let mut headers = HeaderMap::new();
+        if let Some(token) = token {
+            let mut authorization = HeaderValue::from_str(&format!("Bearer {token}"))?;
+            authorization.set_sensitive(true);
+            headers.insert(AUTHORIZATION, authorization);
+        }
+        let client = Client::builder()
+            .connect_timeout(get_connect_timeout())
+            .timeout(get_timeout())
+            .default_headers(headers)
+            .build()?;
+        Ok(AsyncSasquatchClient {
+            client,
+            rest_proxy_urls: rest_proxy_urls
+                .iter()
+                .map(|url| url.trim_end_matches('/').to_owned())
+                .collect(),
+            namespace: namespace.to_owned(),
+        })
+    }
+
+    /// Client configured from the environment, as `SasquatchClient::from_env`.
+    pub fn from_env() -> Result<AsyncSasquatchClient, Box<dyn Error>> {
+        let token = env::var("SASQUATCH_REST_PROXY_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+        AsyncSasquatchClient::new(get_rest_proxy_urls(), &get_namespace(), token.as_deref())
+    }
+
+    pub fn get_rest_proxy_urls(&self) -> &[String] {
+        &self.rest_proxy_urls
+    }
+
+    pub fn get_namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Send `record` to the `topic_name` topic of all the REST proxies.
+    ///
+    /// A failure to send to one of the REST proxies does not prevent
+    /// sending to the others, the errors are returned together.
+    pub async fn send_record<T: AvroSchema + Debug + Serialize>(
+        &self,
+        record: T,
+        topic_name: &str,
+    ) -> Result<(), String> {
+        let queued = prepare_payload(&self.namespace, &get_payload(record), topic_name)?;
+        if self.rest_proxy_urls.is_empty() {
+            return Err("Environment variable SASQUATCH_REST_PROXY_URL, not set. \
+                This variable defines the url of the sasquatch service and needs \
+                to be defined for actions to be registered."
+                .to_owned());
+        }
+        let mut errors = Vec::new();
+        for url in &self.rest_proxy_urls {
+            if let Err(error) = self.post_payload(url, &queued).await {
+                errors.push(format!("{url}: {error}"));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    async fn post_payload(
+        &self,
+        rest_proxy_url: &str,
+        queued: &QueuedPayload,
+    ) -> Result<(), String> {
+        let QueuedPayload {
+            topic_name,
+            payload,
+            ..
+        } = queued;
+        match self
+            .client
+            .post(format!("{rest_proxy_url}/topics/{topic_name}"))
+            .header("Content-Type", "application/vnd.kafka.avro.v2+json")
+            .header("Accept", "application/vnd.kafka.v2+json")
+            .json(payload)
+            .send()
+            .await
+        {
+            Ok(res) if res.status().is_success() => {
+                log::trace!("Payload: {payload:?}.");
+                Ok(())
+            }
+            Ok(res) => Err(format!(
+                "Server replied with error to payload request: {res:?}. {payload:?}"
+            )),
+            Err(error) => Err(format!("Error sending payload: {error}")),
+        }
+    }
+}
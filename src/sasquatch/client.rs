@@ -0,0 +1,187 @@
+use std::{env, error::Error, fmt::Debug, time::Duration};
+
+use super::{
+    http::{build_client_with, get_connect_timeout, get_rest_proxy_urls, get_timeout},
+    log_summary::{get_payload, AvroSchema},
+    schema::{get_namespace, DEFAULT_NAMESPACE},
+    sender::TelemetrySender,
+    spool::TelemetrySpool,
+    topic_config::TopicConfig,
+};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use serde::ser::Serialize;
+
+/// Client sending the telemetry records of the observing environment to
+/// sasquatch.
+///
+/// Records are sent in the background by a `TelemetrySender`, and are
+/// flushed when the client is dropped. The records of the actions are sent
+/// with the `TelemetrySink` methods.
+pub struct SasquatchClient {
+    rest_proxy_urls: Vec<String>,
+    spool: Option<TelemetrySpool>,
+    sender: TelemetrySender,
+}
+
+/// Builder of a `SasquatchClient`.
+#[derive(Clone, Debug)]
+pub struct SasquatchClientBuilder {
+    rest_proxy_urls: Vec<String>,
+    connect_timeout: Duration,
+    timeout: Duration,
+    namespace: String,
+    token: Option<String>,
+    spool: Option<TelemetrySpool>,
+    auto_create: Option<TopicConfig>,
+}
+
+impl Default for SasquatchClientBuilder {
+    fn default() -> Self {
+        SasquatchClientBuilder {
+            rest_proxy_urls: Vec::new(),
+            connect_timeout: get_connect_timeout(),
+            timeout: get_timeout(),
+            namespace: DEFAULT_NAMESPACE.to_owned(),
+            token: None,
+            spool: None,
+            auto_create: None,
+        }
+    }
+}
+
+impl SasquatchClientBuilder {
+    /// Add a REST proxy to send the records to, given by its base URL
+    /// (including its path).
+    pub fn with_rest_proxy_url(mut self, rest_proxy_url: &str) -> Self {
+        self.rest_proxy_urls
+            .push(rest_proxy_url.trim_end_matches('/').to_owned());
+        self
+    }
+    /// Set the REST proxies to send the records to.
+    pub fn with_rest_proxy_urls(mut self, rest_proxy_urls: Vec<String>) -> Self {
+        self.rest_proxy_urls = rest_proxy_urls;
+        self
+    }
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// Set the namespace of the records, which is also the prefix of the
+    /// topic names.
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = namespace.to_owned();
+        self
+    }
+    /// Set the token sent as bearer authorization to the REST proxies.
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_owned());
+        self
+    }
+    /// Store the records that fail to be sent in `spool`, and retry the
+    /// records already in it.
+    pub fn with_spool(mut self, spool: TelemetrySpool) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+    /// Create the topics that do not exist with `topic_config`, instead of
+    /// failing to send the records.
+    pub fn with_auto_create(mut self, topic_config: TopicConfig) -> Self {
+        self.auto_create = Some(topic_config);
+        self
+    }
+
+    /// Build the client and start sending records.
+    pub fn build(self) -> Result<SasquatchClient, Box<dyn Error>> {
+        let mut headers = HeaderMap::new();
+        if let Some(token) = &self.token {
+            let mut authorization = HeaderValue::from_str(&format!("Bearer {token}"))?;
+            authorization.set_sensitive(true);
+            headers.insert(AUTHORIZATION, authorization);
+        }
+        let client = build_client_with(self.connect_timeout, self.timeout, headers)?;
+        Ok(SasquatchClient {
+            sender: TelemetrySender::new(
+                client,
+                self.rest_proxy_urls.clone(),
+                &self.namespace,
+                self.spool.clone(),
+                self.auto_create,
+            ),
+            rest_proxy_urls: self.rest_proxy_urls,
+            spool: self.spool,
+        })
+    }
+}
+
+impl SasquatchClient {
+    pub fn builder() -> SasquatchClientBuilder {
+        SasquatchClientBuilder::default()
+    }
+
+    /// Builder configured from the environment: the REST proxies from
+    /// `get_rest_proxy_urls`, the namespace from `get_namespace` and the
+    /// token from SASQUATCH_REST_PROXY_TOKEN, if set.
+    pub fn from_env() -> SasquatchClientBuilder {
+        let builder = SasquatchClient::builder()
+            .with_rest_proxy_urls(get_rest_proxy_urls())
+            .with_namespace(&get_namespace());
+        match env::var("SASQUATCH_REST_PROXY_TOKEN") {
+            Ok(token) if !token.is_empty() => builder.with_token(&token),
+            _ => builder,
+        }
+    }
+
+    pub fn get_rest_proxy_urls(&self) -> &[String] {
+        &self.rest_proxy_urls
+    }
+
+    pub fn get_spool(&self) -> Option<&TelemetrySpool> {
+        self.spool.as_ref()
+    }
+
+    /// Queue `record` to be sent to the `topic_name` topic.
+    pub fn send_record<T: AvroSchema + Debug + Serialize>(
+        &self,
+        record: T,
+        topic_name: &str,
+    ) -> Result<(), String> {
+        self.sender.send(&get_payload(record), topic_name)
+    }
+
+    /// Wait for all queued records to be sent.
+    pub fn flush(self) {
+        self.sender.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SasquatchClient;
+    use std::time::Duration;
+
+    #[test]
+    fn test_builder() {
+        let client = SasquatchClient::builder()
+            .with_rest_proxy_url("http://127.0.0.1:1/sasquatch-rest-proxy/")
+            .with_connect_timeout(Duration::from_millis(10))
+            .with_namespace("lsst.obsenv.test")
+            .with_token("secret")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            client.get_rest_proxy_urls(),
+            ["http://127.0.0.1:1/sasquatch-rest-proxy"]
+        );
+        assert!(client.get_spool().is_none());
+
+        assert!(SasquatchClient::builder()
+            .with_token("invalid\ntoken")
+            .build()
+            .is_err());
+    }
+}
@@ -1,6 +1,17 @@
-use reqwest;
+use super::{
+    drift::Drift,
+    http::{build_client, send_idempotent},
+    lock_state::LockState,
+    log_summary::{ActionData, Summary},
+    metrics::ActionMetrics,
+    run_branch::RunBranch,
+    schema::get_topic,
+    topic_config::{TopicConfig, TopicConfigValue},
+};
+use reqwest::{self, StatusCode};
 use serde_json;
 use std::error::Error as StdError;
+use thiserror::Error as ThisError;
 
 #[derive(Debug, Deserialize, Serialize, Default)]
 struct KafkaClusterList {
@@ -35,82 +46,198 @@ struct Related {
     related: String,
 }
 
-#[derive(Debug, Deserialize, Serialize, Default)]
-struct TopicConfig {
-    topic_name: String,
-    partitions_count: usize,
-    replication_factor: usize,
+#[derive(Debug, Deserialize, Default)]
+struct KafkaList<T> {
+    data: Vec<T>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct KafkaError {
+    error_code: i64,
+    message: String,
 }
 
+#[derive(Clone, Debug, Eq, ThisError, PartialEq)]
+#[error("{0}")]
+struct ErrorManagingTopic(String);
+
+/// Error code of the REST proxy when creating a topic that already exists.
+const TOPIC_ALREADY_EXISTS: i64 = 40002;
+
 impl KafkaClusterList {
     fn get_cluster_id(&self) -> &str {
         &self.data[0].cluster_id
     }
 }
 
-impl TopicConfig {
-    pub fn with_topic_name(mut self, topic_name: &str) -> Self {
-        self.topic_name = topic_name.to_owned();
-        self
+/// Create the telemetry topics, with the partitions, replication factor and
+/// configuration in `topic_config`.
+///
+/// Topics that already exist are left untouched.
+pub fn create_topics(
+    rest_proxy_url: &str,
+    topic_config: &TopicConfig,
+) -> Result<(), Box<dyn StdError>> {
+    let client = build_client()?;
+    let cluster_id = get_cluster_id(&client, rest_proxy_url)?;
+    for topic_name in [
+        Summary::get_topic_name(),
+        ActionData::get_topic_name(),
+        RunBranch::get_topic_name(),
+        Drift::get_topic_name(),
+        LockState::get_topic_name(),
+        ActionMetrics::get_topic_name(),
+    ] {
+        let topic_config = topic_config.clone().with_topic_name(&get_topic(topic_name));
+        create_topic(&client, rest_proxy_url, &cluster_id, &topic_config)?;
     }
+    Ok(())
+}
 
-    pub fn with_partitions_count(mut self, partitions_count: usize) -> Self {
-        self.partitions_count = partitions_count;
-        self
+/// List the telemetry topics, i.e. those in the telemetry namespace.
+pub fn list_topics(rest_proxy_url: &str) -> Result<Vec<TopicConfig>, Box<dyn StdError>> {
+    let client = build_client()?;
+    let cluster_id = get_cluster_id(&client, rest_proxy_url)?;
+    let res =
+        send_idempotent(client.get(format!("{rest_proxy_url}/v3/clusters/{cluster_id}/topics")))?;
+    if !res.status().is_success() {
+        return Err(Box::new(ErrorManagingTopic(format!(
+            "Error listing topics: {}",
+            res.text()?
+        ))));
     }
+    let prefix = get_topic("");
+    let mut topics: Vec<TopicConfig> = res
+        .json::<KafkaList<TopicConfig>>()?
+        .data
+        .into_iter()
+        .filter(|topic| topic.get_topic_name().starts_with(&prefix))
+        .collect();
+    topics.sort_by(|a, b| a.get_topic_name().cmp(b.get_topic_name()));
+    Ok(topics)
+}
 
-    pub fn with_replication_factor(mut self, replication_factor: usize) -> Self {
-        self.replication_factor = replication_factor;
-        self
+/// Get the configuration of a topic.
+pub fn describe_topic(
+    rest_proxy_url: &str,
+    topic_name: &str,
+) -> Result<Vec<TopicConfigValue>, Box<dyn StdError>> {
+    let client = build_client()?;
+    let cluster_id = get_cluster_id(&client, rest_proxy_url)?;
+    let res = send_idempotent(client.get(format!(
+        "{rest_proxy_url}/v3/clusters/{cluster_id}/topics/{topic_name}/configs"
+    )))?;
+    if !res.status().is_success() {
+        return Err(Box::new(ErrorManagingTopic(format!(
+            "Error describing topic {topic_name}: {}",
+            res.text()?
+        ))));
     }
+    Ok(res.json::<KafkaList<TopicConfigValue>>()?.data)
 }
 
-pub fn create_topics(sasquatch_rest_proxy_url: &str) -> Result<(), Box<dyn StdError>> {
-    let client = reqwest::blocking::Client::new();
-    let body = client
-        .get(format!(
-            "{sasquatch_rest_proxy_url}/sasquatch-rest-proxy/v3/clusters"
-        ))
-        .header("content-type", "application/json")
-        .send()?
-        .text()?;
-    let kafka_cluster_list: KafkaClusterList = serde_json::from_str(&body)?;
-    let cluster_id = kafka_cluster_list.get_cluster_id();
-    let topic_config = TopicConfig::default()
-        .with_topic_name("lsst.obsenv.summary")
-        .with_partitions_count(1)
-        .with_replication_factor(3);
-    log::debug!("{topic_config:?}");
-    let res = client
-        .post(format!(
-            "{sasquatch_rest_proxy_url}/sasquatch-rest-proxy/v3/clusters/{cluster_id}/topics"
-        ))
-        .json(&topic_config)
-        .send()?;
-    log::debug!("{res:?}");
-    let topic_config = TopicConfig::default()
-        .with_topic_name("lsst.obsenv.action")
-        .with_partitions_count(1)
-        .with_replication_factor(3);
-    log::debug!("{topic_config:?}");
-    let res = client
-        .post(format!(
-            "{sasquatch_rest_proxy_url}/sasquatch-rest-proxy/v3/clusters/{cluster_id}/topics"
-        ))
-        .json(&topic_config)
-        .send()?;
-    log::debug!("{res:?}");
-    let topic_config = TopicConfig::default()
-        .with_topic_name("lsst.obsenv.run_branch")
-        .with_partitions_count(1)
-        .with_replication_factor(3);
+/// Delete all the telemetry topics, returning the names of the deleted
+/// topics.
+///
+/// All the records in the topics are lost.
+pub fn delete_topics(rest_proxy_url: &str) -> Result<Vec<String>, Box<dyn StdError>> {
+    let client = build_client()?;
+    let cluster_id = get_cluster_id(&client, rest_proxy_url)?;
+    let mut deleted = Vec::new();
+    for topic in list_topics(rest_proxy_url)? {
+        let res = send_idempotent(client.delete(format!(
+            "{rest_proxy_url}/v3/clusters/{cluster_id}/topics/{}",
+            topic.get_topic_name()
+        )))?;
+        if !res.status().is_success() {
+            return Err(Box::new(ErrorManagingTopic(format!(
+                "Error deleting topic {}: {}",
+                topic.get_topic_name(),
+                res.text()?
+            ))));
+        }
+        log::info!("Deleted topic {}.", topic.get_topic_name());
+        deleted.push(topic.get_topic_name().to_owned());
+    }
+    Ok(deleted)
+}
+
+/// Check that the topic `topic_name` (the full name) exists.
+///
+/// A missing topic is created with `auto_create` if given, otherwise an
+/// error asking to create the topics is returned.
+pub fn ensure_topic(
+    client: &reqwest::blocking::Client,
+    rest_proxy_url: &str,
+    topic_name: &str,
+    auto_create: Option<&TopicConfig>,
+) -> Result<(), Box<dyn StdError>> {
+    let cluster_id = get_cluster_id(client, rest_proxy_url)?;
+    let res = send_idempotent(client.get(format!(
+        "{rest_proxy_url}/v3/clusters/{cluster_id}/topics/{topic_name}"
+    )))?;
+    match (res.status(), auto_create) {
+        (status, _) if status.is_success() => Ok(()),
+        (StatusCode::NOT_FOUND, Some(topic_config)) => {
+            log::info!("Topic {topic_name} does not exist, creating it.");
+            let topic_config = topic_config.clone().with_topic_name(topic_name);
+            create_topic(client, rest_proxy_url, &cluster_id, &topic_config)
+        }
+        (StatusCode::NOT_FOUND, None) => Err(Box::new(ErrorManagingTopic(format!(
+            "Topic {topic_name} does not exist, run --action create-topics first."
+        )))),
+        _ => Err(Box::new(ErrorManagingTopic(format!(
+            "Error checking topic {topic_name}: {}",
+            res.text()?
+        )))),
+    }
+}
+
+fn create_topic(
+    client: &reqwest::blocking::Client,
+    rest_proxy_url: &str,
+    cluster_id: &str,
+    topic_config: &TopicConfig,
+) -> Result<(), Box<dyn StdError>> {
     log::debug!("{topic_config:?}");
-    let res = client
-        .post(format!(
-            "{sasquatch_rest_proxy_url}/sasquatch-rest-proxy/v3/clusters/{cluster_id}/topics"
-        ))
-        .json(&topic_config)
-        .send()?;
+    let res = send_idempotent(
+        client
+            .post(format!("{rest_proxy_url}/v3/clusters/{cluster_id}/topics"))
+            .json(topic_config),
+    )?;
     log::debug!("{res:?}");
-    Ok(())
+    if res.status().is_success() {
+        log::info!("Created topic {}.", topic_config.get_topic_name());
+        return Ok(());
+    }
+    let text = res.text()?;
+    match serde_json::from_str::<KafkaError>(&text) {
+        Ok(error) if error.error_code == TOPIC_ALREADY_EXISTS => {
+            log::info!("Topic {} already exists.", topic_config.get_topic_name());
+            Ok(())
+        }
+        Ok(error) => Err(Box::new(ErrorManagingTopic(format!(
+            "Error creating topic {}: {}",
+            topic_config.get_topic_name(),
+            error.message
+        )))),
+        Err(_) => Err(Box::new(ErrorManagingTopic(format!(
+            "Error creating topic {}: {text}",
+            topic_config.get_topic_name()
+        )))),
+    }
+}
+
+fn get_cluster_id(
+    client: &reqwest::blocking::Client,
+    rest_proxy_url: &str,
+) -> Result<String, Box<dyn StdError>> {
+    let body = send_idempotent(
+        client
+            .get(format!("{rest_proxy_url}/v3/clusters"))
+            .header("content-type", "application/json"),
+    )?
+    .text()?;
+    let kafka_cluster_list: KafkaClusterList = serde_json::from_str(&body)?;
+    Ok(kafka_cluster_list.get_cluster_id().to_owned())
 }
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+
+use super::{
+    log_summary::AvroSchema,
+    schema::{get_namespace, FieldType, RecordSchema},
+};
+use crate::identity::{get_hostname, get_site};
+use chrono::Utc;
+
+/// Repositories of an environment that deviate from their base versions.
+///
+/// The lists of repositories and versions are comma separated, in the same
+/// order, so the record can be used for alerts in sasquatch. A record with
+/// no repositories means the environment is back to its base versions.
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct Drift {
+    timestamp: i64,
+    site: String,
+    host: String,
+    env_path: String,
+    drifted_count: i64,
+    repositories: String,
+    current_versions: String,
+    base_versions: String,
+}
+
+impl AvroSchema for Drift {
+    fn get_schema(&self) -> RecordSchema {
+        RecordSchema::new(&get_namespace(), "drift")
+            .with_field("timestamp", FieldType::Long)
+            .with_optional_field("site", FieldType::String)
+            .with_optional_field("host", FieldType::String)
+            .with_optional_field("env_path", FieldType::String)
+            .with_field("drifted_count", FieldType::Long)
+            .with_field("repositories", FieldType::String)
+            .with_field("current_versions", FieldType::String)
+            .with_field("base_versions", FieldType::String)
+    }
+
+    fn get_key(&self) -> Option<String> {
+        Some(format!("{}:{}", self.site, self.env_path))
+    }
+}
+
+impl Drift {
+    /// Create the record from the drifted repositories, with their base and
+    /// current versions.
+    pub fn from_btree_map(drift: &BTreeMap<String, (String, String)>) -> Drift {
+        let join = |field: fn(&(String, String)) -> &str| {
+            drift.values().map(field).collect::<Vec<&str>>().join(",")
+        };
+        Drift {
+            timestamp: Utc::now().timestamp_millis(),
+            site: get_site(),
+            host: get_hostname(),
+            env_path: "".to_owned(),
+            drifted_count: drift.len() as i64,
+            repositories: drift
+                .keys()
+                .map(|repository| repository.as_str())
+                .collect::<Vec<&str>>()
+                .join(","),
+            current_versions: join(|(_, current)| current),
+            base_versions: join(|(base, _)| base),
+        }
+    }
+    /// Set the path of the environment.
+    pub fn with_env_path(mut self, env_path: &str) -> Self {
+        self.env_path = env_path.to_owned();
+        self
+    }
+    pub fn get_drifted_count(&self) -> i64 {
+        self.drifted_count
+    }
+    pub fn get_repositories(&self) -> &str {
+        &self.repositories
+    }
+    pub fn get_topic_name() -> &'static str {
+        "drift"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Drift;
+    use crate::sasquatch::log_summary::AvroSchema;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_drift() {
+        let drift = Drift::from_btree_map(&BTreeMap::from_iter([
+            (
+                "ts_ofc".to_owned(),
+                ("2.0.0".to_owned(), "v2.1.0".to_owned()),
+            ),
+            (
+                "ts_wep".to_owned(),
+                ("1.0.0".to_owned(), "tickets/DM-1".to_owned()),
+            ),
+        ]));
+
+        assert_eq!(drift.get_drifted_count(), 2);
+        assert_eq!(drift.get_repositories(), "ts_ofc,ts_wep");
+        let record = serde_json::to_value(&drift).unwrap();
+        drift.get_schema().validate(&record).unwrap();
+        assert_eq!(record["current_versions"], "v2.1.0,tickets/DM-1");
+        assert_eq!(record["base_versions"], "2.0.0,1.0.0");
+    }
+}
@@ -0,0 +1,613 @@
+use super::{
+    http::{build_client, send_idempotent},
+    schema::{get_namespace, get_topic},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use lsst_efd_client::EfdAuth;
+use reqwest::blocking::Client;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::{env, error::Error, fs::read_to_string, path::Path};
+use thiserror::Error as ThisError;
+
+/// Row returned by a query, mapping column names to values.
+pub type Row = Map<String, Value>;
+
+#[derive(Debug, Deserialize, Default)]
+struct QueryResponse {
+    #[serde(default)]
+    results: Vec<StatementResult>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct StatementResult {
+    #[serde(default)]
+    series: Vec<Series>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct Series {
+    columns: Vec<String>,
+    values: Vec<Vec<Value>>,
+}
+
+#[derive(Clone, Debug, Eq, ThisError, PartialEq)]
+#[error("{0}")]
+pub struct EfdQueryError(String);
+
+/// Query of the records in a telemetry topic.
+#[derive(Clone, Debug, Default)]
+pub struct EfdQuery {
+    topic_name: String,
+    fields: Vec<String>,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    filters: Vec<(String, String)>,
+    descending: bool,
+    limit: Option<usize>,
+}
+
+/// Query API of the InfluxDB instance of the EFD.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EfdQueryApi {
+    /// InfluxDB v1 query endpoint, InfluxQL.
+    InfluxQl,
+    /// InfluxDB v2 query endpoint, Flux. The bucket defaults to the v1
+    /// database and retention policy of the namespace.
+    Flux { org: String, bucket: Option<String> },
+}
+
+/// Credentials to authenticate with the EFD.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EfdCredentials {
+    /// User name and password, as provided by the EFD auth service. With
+    /// Flux, the password is used as the token.
+    Basic { username: String, password: String },
+    /// Bearer token, e.g. for endpoints protected by Gafaelfawr.
+    Bearer(String),
+}
+
+/// Client to query the telemetry in the EFD.
+pub struct EfdClient {
+    base_url: String,
+    credentials: EfdCredentials,
+    query_api: EfdQueryApi,
+    client: Client,
+}
+
+impl EfdQuery {
+    /// Query all the fields of the records in the topic `topic_name`
+    /// (e.g. "action"), in the telemetry namespace.
+    pub fn new(topic_name: &str) -> EfdQuery {
+        EfdQuery {
+            topic_name: topic_name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Only return the given fields.
+    pub fn with_fields(mut self, fields: &[&str]) -> Self {
+        self.fields = fields.iter().map(|field| field.to_string()).collect();
+        self
+    }
+
+    /// Only return records from `start` on.
+    pub fn with_start(mut self, start: DateTime<Utc>) -> Self {
+        self.start = Some(start);
+        self
+    }
+
+    /// Only return records up to `end`.
+    pub fn with_end(mut self, end: DateTime<Utc>) -> Self {
+        self.end = Some(end);
+        self
+    }
+
+    /// Only return records where `field` equals `value`.
+    pub fn with_filter(mut self, field: &str, value: &str) -> Self {
+        self.filters.push((field.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Return the newest records first.
+    pub fn descending(mut self) -> Self {
+        self.descending = true;
+        self
+    }
+
+    /// Return at most `limit` records.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Flux script for the query, reading from `bucket`.
+    pub fn to_flux(&self, bucket: &str) -> String {
+        let start = match &self.start {
+            Some(start) => start.to_rfc3339_opts(SecondsFormat::Millis, true),
+            None => "0".to_owned(),
+        };
+        let stop = match &self.end {
+            Some(end) => end.to_rfc3339_opts(SecondsFormat::Millis, true),
+            None => "now()".to_owned(),
+        };
+        let mut lines = vec![
+            format!("from(bucket: {})", quote_flux_string(bucket)),
+            format!("  |> range(start: {start}, stop: {stop})"),
+            format!(
+                "  |> filter(fn: (r) => r._measurement == {})",
+                quote_flux_string(&get_topic(&self.topic_name))
+            ),
+            r#"  |> pivot(rowKey: ["_time"], columnKey: ["_field"], valueColumn: "_value")"#
+                .to_owned(),
+        ];
+        for (field, value) in &self.filters {
+            lines.push(format!(
+                "  |> filter(fn: (r) => r[{}] == {})",
+                quote_flux_string(field),
+                quote_flux_string(value)
+            ));
+        }
+        if !self.fields.is_empty() {
+            let columns: Vec<String> = ["_time"]
+                .iter()
+                .copied()
+                .chain(self.fields.iter().map(|field| field.as_str()))
+                .map(quote_flux_string)
+                .collect();
+            lines.push(format!("  |> keep(columns: [{}])", columns.join(", ")));
+        }
+        lines.push(format!(
+            r#"  |> sort(columns: ["_time"], desc: {})"#,
+            self.descending
+        ));
+        if let Some(limit) = self.limit {
+            lines.push(format!("  |> limit(n: {limit})"));
+        }
+        lines.join("\n")
+    }
+
+    /// InfluxQL statement for the query.
+    pub fn to_influxql(&self) -> String {
+        let fields = if self.fields.is_empty() {
+            "*".to_owned()
+        } else {
+            self.fields
+                .iter()
+                .map(|field| quote_identifier(field))
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        let mut query = format!(
+            r#"SELECT {fields} FROM {}."autogen".{}"#,
+            quote_identifier(&get_namespace()),
+            quote_identifier(&get_topic(&self.topic_name))
+        );
+
+        let mut conditions = Vec::new();
+        if let Some(start) = &self.start {
+            conditions.push(format!(
+                "time >= '{}'",
+                start.to_rfc3339_opts(SecondsFormat::Millis, true)
+            ));
+        }
+        if let Some(end) = &self.end {
+            conditions.push(format!(
+                "time <= '{}'",
+                end.to_rfc3339_opts(SecondsFormat::Millis, true)
+            ));
+        }
+        for (field, value) in &self.filters {
+            conditions.push(format!(
+                "{} = '{}'",
+                quote_identifier(field),
+                value.replace('\\', "\\\\").replace('\'', "\\'")
+            ));
+        }
+        if !conditions.is_empty() {
+            query.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+        if self.descending {
+            query.push_str(" ORDER BY time DESC");
+        }
+        if let Some(limit) = self.limit {
+            query.push_str(&format!(" LIMIT {limit}"));
+        }
+        query
+    }
+}
+
+impl EfdClient {
+    /// Create a client for the EFD instance `efd_name`, retrieving the
+    /// connection information and credentials from the EFD auth service.
+    ///
+    /// The query API is InfluxQL unless MANAGE_OBS_ENV_EFD_QUERY_API is
+    /// "flux", in which case MANAGE_OBS_ENV_EFD_ORG (and optionally
+    /// MANAGE_OBS_ENV_EFD_BUCKET) select where to read from.
+    ///
+    /// If a token is configured (see `read_token`) it is used instead of the
+    /// user name and password from the EFD auth service.
+    pub fn from_efd_name(efd_name: &str) -> Result<EfdClient, Box<dyn Error>> {
+        let efd_auth = EfdAuth::new_blocking(efd_name)?;
+        let query_api = match env::var("MANAGE_OBS_ENV_EFD_QUERY_API").as_deref() {
+            Ok("flux") => EfdQueryApi::Flux {
+                org: env::var("MANAGE_OBS_ENV_EFD_ORG").map_err(|_| {
+                    EfdQueryError(
+                        "MANAGE_OBS_ENV_EFD_ORG must be set to query the EFD with Flux.".to_owned(),
+                    )
+                })?,
+                bucket: env::var("MANAGE_OBS_ENV_EFD_BUCKET").ok(),
+            },
+            Ok("influxql") | Err(_) => EfdQueryApi::InfluxQl,
+            Ok(other) => {
+                return Err(Box::new(EfdQueryError(format!(
+                    "Invalid MANAGE_OBS_ENV_EFD_QUERY_API {other:?}, expected influxql or flux."
+                ))))
+            }
+        };
+        let credentials = match read_token()? {
+            Some(token) => EfdCredentials::Bearer(token),
+            None => EfdCredentials::Basic {
+                username: efd_auth.get_username().to_owned(),
+                password: efd_auth.get_password().to_owned(),
+            },
+        };
+        Ok(EfdClient {
+            base_url: format!(
+                "https://{}:{}/influxdb",
+                efd_auth.get_host(),
+                efd_auth.get_port(),
+            ),
+            credentials,
+            query_api,
+            client: build_client()?,
+        })
+    }
+
+    /// Use the given credentials.
+    pub fn with_credentials(mut self, credentials: EfdCredentials) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Use the given query API.
+    pub fn with_query_api(mut self, query_api: EfdQueryApi) -> Self {
+        self.query_api = query_api;
+        self
+    }
+
+    /// Run an InfluxQL statement, returning the rows of all the series in
+    /// the result.
+    pub fn query(&self, query: &str) -> Result<Vec<Row>, Box<dyn Error>> {
+        log::debug!("Querying the EFD: {query}");
+        let request = self.client.get(format!("{}/query", self.base_url));
+        let request = match &self.credentials {
+            EfdCredentials::Basic { username, password } => {
+                request.basic_auth(username, Some(password))
+            }
+            EfdCredentials::Bearer(token) => request.bearer_auth(token),
+        };
+        let response = send_idempotent(request.query(&[("db", "efd"), ("q", query)]))?;
+
+        if response.status().is_success() {
+            let text = response.text()?;
+            Ok(parse_rows(&text)?)
+        } else {
+            Err(Box::new(EfdQueryError(format!("Error: {:?}", response))))
+        }
+    }
+
+    /// Run a Flux script in `org`, returning the rows of all the tables in
+    /// the result.
+    pub fn query_flux(&self, org: &str, script: &str) -> Result<Vec<Row>, Box<dyn Error>> {
+        log::debug!("Querying the EFD: {script}");
+        let request = self
+            .client
+            .post(format!("{}/api/v2/query", self.base_url))
+            .header(
+                "Authorization",
+                match &self.credentials {
+                    EfdCredentials::Basic { password, .. } => format!("Token {password}"),
+                    EfdCredentials::Bearer(token) => format!("Bearer {token}"),
+                },
+            )
+            .header("Content-Type", "application/vnd.flux")
+            .header("Accept", "application/csv")
+            .query(&[("org", org)])
+            .body(script.to_owned());
+        let response = send_idempotent(request)?;
+
+        if response.status().is_success() {
+            let text = response.text()?;
+            Ok(parse_flux_csv(&text)?)
+        } else {
+            Err(Box::new(EfdQueryError(format!("Error: {:?}", response))))
+        }
+    }
+
+    /// Run the query, returning the rows.
+    pub fn query_topic(&self, query: &EfdQuery) -> Result<Vec<Row>, Box<dyn Error>> {
+        match &self.query_api {
+            EfdQueryApi::InfluxQl => self.query(&query.to_influxql()),
+            EfdQueryApi::Flux { org, bucket } => {
+                let bucket = match bucket {
+                    Some(bucket) => bucket.to_owned(),
+                    None => format!("{}/autogen", get_namespace()),
+                };
+                self.query_flux(org, &query.to_flux(&bucket))
+            }
+        }
+    }
+
+    /// Run the query, deserializing the rows into records.
+    ///
+    /// The time column, added by the EFD, is not passed on to the records.
+    pub fn query_records<T: DeserializeOwned>(
+        &self,
+        query: &EfdQuery,
+    ) -> Result<Vec<T>, Box<dyn Error>> {
+        rows_to_records(self.query_topic(query)?)
+    }
+}
+
+/// Read the token to query the EFD with, if any.
+///
+/// The token is taken from MANAGE_OBS_ENV_EFD_TOKEN or, if that is not set,
+/// read from the file in MANAGE_OBS_ENV_EFD_TOKEN_FILE (e.g. a mounted
+/// secret).
+pub fn read_token() -> Result<Option<String>, EfdQueryError> {
+    if let Ok(token) = env::var("MANAGE_OBS_ENV_EFD_TOKEN") {
+        return Ok(Some(token));
+    }
+    match env::var("MANAGE_OBS_ENV_EFD_TOKEN_FILE") {
+        Ok(token_file) => read_token_file(Path::new(&token_file)).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+fn read_token_file(token_file: &Path) -> Result<String, EfdQueryError> {
+    let token = read_to_string(token_file).map_err(|error| {
+        EfdQueryError(format!(
+            "Failed to read EFD token from {token_file:?}: {error}"
+        ))
+    })?;
+    let token = token.trim();
+    if token.is_empty() {
+        Err(EfdQueryError(format!(
+            "EFD token file {token_file:?} is empty."
+        )))
+    } else {
+        Ok(token.to_owned())
+    }
+}
+
+/// Parse the response of the query endpoint into rows.
+pub fn parse_rows(text: &str) -> Result<Vec<Row>, EfdQueryError> {
+    let response: QueryResponse = serde_json::from_str(text)
+        .map_err(|error| EfdQueryError(format!("Error: {error:?} parsing response: {text:?}")))?;
+    let mut rows = Vec::new();
+    for result in response.results {
+        if let Some(error) = result.error {
+            return Err(EfdQueryError(format!("Query failed: {error}")));
+        }
+        for series in result.series {
+            rows.extend(
+                series
+                    .values
+                    .into_iter()
+                    .map(|values| series.columns.iter().cloned().zip(values).collect::<Row>()),
+            );
+        }
+    }
+    Ok(rows)
+}
+
+/// Parse the annotated CSV returned by the Flux query endpoint into rows.
+///
+/// Values are converted according to the datatype annotation and the
+/// "_time" column is returned as "time", as with InfluxQL. The columns
+/// Flux adds to every table (result, table, _start, _stop and
+/// _measurement) are not included.
+pub fn parse_flux_csv(text: &str) -> Result<Vec<Row>, EfdQueryError> {
+    let mut rows = Vec::new();
+    let mut datatypes: Vec<String> = Vec::new();
+    let mut header: Option<Vec<String>> = None;
+    for line in text.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            // Tables are separated by an empty line and have their own
+            // annotations and header.
+            header = None;
+            datatypes.clear();
+            continue;
+        }
+        let cells = split_csv_line(line);
+        if cells[0].starts_with('#') {
+            if cells[0] == "#datatype" {
+                datatypes = cells;
+            }
+            continue;
+        }
+        let Some(columns) = &header else {
+            header = Some(cells);
+            continue;
+        };
+        if columns.get(1).map(|column| column.as_str()) == Some("error") {
+            return Err(EfdQueryError(format!(
+                "Query failed: {}",
+                cells.get(1).cloned().unwrap_or_default()
+            )));
+        }
+        let mut row = Row::new();
+        for (index, (column, cell)) in columns.iter().zip(cells).enumerate() {
+            let column = match column.as_str() {
+                "" | "result" | "table" | "_start" | "_stop" | "_measurement" => continue,
+                "_time" => "time",
+                column => column,
+            };
+            let value = match datatypes.get(index).map(|datatype| datatype.as_str()) {
+                Some("long") | Some("unsignedLong") => cell
+                    .parse::<i64>()
+                    .map(Value::from)
+                    .unwrap_or(Value::from(cell)),
+                Some("double") => cell
+                    .parse::<f64>()
+                    .map(Value::from)
+                    .unwrap_or(Value::from(cell)),
+                Some("boolean") => Value::from(cell == "true"),
+                _ => Value::from(cell),
+            };
+            row.insert(column.to_owned(), value);
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Split a CSV line into cells, handling quoted cells.
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => cells.push(std::mem::take(&mut cell)),
+            c => cell.push(c),
+        }
+    }
+    cells.push(cell);
+    cells
+}
+
+fn quote_flux_string(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace("${", "\\${")
+    )
+}
+
+fn rows_to_records<T: DeserializeOwned>(rows: Vec<Row>) -> Result<Vec<T>, Box<dyn Error>> {
+    rows.into_iter()
+        .map(|mut row| {
+            row.remove("time");
+            serde_json::from_value(Value::Object(row)).map_err(|error| error.into())
+        })
+        .collect()
+}
+
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_flux_csv, parse_rows, read_token_file, rows_to_records, EfdQuery};
+    use crate::sasquatch::run_branch::RunBranch;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_to_influxql() {
+        let query = EfdQuery::new("action")
+            .with_fields(&["timestamp", "action"])
+            .with_start(Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap())
+            .with_filter("repository", "ts_wep")
+            .with_filter("user", "o'brien")
+            .descending()
+            .with_limit(10);
+
+        assert_eq!(
+            query.to_influxql(),
+            r#"SELECT "timestamp", "action" FROM "lsst.obsenv"."autogen"."lsst.obsenv.action" WHERE time >= '2024-05-01T12:00:00.000Z' AND "repository" = 'ts_wep' AND "user" = 'o\'brien' ORDER BY time DESC LIMIT 10"#
+        );
+        assert_eq!(
+            EfdQuery::new("summary").to_influxql(),
+            r#"SELECT * FROM "lsst.obsenv"."autogen"."lsst.obsenv.summary""#
+        );
+    }
+
+    #[test]
+    fn test_parse_rows() {
+        let text = r#"{"results":[{"statement_id":0,"series":[{"name":"lsst.obsenv.run_branch","columns":["time","timestamp","branch_name"],"values":[["2024-05-01T12:00:00Z",1714564800000,"tickets/DM-1"]]}]}]}"#;
+
+        let rows = parse_rows(text).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["branch_name"], "tickets/DM-1");
+
+        let records: Vec<RunBranch> = rows_to_records(rows).unwrap();
+        assert_eq!(records[0].get_branch_name(), "tickets/DM-1");
+
+        assert!(parse_rows(r#"{"results":[{"statement_id":0}]}"#)
+            .unwrap()
+            .is_empty());
+        assert!(parse_rows(r#"{"results":[{"statement_id":0,"error":"bad query"}]}"#).is_err());
+    }
+
+    #[test]
+    fn test_to_flux() {
+        let query = EfdQuery::new("run_branch")
+            .with_fields(&["timestamp", "branch_name"])
+            .with_filter("site", "summit")
+            .descending()
+            .with_limit(1);
+
+        assert_eq!(
+            query.to_flux("lsst.obsenv/autogen"),
+            [
+                r#"from(bucket: "lsst.obsenv/autogen")"#,
+                r#"  |> range(start: 0, stop: now())"#,
+                r#"  |> filter(fn: (r) => r._measurement == "lsst.obsenv.run_branch")"#,
+                r#"  |> pivot(rowKey: ["_time"], columnKey: ["_field"], valueColumn: "_value")"#,
+                r#"  |> filter(fn: (r) => r["site"] == "summit")"#,
+                r#"  |> keep(columns: ["_time", "timestamp", "branch_name"])"#,
+                r#"  |> sort(columns: ["_time"], desc: true)"#,
+                r#"  |> limit(n: 1)"#,
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_parse_flux_csv() {
+        let text = "#group,false,false,false,false,false\r\n\
+            #datatype,string,long,dateTime:RFC3339,long,string\r\n\
+            #default,_result,,,,\r\n\
+            ,result,table,_time,timestamp,branch_name\r\n\
+            ,,0,2024-05-01T12:00:00Z,1714564800000,\"tickets/DM-1,2\"\r\n\
+            \r\n";
+
+        let rows = parse_flux_csv(text).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["time"], "2024-05-01T12:00:00Z");
+        assert_eq!(rows[0]["timestamp"], 1714564800000_i64);
+        assert!(rows[0].get("table").is_none());
+
+        let records: Vec<RunBranch> = rows_to_records(rows).unwrap();
+        assert_eq!(records[0].get_branch_name(), "tickets/DM-1,2");
+
+        assert!(parse_flux_csv(",error,reference\r\n,bad query,\r\n").is_err());
+    }
+
+    #[test]
+    fn test_read_token_file() {
+        let token_file =
+            std::env::temp_dir().join(format!("obs_env_efd_token_{}", std::process::id()));
+        std::fs::write(&token_file, "secret-token\n").unwrap();
+        assert_eq!(read_token_file(&token_file).unwrap(), "secret-token");
+
+        std::fs::write(&token_file, "\n").unwrap();
+        assert!(read_token_file(&token_file).is_err());
+
+        std::fs::remove_file(&token_file).unwrap();
+        assert!(read_token_file(&token_file).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use reqwest::{
+    blocking::{Client, RequestBuilder, Response},
+    header::HeaderMap,
+};
+use std::{env, str::FromStr, thread, time::Duration};
+
+const DEFAULT_CONNECT_TIMEOUT: u64 = 5;
+const DEFAULT_TIMEOUT: u64 = 30;
+const DEFAULT_RETRIES: u32 = 2;
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Base URL of the sasquatch REST proxy, including its path.
+///
+/// This is SASQUATCH_REST_PROXY_BASE_URL if set, for deployments that expose
+/// the proxy at a non-standard path, and otherwise the standard
+/// "/sasquatch-rest-proxy" path of SASQUATCH_REST_PROXY_URL.
+pub fn get_rest_proxy_url() -> Option<String> {
+    match env::var("SASQUATCH_REST_PROXY_BASE_URL") {
+        Ok(base_url) if !base_url.is_empty() => Some(base_url.trim_end_matches('/').to_owned()),
+        _ => env::var("SASQUATCH_REST_PROXY_URL")
+            .ok()
+            .map(|url| format!("{}/sasquatch-rest-proxy", url.trim_end_matches('/'))),
+    }
+}
+
+/// Base URLs of all the sasquatch REST proxies the telemetry is sent to.
+///
+/// These are the REST proxy from `get_rest_proxy_url` followed by the
+/// comma separated base URLs in SASQUATCH_REST_PROXY_MIRROR_URLS, e.g. to
+/// also send the telemetry to a replica of the EFD.
+pub fn get_rest_proxy_urls() -> Vec<String> {
+    let mirror_urls = env::var("SASQUATCH_REST_PROXY_MIRROR_URLS").unwrap_or_default();
+    get_rest_proxy_url()
+        .into_iter()
+        .chain(
+            mirror_urls
+                .split(',')
+                .map(|url| url.trim().trim_end_matches('/').to_owned())
+                .filter(|url| !url.is_empty()),
+        )
+        .collect()
+}
+
+/// URL of the schema registry.
+///
+/// This is SASQUATCH_SCHEMA_REGISTRY_URL if set, and otherwise the standard
+/// "/schema-registry" path of SASQUATCH_REST_PROXY_URL.
+pub fn get_schema_registry_url() -> Option<String> {
+    env::var("SASQUATCH_SCHEMA_REGISTRY_URL").ok().or_else(|| {
+        env::var("SASQUATCH_REST_PROXY_URL")
+            .ok()
+            .map(|url| format!("{}/schema-registry", url.trim_end_matches('/')))
+    })
+}
+
+/// Connect timeout of the HTTP requests, from
+/// MANAGE_OBS_ENV_HTTP_CONNECT_TIMEOUT in seconds (default 5).
+pub fn get_connect_timeout() -> Duration {
+    Duration::from_secs(get_env_or(
+        "MANAGE_OBS_ENV_HTTP_CONNECT_TIMEOUT",
+        DEFAULT_CONNECT_TIMEOUT,
+    ))
+}
+
+/// Overall timeout of the HTTP requests, from MANAGE_OBS_ENV_HTTP_TIMEOUT
+/// in seconds (default 30).
+pub fn get_timeout() -> Duration {
+    Duration::from_secs(get_env_or("MANAGE_OBS_ENV_HTTP_TIMEOUT", DEFAULT_TIMEOUT))
+}
+
+/// Build the HTTP client used to talk to sasquatch and the EFD, with the
+/// timeouts from `get_connect_timeout` and `get_timeout`.
+pub fn build_client() -> reqwest::Result<Client> {
+    build_client_with(get_connect_timeout(), get_timeout(), HeaderMap::new())
+}
+
+/// Build an HTTP client with the given timeouts, sending `headers` (e.g.
+/// the authorization) with every request.
+pub fn build_client_with(
+    connect_timeout: Duration,
+    timeout: Duration,
+    headers: HeaderMap,
+) -> reqwest::Result<Client> {
+    Client::builder()
+        .connect_timeout(connect_timeout)
+        .timeout(timeout)
+        .default_headers(headers)
+        .build()
+}
+
+/// Send an idempotent request, retrying on connection errors, timeouts and
+/// server errors.
+///
+/// The number of retries is taken from MANAGE_OBS_ENV_HTTP_RETRIES
+/// (default 2), with exponential backoff between them. Requests that can't
+/// be retried, e.g. because their body is a stream, are sent once.
+pub fn send_idempotent(request: RequestBuilder) -> reqwest::Result<Response> {
+    let retries = get_env_or("MANAGE_OBS_ENV_HTTP_RETRIES", DEFAULT_RETRIES);
+    let mut attempt = 0;
+    loop {
+        let Some(retry_request) = request.try_clone() else {
+            return request.send();
+        };
+        match retry_request.send() {
+            Ok(res) if res.status().is_server_error() && attempt < retries => {
+                log::warn!(
+                    "Request to {} failed with {}, retrying.",
+                    res.url(),
+                    res.status()
+                );
+            }
+            Err(error) if (error.is_connect() || error.is_timeout()) && attempt < retries => {
+                log::warn!("Request failed, retrying: {error}");
+            }
+            result => return result,
+        }
+        thread::sleep(get_backoff(attempt));
+        attempt += 1;
+    }
+}
+
+/// Time to wait before retry number `attempt` (starting at 0).
+fn get_backoff(attempt: u32) -> Duration {
+    RETRY_BACKOFF.saturating_mul(2_u32.saturating_pow(attempt))
+}
+
+fn get_env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            log::warn!("Invalid value {value:?} for {name}, using the default.");
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_client, get_backoff, send_idempotent};
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(get_backoff(0), Duration::from_millis(500));
+        assert_eq!(get_backoff(2), Duration::from_millis(2000));
+    }
+
+    #[test]
+    fn test_send_idempotent_retries_server_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            for status in ["503 Service Unavailable", "200 OK"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buffer = [0; 4096];
+                let _ = stream.read(&mut buffer).unwrap();
+                stream
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                        )
+                        .as_bytes(),
+                    )
+                    .unwrap();
+            }
+        });
+
+        let client = build_client().unwrap();
+        let res = send_idempotent(client.get(url)).unwrap();
+
+        assert!(res.status().is_success());
+        server.join().unwrap();
+    }
+}
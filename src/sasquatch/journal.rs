@@ -0,0 +1,188 @@
+use std::{
+    fs::{read_to_string, remove_file, rename, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    drift::Drift,
+    lock_state::LockState,
+    log_summary::{ActionData, Summary},
+    metrics::ActionMetrics,
+    run_branch::RunBranch,
+    sink::TelemetrySink,
+};
+use crate::error::ObsEnvError;
+use serde::ser::Serialize;
+use serde_json::Value;
+
+/// Name of the journal file, in the environment, used by default.
+pub const JOURNAL_FILE_NAME: &str = ".obs_env_telemetry.jsonl";
+
+const SYNCING_EXTENSION: &str = "syncing";
+
+/// Entry of the journal: a record and the topic it goes to.
+#[derive(Debug, Deserialize, Serialize)]
+struct JournalEntry {
+    topic_name: String,
+    record: Value,
+}
+
+/// Local journal of the telemetry records, used instead of sasquatch when
+/// running offline.
+///
+/// Records are appended to a JSON lines file, one record per line, and are
+/// sent to sasquatch later with `sync`.
+#[derive(Clone, Debug)]
+pub struct TelemetryJournal {
+    path: PathBuf,
+}
+
+impl TelemetryJournal {
+    pub fn new(path: &Path) -> TelemetryJournal {
+        TelemetryJournal {
+            path: path.to_path_buf(),
+        }
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `record` to the journal.
+    fn append<T: Serialize>(&self, record: &T, topic_name: &str) -> Result<(), String> {
+        let entry = JournalEntry {
+            topic_name: topic_name.to_owned(),
+            record: serde_json::to_value(record)
+                .map_err(|error| format!("Error serializing {topic_name} record: {error}"))?,
+        };
+        let line = serde_json::to_string(&entry)
+            .map_err(|error| format!("Error serializing {topic_name} record: {error}"))?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{line}"))
+            .map_err(|error| {
+                format!(
+                    "Failed to write to telemetry journal {:?}: {error}",
+                    self.path
+                )
+            })
+    }
+
+    /// Send the records in the journal to `sink`, oldest first, and remove
+    /// them from the journal.
+    ///
+    /// The journal is moved aside while it is sent, so records appended in
+    /// the meantime are kept for the next sync. Records that can't be read
+    /// are logged and dropped. Returns the number of records sent.
+    pub fn sync(&self, sink: &dyn TelemetrySink) -> Result<usize, ObsEnvError> {
+        let syncing = self.path.with_extension(SYNCING_EXTENSION);
+        // A journal left aside by an interrupted sync is sent first.
+        if !syncing.exists() {
+            if !self.path.exists() {
+                return Ok(0);
+            }
+            rename(&self.path, &syncing).map_err(|error| {
+                ObsEnvError::ERROR(format!(
+                    "Failed to claim telemetry journal {:?}: {error}",
+                    self.path
+                ))
+            })?;
+        }
+        let content = read_to_string(&syncing).map_err(|error| {
+            ObsEnvError::ERROR(format!(
+                "Failed to read telemetry journal {syncing:?}: {error}"
+            ))
+        })?;
+        let mut sent = 0;
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str::<JournalEntry>(line)
+                .map_err(|error| error.to_string())
+                .and_then(|entry| send_entry(sink, entry))
+            {
+                Ok(()) => sent += 1,
+                Err(error) => log::error!("Dropping journal record {line}: {error}"),
+            }
+        }
+        remove_file(&syncing).map_err(|error| {
+            ObsEnvError::ERROR(format!(
+                "Failed to remove telemetry journal {syncing:?}: {error}"
+            ))
+        })?;
+        Ok(sent)
+    }
+}
+
+/// Send the record of `entry` to `sink`, as the record type of its topic.
+fn send_entry(sink: &dyn TelemetrySink, entry: JournalEntry) -> Result<(), String> {
+    fn parse<T: serde::de::DeserializeOwned>(record: Value) -> Result<T, String> {
+        serde_json::from_value(record).map_err(|error| error.to_string())
+    }
+    let JournalEntry { topic_name, record } = entry;
+    match topic_name.as_str() {
+        name if name == ActionData::get_topic_name() => sink.send_action(parse(record)?),
+        name if name == Summary::get_topic_name() => sink.send_summary(parse(record)?),
+        name if name == RunBranch::get_topic_name() => sink.send_run_branch(parse(record)?),
+        name if name == Drift::get_topic_name() => sink.send_drift(parse(record)?),
+        name if name == LockState::get_topic_name() => sink.send_lock_state(parse(record)?),
+        name if name == ActionMetrics::get_topic_name() => sink.send_metrics(parse(record)?),
+        _ => Err(format!("Unknown topic {topic_name}")),
+    }
+}
+
+impl TelemetrySink for TelemetryJournal {
+    fn send_action(&self, action_data: ActionData) -> Result<(), String> {
+        self.append(&action_data, ActionData::get_topic_name())
+    }
+    fn send_summary(&self, summary: Summary) -> Result<(), String> {
+        self.append(&summary, Summary::get_topic_name())
+    }
+    fn send_run_branch(&self, run_branch: RunBranch) -> Result<(), String> {
+        self.append(&run_branch, RunBranch::get_topic_name())
+    }
+    fn send_drift(&self, drift: Drift) -> Result<(), String> {
+        self.append(&drift, Drift::get_topic_name())
+    }
+    fn send_lock_state(&self, lock_state: LockState) -> Result<(), String> {
+        self.append(&lock_state, LockState::get_topic_name())
+    }
+    fn send_metrics(&self, metrics: ActionMetrics) -> Result<(), String> {
+        self.append(&metrics, ActionMetrics::get_topic_name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TelemetryJournal;
+    use crate::sasquatch::{
+        run_branch::RunBranch,
+        sink::{TelemetryRecorder, TelemetrySink},
+    };
+    use std::{env, fs, process};
+
+    #[test]
+    fn test_journal_sync() {
+        let path = env::temp_dir().join(format!("obs_env_journal_{}.jsonl", process::id()));
+        let journal = TelemetryJournal::new(&path);
+        journal
+            .send_run_branch(RunBranch::new("tickets/DM-1"))
+            .unwrap();
+        journal.send_run_branch(RunBranch::new("")).unwrap();
+        fs::write(
+            &path,
+            fs::read_to_string(&path).unwrap() + "{\"topic_name\": \"unknown\"}\n",
+        )
+        .unwrap();
+
+        let recorder = TelemetryRecorder::new();
+        assert_eq!(journal.sync(&recorder).unwrap(), 2);
+
+        let records = recorder.get_records(RunBranch::get_topic_name());
+        assert_eq!(records[0]["branch_name"], "tickets/DM-1");
+        assert_eq!(records[1]["branch_name"], "");
+        assert!(!path.exists());
+        assert_eq!(journal.sync(&recorder).unwrap(), 0);
+    }
+}
@@ -0,0 +1,149 @@
+#[cfg(feature = "telemetry")]
+use std::error::Error;
+
+#[cfg(feature = "telemetry")]
+use super::efd_client::{EfdClient, EfdQuery};
+use super::{
+    log_summary::AvroSchema,
+    schema::{get_namespace, FieldType, RecordSchema},
+};
+use crate::{env_lock::LockInfo, identity::get_site};
+use chrono::Utc;
+
+/// Lock or unlock event of the environment lock.
+///
+/// The latest event of an environment tells whether it is locked, and by
+/// whom.
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct LockState {
+    timestamp: i64,
+    /// "lock" or "unlock".
+    event: String,
+    site: String,
+    env_path: String,
+    /// User holding the lock, or that held it for unlock events.
+    holder: String,
+    host: String,
+    pid: i64,
+    /// Action the lock was acquired for.
+    reason: String,
+    /// Time the lock was acquired, in milliseconds since the epoch.
+    locked_since: i64,
+}
+
+impl AvroSchema for LockState {
+    fn get_schema(&self) -> RecordSchema {
+        RecordSchema::new(&get_namespace(), "lock")
+            .with_field("timestamp", FieldType::Long)
+            .with_field("event", FieldType::String)
+            .with_optional_field("site", FieldType::String)
+            .with_optional_field("env_path", FieldType::String)
+            .with_optional_field("holder", FieldType::String)
+            .with_optional_field("host", FieldType::String)
+            .with_optional_field("pid", FieldType::Long)
+            .with_optional_field("reason", FieldType::String)
+            .with_optional_field("locked_since", FieldType::Long)
+    }
+
+    fn get_key(&self) -> Option<String> {
+        Some(format!("{}:{}", self.site, self.env_path))
+    }
+}
+
+impl LockState {
+    /// Event for the lock `holder` acquired on the environment at
+    /// `env_path`.
+    pub fn locked(env_path: &str, holder: &LockInfo) -> LockState {
+        LockState {
+            event: "lock".to_owned(),
+            ..LockState::unlocked(env_path, Some(holder))
+        }
+    }
+    /// Event for the environment at `env_path` being unlocked, with the
+    /// previous lock holder if known.
+    pub fn unlocked(env_path: &str, holder: Option<&LockInfo>) -> LockState {
+        let mut lock_state = LockState {
+            timestamp: Utc::now().timestamp_millis(),
+            event: "unlock".to_owned(),
+            site: get_site(),
+            env_path: env_path.to_owned(),
+            ..Default::default()
+        };
+        if let Some(holder) = holder {
+            lock_state.holder = holder.owner.to_owned();
+            lock_state.host = holder.host.to_owned();
+            lock_state.pid = holder.pid.into();
+            lock_state.reason = holder.action.to_owned();
+            lock_state.locked_since = holder.timestamp;
+        }
+        lock_state
+    }
+    pub fn is_locked(&self) -> bool {
+        self.event == "lock"
+    }
+    pub fn get_timestamp(&self) -> i64 {
+        self.timestamp
+    }
+    pub fn get_holder(&self) -> &str {
+        &self.holder
+    }
+    pub fn get_host(&self) -> &str {
+        &self.host
+    }
+    pub fn get_reason(&self) -> &str {
+        &self.reason
+    }
+    pub fn get_locked_since(&self) -> i64 {
+        self.locked_since
+    }
+    pub fn get_topic_name() -> &'static str {
+        "lock"
+    }
+    /// Retrieve the latest lock event of the environment at `env_path`
+    /// registered in the EFD instance `efd_name`, None if there is none.
+    #[cfg(feature = "telemetry")]
+    pub fn retrieve_from_efd(
+        efd_name: &str,
+        env_path: &str,
+    ) -> Result<Option<LockState>, Box<dyn Error>> {
+        let efd_client = EfdClient::from_efd_name(efd_name)?;
+        let query = EfdQuery::new(LockState::get_topic_name())
+            .with_filter("env_path", env_path)
+            .descending()
+            .with_limit(1);
+        Ok(efd_client
+            .query_records::<LockState>(&query)?
+            .into_iter()
+            .next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LockState;
+    use crate::{env_lock::LockInfo, sasquatch::log_summary::AvroSchema};
+
+    #[test]
+    fn test_lock_state() {
+        let holder = LockInfo {
+            owner: "someone".to_owned(),
+            host: "host".to_owned(),
+            pid: 42,
+            timestamp: 1000,
+            action: "Reset".to_owned(),
+        };
+
+        let locked = LockState::locked("/obs-env", &holder);
+        assert!(locked.is_locked());
+        assert_eq!(locked.get_holder(), "someone");
+        assert_eq!(locked.get_locked_since(), 1000);
+        let record = serde_json::to_value(&locked).unwrap();
+        locked.get_schema().validate(&record).unwrap();
+
+        let unlocked = LockState::unlocked("/obs-env", None);
+        assert!(!unlocked.is_locked());
+        let record = serde_json::to_value(&unlocked).unwrap();
+        unlocked.get_schema().validate(&record).unwrap();
+    }
+}
@@ -1,148 +1,417 @@
-use crate::error::ObsEnvError;
+#[cfg(feature = "telemetry")]
+use super::efd_client::{EfdClient, EfdQuery};
+use super::schema::{get_namespace, FieldType, RecordSchema};
+use crate::{
+    error::ObsEnvError,
+    identity::{get_hostname, get_site, get_user},
+};
 use chrono::Utc;
-use std::{collections::BTreeMap, env};
+use serde::ser::Serialize;
+use sha2::{Digest, Sha256};
+#[cfg(feature = "telemetry")]
+use std::error::Error;
+use std::{collections::BTreeMap, time::Duration};
 
 pub trait AvroSchema {
-    fn get_avro_schema(&self) -> String;
+    /// Schema of the record.
+    fn get_schema(&self) -> RecordSchema;
+    /// JSON definition of the schema of the record.
+    fn get_avro_schema(&self) -> String {
+        self.get_schema().to_string()
+    }
+    /// Kafka key of the record, if any.
+    ///
+    /// Records with the same key describe the same thing (e.g. the run
+    /// branch of a site), so topics with keyed records can use log
+    /// compaction to keep only the latest state.
+    fn get_key(&self) -> Option<String> {
+        None
+    }
 }
 
+/// Avro schema of the record keys.
+const KEY_SCHEMA: &str = r#""string""#;
+
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct Payload<T>
 where
     T: AvroSchema,
 {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    key_schema: Option<String>,
     value_schema: String,
     records: Vec<Record<T>>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct Record<T> {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    key: Option<String>,
     value: T,
 }
 
+impl<T: AvroSchema + Serialize> Payload<T> {
+    /// Check that all the records match their schema, so a mismatch is
+    /// reported locally instead of being rejected by the REST proxy.
+    pub fn validate(&self) -> Result<(), String> {
+        for record in &self.records {
+            let value = serde_json::to_value(&record.value)
+                .map_err(|error| format!("Error serializing record: {error}"))?;
+            record.value.get_schema().validate(&value)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Default)]
+#[serde(default)]
 pub struct ActionData {
     timestamp: i64,
     action: String,
     repository: String,
     branch_name: String,
     user: String,
+    /// User the action was run for, if different from `user`.
+    acting_user: String,
+    host: String,
+    site: String,
+    env_path: String,
+    result: String,
+    error_message: String,
+    duration_ms: i64,
 }
 
+/// Versions of all repositories in the environment.
+///
+/// There is one field per repository, named after the repository (see
+/// `get_field_name`), so the record, and its schema, follow the list of
+/// repositories in the environment.
+///
+/// The checksum of the versions (see `compute_checksum`) allows comparing
+/// the state of environments without comparing every field.
 #[derive(Debug, Deserialize, Serialize, Default)]
+#[serde(default)]
 pub struct Summary {
     timestamp: i64,
-    spectractor: String,
-    atmospec: String,
-    cwfs: String,
-    summit_extras: String,
-    summit_utils: String,
-    ts_config_attcs: String,
-    ts_config_mttcs: String,
-    ts_config_ocs: String,
-    ts_externalscripts: String,
-    ts_observatory_control: String,
-    ts_observing_utilities: String,
-    ts_standardscripts: String,
-    ts_maintel_standardscripts: String,
-    ts_auxtel_standardscripts: String,
-    ts_wep: String,
+    site: String,
+    host: String,
+    env_path: String,
+    checksum: String,
+    #[serde(flatten)]
+    versions: BTreeMap<String, String>,
 }
 
 impl AvroSchema for Summary {
-    fn get_avro_schema(&self) -> String {
-        r#"{"namespace": "lsst.obsenv","type": "record","name": "summary","fields": [{"name": "timestamp", "type": "long"},{"name": "spectractor", "type": "string"},{"name": "atmospec", "type": "string"},{"name": "cwfs", "type": "string"},{"name": "summit_extras", "type": "string"},{"name": "summit_utils", "type": "string"},{"name": "ts_config_attcs", "type": "string"},{"name": "ts_config_mttcs", "type": "string"},{"name": "ts_config_ocs", "type": "string"},{"name": "ts_externalscripts", "type": "string"},{"name": "ts_observatory_control", "type": "string"},{"name": "ts_observing_utilities", "type": "string"},{"name": "ts_standardscripts", "type": "string"},{"name": "ts_maintel_standardscripts", "type": "string"},{"name": "ts_auxtel_standardscripts", "type": "string"},{"name": "ts_wep", "type": "string"}]}"#
-        .to_owned()
+    fn get_schema(&self) -> RecordSchema {
+        // Repository fields have a default so adding a repository keeps the
+        // schema backward compatible.
+        self.versions.keys().fold(
+            RecordSchema::new(&get_namespace(), "summary")
+                .with_field("timestamp", FieldType::Long)
+                .with_optional_field("site", FieldType::String)
+                .with_optional_field("host", FieldType::String)
+                .with_optional_field("env_path", FieldType::String)
+                .with_optional_field("checksum", FieldType::String),
+            |schema, name| schema.with_optional_field(name, FieldType::String),
+        )
+    }
+
+    fn get_key(&self) -> Option<String> {
+        Some(format!("{}:{}", self.site, self.env_path))
     }
 }
 
 impl AvroSchema for ActionData {
-    fn get_avro_schema(&self) -> String {
-        r#"{"namespace": "lsst.obsenv","type": "record","name": "action","fields": [{"name": "timestamp", "type": "long"},{"name": "action", "type": "string"},{"name": "repository", "type": "string"},{"name": "branch_name", "type": "string"},{"name": "user", "type": "string"}]}"#.to_owned()
+    fn get_schema(&self) -> RecordSchema {
+        RecordSchema::new(&get_namespace(), "action")
+            .with_field("timestamp", FieldType::Long)
+            .with_field("action", FieldType::String)
+            .with_field("repository", FieldType::String)
+            .with_field("branch_name", FieldType::String)
+            .with_field("user", FieldType::String)
+            .with_optional_field("host", FieldType::String)
+            .with_optional_field("site", FieldType::String)
+            .with_optional_field("env_path", FieldType::String)
+            .with_optional_field("result", FieldType::String)
+            .with_optional_field("error_message", FieldType::String)
+            .with_optional_field("duration_ms", FieldType::Long)
+            .with_optional_field("acting_user", FieldType::String)
     }
-}
 
-macro_rules! extract_value {
-    ($item:expr, $container:expr) => {
-        if let Some(value) = $container.get($item) {
-            match value {
-                Ok(value) => value.to_owned(),
-                Err(error) => error.to_string(),
-            }
-        } else {
-            "Unknown".to_owned()
-        }
-    };
+    // Every action is its own record, so the key is unique to keep the full
+    // history when the topic is compacted.
+    fn get_key(&self) -> Option<String> {
+        Some(format!(
+            "{}:{}:{}",
+            self.host, self.env_path, self.timestamp
+        ))
+    }
 }
 
 impl Summary {
     pub fn from_btree_map(summary: &BTreeMap<String, Result<String, ObsEnvError>>) -> Summary {
-        let timestamp = Utc::now().timestamp_millis();
-        let spectractor = extract_value!("Spectractor", summary);
-        let atmospec = extract_value!("atmospec", summary);
-        let cwfs = extract_value!("cwfs", summary);
-        let summit_extras = extract_value!("summit_extras", summary);
-        let summit_utils = extract_value!("summit_utils", summary);
-        let ts_config_attcs = extract_value!("ts_config_attcs", summary);
-        let ts_config_mttcs = extract_value!("ts_config_mttcs", summary);
-        let ts_config_ocs = extract_value!("ts_config_ocs", summary);
-        let ts_externalscripts = extract_value!("ts_externalscripts", summary);
-        let ts_observatory_control = extract_value!("ts_observatory_control", summary);
-        let ts_observing_utilities = extract_value!("ts_observing_utilities", summary);
-        let ts_standardscripts = extract_value!("ts_standardscripts", summary);
-        let ts_maintel_standardscripts = extract_value!("ts_maintel_standardscripts", summary);
-        let ts_auxtel_standardscripts = extract_value!("ts_auxtel_standardscripts", summary);
-        let ts_wep = extract_value!("ts_wep", summary);
-
+        let versions = summary
+            .iter()
+            .map(|(name, version)| {
+                let version = match version {
+                    Ok(version) => version.to_owned(),
+                    Err(error) => error.to_string(),
+                };
+                (Summary::get_field_name(name), version)
+            })
+            .collect();
         Summary {
-            timestamp,
-            spectractor,
-            atmospec,
-            cwfs,
-            summit_extras,
-            summit_utils,
-            ts_config_attcs,
-            ts_config_mttcs,
-            ts_config_ocs,
-            ts_externalscripts,
-            ts_observatory_control,
-            ts_observing_utilities,
-            ts_standardscripts,
-            ts_maintel_standardscripts,
-            ts_auxtel_standardscripts,
-            ts_wep,
+            timestamp: Utc::now().timestamp_millis(),
+            site: get_site(),
+            host: get_hostname(),
+            env_path: "".to_owned(),
+            checksum: Summary::compute_checksum(&versions),
+            versions,
         }
     }
+    /// SHA-256 checksum, in hex, of the JSON serialization of the versions
+    /// by field name.
+    pub fn compute_checksum(versions: &BTreeMap<String, String>) -> String {
+        let versions = serde_json::to_string(versions).unwrap_or_default();
+        format!("{:x}", Sha256::digest(versions.as_bytes()))
+    }
+    pub fn get_checksum(&self) -> &str {
+        &self.checksum
+    }
+    /// Whether the checksum matches the versions.
+    pub fn is_checksum_valid(&self) -> bool {
+        self.checksum == Summary::compute_checksum(&self.versions)
+    }
+    /// Name of the field with the version of `repository`.
+    ///
+    /// Avro names only allow letters, digits and underscores, and field
+    /// names are lower case (e.g. "Spectractor" is "spectractor").
+    pub fn get_field_name(repository: &str) -> String {
+        repository
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+    /// Set the path of the environment the versions are from.
+    pub fn with_env_path(mut self, env_path: &str) -> Self {
+        self.env_path = env_path.to_owned();
+        self
+    }
+    /// Version recorded for `repository`.
+    pub fn get_version(&self, repository: &str) -> Option<&str> {
+        self.versions
+            .get(&Summary::get_field_name(repository))
+            .map(|version| version.as_str())
+    }
+    /// Versions of all the repositories, by field name.
+    pub fn get_versions(&self) -> &BTreeMap<String, String> {
+        &self.versions
+    }
     pub fn get_topic_name() -> &'static str {
         "summary"
     }
+    /// Retrieve the latest summary registered in the EFD instance
+    /// `efd_name`.
+    #[cfg(feature = "telemetry")]
+    pub fn retrieve_from_efd(efd_name: &str) -> Result<Summary, Box<dyn Error>> {
+        let efd_client = EfdClient::from_efd_name(efd_name)?;
+        let query = EfdQuery::new(Summary::get_topic_name())
+            .descending()
+            .with_limit(1);
+        Ok(efd_client
+            .query_records::<Summary>(&query)?
+            .into_iter()
+            .next()
+            .unwrap_or_default())
+    }
 }
 
 impl ActionData {
     pub fn new(action: &str, repository: &str, branch_name: &str) -> ActionData {
-        let user = match env::var("SUDO_USER") {
-            Ok(val) => val,
-            Err(_) => match env::var("USER") {
-                Ok(val) => val,
-                Err(_) => "Unknown".to_owned(),
-            },
-        };
+        let user = get_user();
         ActionData {
             timestamp: Utc::now().timestamp_millis(),
             action: action.to_owned(),
             repository: repository.to_owned(),
             branch_name: branch_name.to_owned(),
             user,
+            acting_user: "".to_owned(),
+            host: get_hostname(),
+            site: get_site(),
+            env_path: "".to_owned(),
+            result: "success".to_owned(),
+            error_message: "".to_owned(),
+            duration_ms: 0,
         }
     }
     pub fn get_topic_name() -> &'static str {
         "action"
     }
+    /// Host where the action originated.
+    pub fn get_host(&self) -> &str {
+        &self.host
+    }
+    /// Set the path of the environment the action applies to.
+    pub fn with_env_path(mut self, env_path: &str) -> Self {
+        self.env_path = env_path.to_owned();
+        self
+    }
+    /// Set the user the action was run for.
+    pub fn with_acting_user(mut self, acting_user: &str) -> Self {
+        self.acting_user = acting_user.to_owned();
+        self
+    }
+    pub fn get_acting_user(&self) -> &str {
+        &self.acting_user
+    }
+    pub fn set_repository(&mut self, repository: &str) {
+        self.repository = repository.to_owned();
+    }
+    pub fn set_branch_name(&mut self, branch_name: &str) {
+        self.branch_name = branch_name.to_owned();
+    }
+    /// Record the outcome of the action: the error that made it fail, if
+    /// any, and how long it took.
+    pub fn set_outcome(&mut self, error: Option<&str>, duration: Duration) {
+        match error {
+            Some(error) => {
+                self.result = "failure".to_owned();
+                self.error_message = error.to_owned();
+            }
+            None => {
+                self.result = "success".to_owned();
+                self.error_message = "".to_owned();
+            }
+        }
+        self.duration_ms = duration.as_millis().try_into().unwrap_or(i64::MAX);
+    }
+    /// Result of the action, "success" or "failure".
+    pub fn get_result(&self) -> &str {
+        &self.result
+    }
+    pub fn get_error_message(&self) -> &str {
+        &self.error_message
+    }
+    pub fn get_duration_ms(&self) -> i64 {
+        self.duration_ms
+    }
 }
 
 pub fn get_payload<T: AvroSchema>(record: T) -> Payload<T> {
+    let key = record.get_key();
     Payload {
+        key_schema: key.as_ref().map(|_| KEY_SCHEMA.to_owned()),
         value_schema: record.get_avro_schema(),
-        records: vec![Record { value: record }],
+        records: vec![Record { key, value: record }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_payload, ActionData, AvroSchema, FieldType, RecordSchema, Summary};
+    use crate::error::ObsEnvError;
+    use std::{collections::BTreeMap, time::Duration};
+
+    #[test]
+    fn test_summary_from_repositories() {
+        let versions = BTreeMap::from_iter([
+            ("Spectractor".to_owned(), Ok("v3.1.0".to_owned())),
+            (
+                "ts_wep".to_owned(),
+                Err(ObsEnvError::GIT("Failed to open repository".to_owned())),
+            ),
+        ]);
+
+        let summary = Summary::from_btree_map(&versions);
+
+        assert_eq!(summary.get_version("Spectractor"), Some("v3.1.0"));
+        assert_eq!(
+            summary.get_version("ts_wep"),
+            Some("GIT: Failed to open repository")
+        );
+        assert_eq!(
+            summary.get_schema().get_field_names(),
+            vec![
+                "timestamp",
+                "site",
+                "host",
+                "env_path",
+                "checksum",
+                "spectractor",
+                "ts_wep"
+            ]
+        );
+        let record = serde_json::to_value(&summary).unwrap();
+        assert_eq!(record["spectractor"], "v3.1.0");
+        summary.get_schema().validate(&record).unwrap();
+        let round_trip: Summary = serde_json::from_value(record.clone()).unwrap();
+        assert!(round_trip.is_checksum_valid());
+        assert_eq!(round_trip.get_checksum(), summary.get_checksum());
+        assert_eq!(serde_json::to_value(round_trip).unwrap(), record);
+    }
+
+    #[test]
+    fn test_summary_checksum() {
+        let summary = |version: &str| {
+            Summary::from_btree_map(&BTreeMap::from_iter([(
+                "ts_wep".to_owned(),
+                Ok(version.to_owned()),
+            )]))
+        };
+
+        assert_eq!(summary("v1.0.0").get_checksum().len(), 64);
+        assert_eq!(
+            summary("v1.0.0").get_checksum(),
+            summary("v1.0.0").get_checksum()
+        );
+        assert_ne!(
+            summary("v1.0.0").get_checksum(),
+            summary("v1.0.1").get_checksum()
+        );
+    }
+
+    #[test]
+    fn test_action_data_outcome() {
+        let mut action = ActionData::new("checkout-branch", "ts_wep", "tickets/DM-1")
+            .with_acting_user("someone");
+        assert_eq!(action.get_result(), "success");
+        assert_eq!(action.get_acting_user(), "someone");
+
+        action.set_outcome(Some("Failed to checkout"), Duration::from_millis(1500));
+        assert_eq!(action.get_result(), "failure");
+        assert_eq!(action.get_error_message(), "Failed to checkout");
+        assert_eq!(action.get_duration_ms(), 1500);
+
+        let record = serde_json::to_value(&action).unwrap();
+        action.get_schema().validate(&record).unwrap();
+        let round_trip: ActionData = serde_json::from_value(record.clone()).unwrap();
+        assert_eq!(serde_json::to_value(round_trip).unwrap(), record);
+    }
+
+    #[test]
+    fn test_payload_validate() {
+        #[derive(Serialize)]
+        struct Drifted {
+            branch_name: String,
+        }
+        impl AvroSchema for Drifted {
+            fn get_schema(&self) -> RecordSchema {
+                RecordSchema::new("lsst.obsenv", "drifted").with_field("branch", FieldType::String)
+            }
+        }
+
+        assert!(get_payload(ActionData::default()).validate().is_ok());
+        let error = get_payload(Drifted {
+            branch_name: "main".to_owned(),
+        })
+        .validate()
+        .unwrap_err();
+        assert_eq!(error, "Field branch missing in drifted record.");
     }
 }
@@ -0,0 +1,143 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use super::{
+    log_summary::AvroSchema,
+    schema::{get_namespace, FieldType, RecordSchema},
+};
+use crate::{
+    identity::{get_hostname, get_site},
+    metrics::RepoMetrics,
+};
+use chrono::Utc;
+
+/// Timing of an action, with a breakdown by repository.
+///
+/// The lists of repositories, fetch times, durations and received bytes are
+/// comma separated, in the same order.
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct ActionMetrics {
+    timestamp: i64,
+    action: String,
+    site: String,
+    host: String,
+    env_path: String,
+    duration_ms: i64,
+    fetch_ms: i64,
+    received_bytes: i64,
+    repositories: String,
+    repo_fetch_ms: String,
+    repo_duration_ms: String,
+    repo_received_bytes: String,
+}
+
+impl AvroSchema for ActionMetrics {
+    fn get_schema(&self) -> RecordSchema {
+        RecordSchema::new(&get_namespace(), "metrics")
+            .with_field("timestamp", FieldType::Long)
+            .with_field("action", FieldType::String)
+            .with_optional_field("site", FieldType::String)
+            .with_optional_field("host", FieldType::String)
+            .with_optional_field("env_path", FieldType::String)
+            .with_field("duration_ms", FieldType::Long)
+            .with_field("fetch_ms", FieldType::Long)
+            .with_field("received_bytes", FieldType::Long)
+            .with_field("repositories", FieldType::String)
+            .with_field("repo_fetch_ms", FieldType::String)
+            .with_field("repo_duration_ms", FieldType::String)
+            .with_field("repo_received_bytes", FieldType::String)
+    }
+}
+
+impl ActionMetrics {
+    /// Create the record for `action`, which took `duration`, from the
+    /// metrics of the repositories it operated on.
+    pub fn new(
+        action: &str,
+        duration: Duration,
+        repositories: &BTreeMap<String, RepoMetrics>,
+    ) -> ActionMetrics {
+        let join = |value: fn(&RepoMetrics) -> u128| {
+            repositories
+                .values()
+                .map(|metrics| value(metrics).to_string())
+                .collect::<Vec<String>>()
+                .join(",")
+        };
+        let fetch_ms: u128 = repositories
+            .values()
+            .map(|metrics| metrics.fetch.duration.as_millis())
+            .sum();
+        let received_bytes: usize = repositories
+            .values()
+            .map(|metrics| metrics.fetch.received_bytes)
+            .sum();
+        ActionMetrics {
+            timestamp: Utc::now().timestamp_millis(),
+            action: action.to_owned(),
+            site: get_site(),
+            host: get_hostname(),
+            env_path: "".to_owned(),
+            duration_ms: duration.as_millis().try_into().unwrap_or(i64::MAX),
+            fetch_ms: fetch_ms.try_into().unwrap_or(i64::MAX),
+            received_bytes: received_bytes.try_into().unwrap_or(i64::MAX),
+            repositories: repositories
+                .keys()
+                .map(|repository| repository.as_str())
+                .collect::<Vec<&str>>()
+                .join(","),
+            repo_fetch_ms: join(|metrics| metrics.fetch.duration.as_millis()),
+            repo_duration_ms: join(|metrics| metrics.duration.as_millis()),
+            repo_received_bytes: join(|metrics| metrics.fetch.received_bytes as u128),
+        }
+    }
+    /// Set the path of the environment the action applies to.
+    pub fn with_env_path(mut self, env_path: &str) -> Self {
+        self.env_path = env_path.to_owned();
+        self
+    }
+    pub fn get_received_bytes(&self) -> i64 {
+        self.received_bytes
+    }
+    pub fn get_topic_name() -> &'static str {
+        "metrics"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ActionMetrics;
+    use crate::{
+        metrics::{FetchStats, RepoMetrics},
+        sasquatch::log_summary::AvroSchema,
+    };
+    use std::{collections::BTreeMap, time::Duration};
+
+    #[test]
+    fn test_action_metrics() {
+        let repo_metrics = |fetch_ms, received_bytes| RepoMetrics {
+            fetch: FetchStats {
+                duration: Duration::from_millis(fetch_ms),
+                received_bytes,
+            },
+            duration: Duration::from_millis(fetch_ms + 5),
+        };
+        let metrics = ActionMetrics::new(
+            "reset",
+            Duration::from_secs(1),
+            &BTreeMap::from_iter([
+                ("ts_ofc".to_owned(), repo_metrics(10, 100)),
+                ("ts_wep".to_owned(), repo_metrics(20, 200)),
+            ]),
+        );
+
+        assert_eq!(metrics.get_received_bytes(), 300);
+        let record = serde_json::to_value(&metrics).unwrap();
+        metrics.get_schema().validate(&record).unwrap();
+        assert_eq!(record["duration_ms"], 1000);
+        assert_eq!(record["fetch_ms"], 30);
+        assert_eq!(record["repositories"], "ts_ofc,ts_wep");
+        assert_eq!(record["repo_duration_ms"], "15,25");
+        assert_eq!(record["repo_received_bytes"], "100,200");
+    }
+}
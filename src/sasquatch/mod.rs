@@ -1,3 +1,26 @@
+#[cfg(feature = "sidecar")]
+pub mod async_client;
+#[cfg(feature = "telemetry")]
+pub mod client;
+#[cfg(feature = "telemetry")]
 pub mod create_topic;
+pub mod drift;
+#[cfg(feature = "telemetry")]
+pub mod efd_client;
+#[cfg(feature = "telemetry")]
+pub mod http;
+pub mod journal;
+pub mod lock_state;
 pub mod log_summary;
+pub mod metrics;
 pub mod run_branch;
+pub mod schema;
+#[cfg(feature = "telemetry")]
+pub mod schema_registry;
+#[cfg(feature = "telemetry")]
+pub mod sender;
+pub mod sink;
+pub mod site_comparison;
+#[cfg(feature = "telemetry")]
+pub mod spool;
+pub mod topic_config;
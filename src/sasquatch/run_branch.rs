@@ -1,50 +1,40 @@
+#[cfg(feature = "telemetry")]
 use std::error::Error;
-
-use super::log_summary::AvroSchema;
+use std::time::Duration;
+
+#[cfg(feature = "telemetry")]
+use super::efd_client::{EfdClient, EfdQuery};
+use super::{
+    log_summary::AvroSchema,
+    schema::{get_namespace, FieldType, RecordSchema},
+};
+use crate::identity::{get_site, get_user};
 use chrono::Utc;
-use lsst_efd_client::EfdAuth;
-use reqwest::blocking::Client;
-use thiserror::Error as ThisError;
 
 #[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(default)]
 pub struct RunBranch {
     timestamp: i64,
     branch_name: String,
+    site: String,
+    user: String,
+    /// Time after which the run branch no longer applies, in milliseconds
+    /// since the epoch, 0 if it does not expire.
+    expires: i64,
 }
 
-#[derive(Debug, Deserialize, Serialize, Default)]
-pub struct QueryResult<T> {
-    pub results: Vec<Payload<T>>,
-}
-
-#[derive(Debug, Deserialize, Serialize, Default)]
-struct RunBranchSeries {
-    name: String,
-    columns: Vec<String>,
-    values: Vec<(String, i64, String)>,
-}
-
-#[derive(Debug, Deserialize, Serialize, Default)]
-pub struct Payload<T> {
-    statement_id: usize,
-    pub series: Vec<T>,
-}
-
-#[derive(Clone, Debug, Eq, ThisError, PartialEq)]
-#[error("{0}")]
-struct ErrorRetrievingRunBranch(String);
-
-impl RunBranchSeries {
-    fn as_run_branch(&self) -> RunBranch {
-        RunBranch {
-            timestamp: self.values[0].1,
-            branch_name: self.values[0].2.clone(),
-        }
-    }
-}
 impl AvroSchema for RunBranch {
-    fn get_avro_schema(&self) -> String {
-        r#"{"namespace": "lsst.obsenv","type": "record","name": "run_branch","fields": [{"name": "timestamp", "type": "long"},{"name": "branch_name", "type": "string"}]}"#.to_owned()
+    fn get_schema(&self) -> RecordSchema {
+        RecordSchema::new(&get_namespace(), "run_branch")
+            .with_field("timestamp", FieldType::Long)
+            .with_field("branch_name", FieldType::String)
+            .with_optional_field("site", FieldType::String)
+            .with_optional_field("user", FieldType::String)
+            .with_optional_field("expires", FieldType::Long)
+    }
+
+    fn get_key(&self) -> Option<String> {
+        Some(self.site.clone())
     }
 }
 
@@ -53,9 +43,20 @@ impl RunBranch {
         RunBranch {
             timestamp: Utc::now().timestamp_millis(),
             branch_name: branch_name.to_owned(),
+            site: get_site(),
+            user: get_user(),
+            expires: 0,
         }
     }
 
+    /// Make the run branch expire after `ttl`.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.expires = self
+            .timestamp
+            .saturating_add(ttl.as_millis().try_into().unwrap_or(i64::MAX));
+        self
+    }
+
     pub fn get_topic_name() -> &'static str {
         "run_branch"
     }
@@ -64,45 +65,102 @@ impl RunBranch {
         &self.branch_name
     }
 
+    /// Time the run branch was registered, in milliseconds since the epoch.
+    pub fn get_timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    /// Time the run branch expires, in milliseconds since the epoch, None if
+    /// it does not expire.
+    pub fn get_expires(&self) -> Option<i64> {
+        (self.expires > 0).then_some(self.expires)
+    }
+
+    /// Has the run branch expired at `now`, in milliseconds since the epoch?
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.get_expires().is_some_and(|expires| expires <= now)
+    }
+
+    /// User that registered the run branch, empty for records from before
+    /// the user was recorded.
+    pub fn get_user(&self) -> &str {
+        &self.user
+    }
+
+    /// Retrieve the latest run branch registered in the EFD instance
+    /// `efd_name`.
+    ///
+    /// Returns an empty run branch if none was ever registered or if the
+    /// latest registration expired.
+    #[cfg(feature = "telemetry")]
     pub fn retrieve_from_efd(efd_name: &str) -> Result<RunBranch, Box<dyn Error>> {
-        let efd_auth = EfdAuth::new_blocking(efd_name)?;
-
-        let influxdb_url = format!(
-            "https://{}:{}/influxdb/query",
-            efd_auth.get_host(),
-            efd_auth.get_port(),
-        );
-
-        // Create a reqwest client
-        let client = Client::new();
-
-        let query = r#"SELECT "timestamp", "branch_name" FROM "lsst.obsenv"."autogen"."lsst.obsenv.run_branch" ORDER BY DESC LIMIT 1"#;
-
-        // Construct the full URL with query parameters
-        let response = client
-            .get(influxdb_url)
-            .basic_auth(efd_auth.get_username(), Some(efd_auth.get_password()))
-            .query(&[("db", "efd"), ("q", &query)])
-            .send()?; // Check the status code
-
-        if response.status().is_success() {
-            // Parse the response JSON
-            let text = response.text()?;
-            let query_result: Result<QueryResult<RunBranchSeries>, serde_json::Error> =
-                serde_json::from_str(&text);
-            match query_result {
-                Ok(query_result) => return Ok(query_result.results[0].series[0].as_run_branch()),
-                Err(error) => {
-                    return Err(Box::new(ErrorRetrievingRunBranch(format!(
-                        "Error: {error:?} parsing response: {text:?}"
-                    ))))
-                }
-            }
+        let efd_client = EfdClient::from_efd_name(efd_name)?;
+        let query = EfdQuery::new(RunBranch::get_topic_name())
+            .descending()
+            .with_limit(1);
+        let run_branch = efd_client
+            .query_records::<RunBranch>(&query)?
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+        if run_branch.is_expired(Utc::now().timestamp_millis()) {
+            log::warn!(
+                "Run branch {} expired, ignoring it. Register it again if it is still needed.",
+                run_branch.get_branch_name()
+            );
+            Ok(RunBranch::default())
         } else {
-            Err(Box::new(ErrorRetrievingRunBranch(format!(
-                "Error: {:?}",
-                response
-            ))))
+            Ok(run_branch)
         }
     }
+
+    /// Retrieve the last `limit` run branches registered in the EFD
+    /// instance `efd_name`, newest first.
+    ///
+    /// Clearing the run branch registers an empty run branch.
+    #[cfg(feature = "telemetry")]
+    pub fn retrieve_history_from_efd(
+        efd_name: &str,
+        limit: usize,
+    ) -> Result<Vec<RunBranch>, Box<dyn Error>> {
+        let efd_client = EfdClient::from_efd_name(efd_name)?;
+        let query = EfdQuery::new(RunBranch::get_topic_name())
+            .descending()
+            .with_limit(limit);
+        efd_client.query_records::<RunBranch>(&query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RunBranch;
+    use crate::sasquatch::log_summary::{get_payload, AvroSchema};
+    use std::time::Duration;
+
+    #[test]
+    fn test_run_branch_schema() {
+        let run_branch = RunBranch::new("tickets/DM-1");
+
+        let record = serde_json::to_value(&run_branch).unwrap();
+        run_branch.get_schema().validate(&record).unwrap();
+        let round_trip: RunBranch = serde_json::from_value(record).unwrap();
+        assert_eq!(round_trip.get_branch_name(), "tickets/DM-1");
+
+        let payload = serde_json::to_value(get_payload(run_branch)).unwrap();
+        assert_eq!(payload["key_schema"], r#""string""#);
+        assert!(payload["records"][0]["key"].is_string());
+    }
+
+    #[test]
+    fn test_run_branch_expiry() {
+        let run_branch = RunBranch::new("tickets/DM-1");
+        assert_eq!(run_branch.get_expires(), None);
+        assert!(!run_branch.is_expired(i64::MAX));
+
+        let run_branch = run_branch.with_ttl(Duration::from_secs(3600));
+        let expires = run_branch.get_timestamp() + 3_600_000;
+        assert_eq!(run_branch.get_expires(), Some(expires));
+        assert!(!run_branch.is_expired(expires - 1));
+        assert!(run_branch.is_expired(expires));
+    }
 }
@@ -0,0 +1,175 @@
+use serde_json::Value;
+use std::{env, fmt};
+
+/// Namespace of the telemetry records used unless
+/// MANAGE_OBS_ENV_TELEMETRY_NAMESPACE is set.
+pub const DEFAULT_NAMESPACE: &str = "lsst.obsenv";
+
+/// Namespace of the telemetry records.
+///
+/// The namespace is also the prefix of the topic names and the name of the
+/// EFD database, so test stands can use an isolated namespace by setting
+/// MANAGE_OBS_ENV_TELEMETRY_NAMESPACE.
+pub fn get_namespace() -> String {
+    match env::var("MANAGE_OBS_ENV_TELEMETRY_NAMESPACE") {
+        Ok(namespace) if !namespace.is_empty() => namespace,
+        _ => DEFAULT_NAMESPACE.to_owned(),
+    }
+}
+
+/// Full name of the topic for `record_name` records.
+pub fn get_topic(record_name: &str) -> String {
+    format!("{}.{record_name}", get_namespace())
+}
+
+/// Avro types used in the telemetry records.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    Long,
+    String,
+}
+
+/// Field of an Avro record schema.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Field {
+    name: String,
+    #[serde(rename = "type")]
+    field_type: FieldType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<Value>,
+}
+
+/// Avro record schema.
+///
+/// Serializes (with `to_string`) to the JSON definition of the schema.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RecordSchema {
+    namespace: String,
+    #[serde(rename = "type")]
+    record_type: &'static str,
+    name: String,
+    fields: Vec<Field>,
+}
+
+impl RecordSchema {
+    pub fn new(namespace: &str, name: &str) -> RecordSchema {
+        RecordSchema {
+            namespace: namespace.to_owned(),
+            record_type: "record",
+            name: name.to_owned(),
+            fields: Vec::new(),
+        }
+    }
+
+    /// Add a required field.
+    pub fn with_field(mut self, name: &str, field_type: FieldType) -> Self {
+        self.fields.push(Field {
+            name: name.to_owned(),
+            field_type,
+            default: None,
+        });
+        self
+    }
+
+    /// Add a field with a default value, which can be added to an existing
+    /// schema without breaking compatibility.
+    pub fn with_optional_field(mut self, name: &str, field_type: FieldType) -> Self {
+        let default = match field_type {
+            FieldType::Long => Value::from(0),
+            FieldType::String => Value::from(""),
+        };
+        self.fields.push(Field {
+            name: name.to_owned(),
+            field_type,
+            default: Some(default),
+        });
+        self
+    }
+
+    pub fn get_namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_field_names(&self) -> Vec<&str> {
+        self.fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .collect()
+    }
+
+    /// Check that `record`, in its JSON representation, matches the schema.
+    ///
+    /// All fields must be present with the right type and there must be no
+    /// fields that are not in the schema.
+    pub fn validate(&self, record: &Value) -> Result<(), String> {
+        let Some(record) = record.as_object() else {
+            return Err(format!("Record for {} is not an object.", self.name));
+        };
+        for field in &self.fields {
+            let Some(value) = record.get(&field.name) else {
+                return Err(format!(
+                    "Field {} missing in {} record.",
+                    field.name, self.name
+                ));
+            };
+            let valid = match field.field_type {
+                FieldType::Long => value.is_i64(),
+                FieldType::String => value.is_string(),
+            };
+            if !valid {
+                return Err(format!(
+                    "Field {} of {} record is not a {:?}: {value}.",
+                    field.name, self.name, field.field_type
+                ));
+            }
+        }
+        if let Some(name) = record
+            .keys()
+            .find(|name| !self.fields.iter().any(|field| &field.name == *name))
+        {
+            return Err(format!("Field {name} is not in the {} schema.", self.name));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RecordSchema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let schema = serde_json::to_string(self).map_err(|_| fmt::Error)?;
+        write!(f, "{schema}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FieldType, RecordSchema};
+    use serde_json::json;
+
+    #[test]
+    fn test_record_schema() {
+        let schema = RecordSchema::new("lsst.obsenv", "run_branch")
+            .with_field("timestamp", FieldType::Long)
+            .with_optional_field("branch_name", FieldType::String);
+
+        assert_eq!(
+            schema.to_string(),
+            r#"{"namespace":"lsst.obsenv","type":"record","name":"run_branch","fields":[{"name":"timestamp","type":"long"},{"name":"branch_name","type":"string","default":""}]}"#
+        );
+
+        assert!(schema
+            .validate(&json!({"timestamp": 1, "branch_name": "main"}))
+            .is_ok());
+        assert!(schema.validate(&json!({"timestamp": 1})).is_err());
+        assert!(schema
+            .validate(&json!({"timestamp": "1", "branch_name": "main"}))
+            .is_err());
+        assert!(schema
+            .validate(&json!({"timestamp": 1, "branch_name": "main", "user": "me"}))
+            .is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use super::http::{build_client, send_idempotent};
+use super::schema::RecordSchema;
+use reqwest::StatusCode;
+use std::error::Error as StdError;
+use thiserror::Error as ThisError;
+
+const CONTENT_TYPE: &str = "application/vnd.schemaregistry.v1+json";
+
+#[derive(Debug, Serialize)]
+struct SchemaRequest {
+    schema: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompatibilityResponse {
+    is_compatible: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct RegisterResponse {
+    id: i64,
+}
+
+#[derive(Clone, Debug, Eq, ThisError, PartialEq)]
+#[error("{0}")]
+struct ErrorRegisteringSchema(String);
+
+/// Name of the schema registry subject for the values of the topic with
+/// records following `schema`.
+pub fn get_subject(schema: &RecordSchema) -> String {
+    format!("{}.{}-value", schema.get_namespace(), schema.get_name())
+}
+
+/// Register the schemas with the schema registry.
+///
+/// Each schema is first checked for compatibility with the latest version
+/// registered for its subject, so an incompatible change is reported instead
+/// of registered.
+pub fn register_schemas(
+    schema_registry_url: &str,
+    schemas: &[RecordSchema],
+) -> Result<(), Box<dyn StdError>> {
+    let client = build_client()?;
+    for schema in schemas {
+        let subject = get_subject(schema);
+        let request = SchemaRequest {
+            schema: schema.to_string(),
+        };
+
+        let res = send_idempotent(
+            client
+                .post(format!(
+                    "{schema_registry_url}/compatibility/subjects/{subject}/versions/latest"
+                ))
+                .header("Content-Type", CONTENT_TYPE)
+                .json(&request),
+        )?;
+        match res.status() {
+            StatusCode::NOT_FOUND => log::debug!("No schema registered for {subject} yet."),
+            status if status.is_success() => {
+                let compatibility: CompatibilityResponse = res.json()?;
+                if !compatibility.is_compatible {
+                    return Err(Box::new(ErrorRegisteringSchema(format!(
+                        "Schema for {subject} is not compatible with the registered schema: {schema}"
+                    ))));
+                }
+            }
+            _ => {
+                return Err(Box::new(ErrorRegisteringSchema(format!(
+                    "Error checking compatibility of {subject}: {}",
+                    res.text()?
+                ))))
+            }
+        }
+
+        // Registering the same schema again returns its existing id.
+        let res = send_idempotent(
+            client
+                .post(format!("{schema_registry_url}/subjects/{subject}/versions"))
+                .header("Content-Type", CONTENT_TYPE)
+                .json(&request),
+        )?;
+        if !res.status().is_success() {
+            return Err(Box::new(ErrorRegisteringSchema(format!(
+                "Error registering schema for {subject}: {}",
+                res.text()?
+            ))));
+        }
+        let registered: RegisterResponse = res.json()?;
+        log::info!("Registered schema for {subject} with id {}.", registered.id);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::get_subject;
+    use crate::sasquatch::{log_summary::AvroSchema, run_branch::RunBranch};
+
+    #[test]
+    fn test_get_subject() {
+        assert_eq!(
+            get_subject(&RunBranch::default().get_schema()),
+            "lsst.obsenv.run_branch-value"
+        );
+    }
+}
@@ -0,0 +1,412 @@
+use super::{
+    create_topic::ensure_topic,
+    log_summary::{AvroSchema, Payload},
+    spool::TelemetrySpool,
+    topic_config::TopicConfig,
+};
+use reqwest::blocking::Client;
+use serde::ser::Serialize;
+use serde_json::Value;
+use std::{
+    collections::HashSet,
+    fmt::Debug,
+    sync::mpsc::{channel, Sender},
+    thread::{self, JoinHandle},
+};
+
+/// Payload waiting to be sent, already serialized, with the full name of
+/// the topic it goes to.
+///
+/// Spooled payloads also have the REST proxy they failed to be sent to, so
+/// they are only retried there.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(super) struct QueuedPayload {
+    pub(super) topic_name: String,
+    pub(super) payload: Value,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(super) rest_proxy_url: Option<String>,
+}
+
+/// Send telemetry to sasquatch from a background thread.
+///
+/// Payloads are queued in memory and sent by a background thread, so a slow
+/// or unreachable sasquatch does not delay the actions. Payloads queued
+/// while a request is in progress are batched: records for the same topic
+/// are posted together and different topics are posted concurrently. Queued
+/// payloads are flushed when the sender is dropped, which blocks until all
+/// of them were sent (or failed).
+///
+/// Payloads are sent to all the REST proxies given, e.g. to mirror the
+/// telemetry to a replica of the EFD, and a failure to send to one of them
+/// does not prevent sending to the others.
+///
+/// With a spool, payloads that fail to be sent are stored in it, and the
+/// payloads already in the spool are retried when the sender starts.
+///
+/// Before the first payload is sent to a topic, the topic is checked to
+/// exist, and created if a topic configuration to auto-create topics is
+/// given.
+pub struct TelemetrySender {
+    namespace: String,
+    queue: Option<Sender<QueuedPayload>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl TelemetrySender {
+    /// Start the sender thread, sending with `client` to the sasquatch REST
+    /// proxies at `rest_proxy_urls` (including their base path), in the
+    /// topics of `namespace`.
+    ///
+    /// If no url is given, payloads are discarded with an error message.
+    pub fn new(
+        client: Client,
+        rest_proxy_urls: Vec<String>,
+        namespace: &str,
+        spool: Option<TelemetrySpool>,
+        auto_create: Option<TopicConfig>,
+    ) -> TelemetrySender {
+        let (queue, receiver) = channel::<QueuedPayload>();
+        let worker = thread::spawn(move || {
+            if rest_proxy_urls.is_empty() {
+                for _ in receiver {
+                    log::error!(
+                        "Environment variable SASQUATCH_REST_PROXY_URL, not set. \
+                        This variable defines the url of the sasquatch service and needs \
+                        to be defined for actions to be registered."
+                    );
+                }
+                return;
+            }
+            if let Some(spool) = &spool {
+                retry_spooled(&client, &rest_proxy_urls, spool);
+            }
+            let mut known_topics = HashSet::new();
+            while let Ok(queued) = receiver.recv() {
+                // Send everything queued so far together: records for the
+                // same topic in one request, different topics concurrently.
+                let mut batch = vec![queued];
+                batch.extend(receiver.try_iter());
+                thread::scope(|scope| {
+                    for queued in merge_payloads(batch) {
+                        for url in &rest_proxy_urls {
+                            let (client, spool, queued) = (&client, &spool, queued.clone());
+                            let topic = (url.clone(), queued.topic_name.clone());
+                            if !known_topics.contains(&topic) {
+                                match ensure_topic(
+                                    client,
+                                    url,
+                                    &queued.topic_name,
+                                    auto_create.as_ref(),
+                                ) {
+                                    Ok(()) => {
+                                        known_topics.insert(topic);
+                                    }
+                                    Err(error) => {
+                                        log::error!("{url}: {error}");
+                                        spool_payload(spool.as_ref(), &queued, url);
+                                        continue;
+                                    }
+                                }
+                            }
+                            scope.spawn(move || {
+                                if let Err(error) = post_payload(client, url, &queued) {
+                                    log::error!("{url}: {error}");
+                                    spool_payload(spool.as_ref(), &queued, url);
+                                }
+                            });
+                        }
+                    }
+                });
+            }
+        });
+        TelemetrySender {
+            namespace: namespace.to_owned(),
+            queue: Some(queue),
+            worker: Some(worker),
+        }
+    }
+
+    /// Queue a payload to be sent to the `topic_name` topic of the
+    /// namespace.
+    ///
+    /// The records are validated against their schema first, and an error
+    /// is returned if they do not match it. The namespace of the schema is
+    /// set to the namespace of the sender.
+    pub fn send<T: AvroSchema + Debug + Serialize>(
+        &self,
+        payload: &Payload<T>,
+        topic_name: &str,
+    ) -> Result<(), String> {
+        log::debug!("{topic_name}");
+        let queued = prepare_payload(&self.namespace, payload, topic_name)?;
+        if let Some(queue) = &self.queue {
+            if queue.send(queued).is_err() {
+                log::error!("Telemetry sender thread is not running, payload dropped.");
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for all queued payloads to be sent.
+    pub fn flush(self) {}
+}
+
+impl Drop for TelemetrySender {
+    fn drop(&mut self) {
+        // Closing the queue stops the worker once it is empty.
+        self.queue.take();
+        if let Some(worker) = self.worker.take() {
+            log::trace!("Flushing telemetry.");
+            if worker.join().is_err() {
+                log::error!("Telemetry sender thread panicked.");
+            }
+        }
+    }
+}
+
+/// Validate `payload` and serialize it for the `topic_name` topic of
+/// `namespace`, setting the namespace of its schema.
+pub(super) fn prepare_payload<T: AvroSchema + Debug + Serialize>(
+    namespace: &str,
+    payload: &Payload<T>,
+    topic_name: &str,
+) -> Result<QueuedPayload, String> {
+    payload
+        .validate()
+        .map_err(|error| format!("Invalid payload for {topic_name}: {error}"))?;
+    let mut payload = serde_json::to_value(payload)
+        .map_err(|error| format!("Error serializing payload {payload:?}: {error}"))?;
+    if let Some(Value::String(value_schema)) = payload.get_mut("value_schema") {
+        if let Ok(mut schema) = serde_json::from_str::<Value>(value_schema) {
+            schema["namespace"] = Value::from(namespace);
+            *value_schema = schema.to_string();
+        }
+    }
+    Ok(QueuedPayload {
+        topic_name: format!("{namespace}.{topic_name}"),
+        payload,
+        rest_proxy_url: None,
+    })
+}
+
+/// Merge payloads for the same topic, with the same schema, into a single
+/// payload with all their records, keeping the order of the records.
+fn merge_payloads(payloads: Vec<QueuedPayload>) -> Vec<QueuedPayload> {
+    let mut merged: Vec<QueuedPayload> = Vec::new();
+    for queued in payloads {
+        let target = merged.iter_mut().find(|target| {
+            target.topic_name == queued.topic_name
+                && target.payload.get("key_schema") == queued.payload.get("key_schema")
+                && target.payload.get("value_schema") == queued.payload.get("value_schema")
+        });
+        match (target, queued.payload.get("records")) {
+            (Some(target), Some(Value::Array(records))) => {
+                if let Some(Value::Array(target_records)) = target.payload.get_mut("records") {
+                    target_records.extend(records.iter().cloned());
+                }
+            }
+            _ => merged.push(queued),
+        }
+    }
+    merged
+}
+
+/// Store a payload that could not be sent to `rest_proxy_url` in the spool,
+/// if any.
+fn spool_payload(spool: Option<&TelemetrySpool>, queued: &QueuedPayload, rest_proxy_url: &str) {
+    if let Some(spool) = spool {
+        let queued = QueuedPayload {
+            rest_proxy_url: Some(rest_proxy_url.to_owned()),
+            ..queued.clone()
+        };
+        match spool.store(&queued) {
+            Ok(path) => log::info!("Payload spooled to {path:?} for retry."),
+            Err(error) => log::error!("{error}"),
+        }
+    }
+}
+
+/// Send the payloads in the spool, oldest first, to the REST proxy they
+/// failed to be sent to (the first one in `rest_proxy_urls` if unknown).
+///
+/// After a failure, no more payloads are sent to that REST proxy.
+fn retry_spooled(client: &Client, rest_proxy_urls: &[String], spool: &TelemetrySpool) {
+    let pending = spool.pending();
+    if pending.is_empty() {
+        return;
+    }
+    log::info!(
+        "Retrying {} spooled telemetry payloads from {:?}.",
+        pending.len(),
+        spool.get_dir()
+    );
+    let mut failed_urls = HashSet::new();
+    for path in pending {
+        let Some((claimed, queued)) = spool.claim(&path) else {
+            continue;
+        };
+        let url = match &queued.rest_proxy_url {
+            Some(url) => url.as_str(),
+            None => rest_proxy_urls[0].as_str(),
+        };
+        if failed_urls.contains(url) {
+            spool.release(&claimed);
+            continue;
+        }
+        match post_payload(client, url, &queued) {
+            Ok(()) => spool.complete(&claimed),
+            Err(error) => {
+                log::warn!("Failed to send spooled payload to {url}, will retry later. {error}");
+                spool.release(&claimed);
+                failed_urls.insert(url.to_owned());
+            }
+        }
+    }
+}
+
+fn post_payload(
+    client: &Client,
+    rest_proxy_url: &str,
+    queued: &QueuedPayload,
+) -> Result<(), String> {
+    let QueuedPayload {
+        topic_name,
+        payload,
+        ..
+    } = queued;
+    match client
+        .post(format!("{rest_proxy_url}/topics/{topic_name}",))
+        .header("Content-Type", "application/vnd.kafka.avro.v2+json")
+        .header("Accept", "application/vnd.kafka.v2+json")
+        .json(payload)
+        .send()
+    {
+        Ok(res) if res.status().is_success() => {
+            log::trace!("Payload: {payload:?}.");
+            Ok(())
+        }
+        Ok(res) => Err(format!(
+            "Server replied with error to payload request: {res:?}. {payload:?}"
+        )),
+        Err(error) => Err(format!("Error sending payload: {error}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge_payloads, QueuedPayload, TelemetrySender};
+    use crate::sasquatch::{
+        http::build_client, log_summary::get_payload, run_branch::RunBranch,
+        schema::DEFAULT_NAMESPACE,
+    };
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    #[test]
+    fn test_send_is_flushed_on_drop() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/sasquatch-rest-proxy",
+            listener.local_addr().unwrap()
+        );
+        let related = serde_json::json!({"related": ""});
+        let clusters = serde_json::json!({
+            "kind": "KafkaClusterList",
+            "metadata": {"self": ""},
+            "data": [{
+                "kind": "KafkaCluster",
+                "metadata": {"self": ""},
+                "cluster_id": "test_cluster",
+                "controller": related,
+                "brokers": related,
+                "broker_configs": related,
+                "consumer_groups": related,
+                "topics": related,
+                "partition_reassignments": related,
+            }],
+        })
+        .to_string();
+        let server = thread::spawn(move || {
+            // The cluster and the topic are looked up before the payload is
+            // posted.
+            let mut requests = Vec::new();
+            for (end, body) in [
+                ("\r\n\r\n", clusters.as_str()),
+                ("\r\n\r\n", ""),
+                ("test_branch", ""),
+            ] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                while !String::from_utf8_lossy(&request).contains(end) {
+                    let read = stream.read(&mut buffer).unwrap();
+                    assert!(read > 0);
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                stream
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                            body.len()
+                        )
+                        .as_bytes(),
+                    )
+                    .unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+
+        let telemetry = TelemetrySender::new(
+            build_client().unwrap(),
+            vec![url],
+            DEFAULT_NAMESPACE,
+            None,
+            None,
+        );
+        telemetry
+            .send(
+                &get_payload(RunBranch::new("test_branch")),
+                RunBranch::get_topic_name(),
+            )
+            .unwrap();
+        telemetry.flush();
+
+        let requests = server.join().unwrap();
+        assert!(requests[1].starts_with(
+            "GET /sasquatch-rest-proxy/v3/clusters/test_cluster/topics/lsst.obsenv.run_branch"
+        ));
+        assert!(requests[2].starts_with("POST /sasquatch-rest-proxy/topics/lsst.obsenv.run_branch"));
+    }
+
+    #[test]
+    fn test_merge_payloads() {
+        let queued = |topic_name: &str, schema: &str, value: i64| QueuedPayload {
+            topic_name: topic_name.to_owned(),
+            payload: serde_json::json!({"value_schema": schema, "records": [{"value": value}]}),
+            rest_proxy_url: None,
+        };
+
+        let merged = merge_payloads(vec![
+            queued("lsst.obsenv.summary", "a", 1),
+            queued("lsst.obsenv.action", "b", 2),
+            queued("lsst.obsenv.summary", "a", 3),
+            queued("lsst.obsenv.summary", "c", 4),
+        ]);
+
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[0].topic_name, "lsst.obsenv.summary");
+        assert_eq!(
+            merged[0].payload["records"],
+            serde_json::json!([{"value": 1}, {"value": 3}])
+        );
+        assert_eq!(merged[1].topic_name, "lsst.obsenv.action");
+        assert_eq!(
+            merged[2].payload["records"],
+            serde_json::json!([{"value": 4}])
+        );
+    }
+}
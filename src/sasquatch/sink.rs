@@ -0,0 +1,271 @@
+use std::{fmt::Debug, sync::Mutex};
+
+#[cfg(feature = "telemetry")]
+use super::{client::SasquatchClient, spool::TelemetrySpool};
+use super::{
+    drift::Drift,
+    lock_state::LockState,
+    log_summary::{get_payload, ActionData, AvroSchema, Summary},
+    metrics::ActionMetrics,
+    run_branch::RunBranch,
+};
+use serde::ser::Serialize;
+use serde_json::Value;
+
+/// Destination of the telemetry records of the actions.
+pub trait TelemetrySink {
+    fn send_action(&self, action_data: ActionData) -> Result<(), String>;
+    fn send_summary(&self, summary: Summary) -> Result<(), String>;
+    fn send_run_branch(&self, run_branch: RunBranch) -> Result<(), String>;
+    fn send_drift(&self, drift: Drift) -> Result<(), String>;
+    fn send_lock_state(&self, lock_state: LockState) -> Result<(), String>;
+    fn send_metrics(&self, metrics: ActionMetrics) -> Result<(), String>;
+
+    /// Whether the records are sent anywhere.
+    fn is_configured(&self) -> bool {
+        true
+    }
+
+    /// Spool of the records that failed to be sent, if any.
+    #[cfg(feature = "telemetry")]
+    fn get_spool(&self) -> Option<&TelemetrySpool> {
+        None
+    }
+}
+
+#[cfg(feature = "telemetry")]
+impl TelemetrySink for SasquatchClient {
+    fn send_action(&self, action_data: ActionData) -> Result<(), String> {
+        self.send_record(action_data, ActionData::get_topic_name())
+    }
+    fn send_summary(&self, summary: Summary) -> Result<(), String> {
+        self.send_record(summary, Summary::get_topic_name())
+    }
+    fn send_run_branch(&self, run_branch: RunBranch) -> Result<(), String> {
+        self.send_record(run_branch, RunBranch::get_topic_name())
+    }
+    fn send_drift(&self, drift: Drift) -> Result<(), String> {
+        self.send_record(drift, Drift::get_topic_name())
+    }
+    fn send_lock_state(&self, lock_state: LockState) -> Result<(), String> {
+        self.send_record(lock_state, LockState::get_topic_name())
+    }
+    fn send_metrics(&self, metrics: ActionMetrics) -> Result<(), String> {
+        self.send_record(metrics, ActionMetrics::get_topic_name())
+    }
+    fn is_configured(&self) -> bool {
+        !self.get_rest_proxy_urls().is_empty()
+    }
+    fn get_spool(&self) -> Option<&TelemetrySpool> {
+        SasquatchClient::get_spool(self)
+    }
+}
+
+/// Status of the telemetry sent by an action.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TelemetryStatus {
+    configured: bool,
+    accepted: usize,
+    errors: Vec<String>,
+}
+
+impl TelemetryStatus {
+    /// Whether the records were sent anywhere, see
+    /// `TelemetrySink::is_configured`.
+    pub fn is_configured(&self) -> bool {
+        self.configured
+    }
+
+    /// Number of records accepted by the sink. Sinks sending the records
+    /// in the background accept them once they are queued.
+    pub fn get_accepted(&self) -> usize {
+        self.accepted
+    }
+
+    /// Errors sending the records that were not accepted.
+    pub fn get_errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Forward the telemetry records to another sink, keeping track of the
+/// outcome of sending them.
+pub struct TelemetryTracker<'a> {
+    sink: &'a dyn TelemetrySink,
+    status: Mutex<TelemetryStatus>,
+}
+
+impl<'a> TelemetryTracker<'a> {
+    pub fn new(sink: &'a dyn TelemetrySink) -> TelemetryTracker<'a> {
+        TelemetryTracker {
+            sink,
+            status: Mutex::new(TelemetryStatus {
+                configured: sink.is_configured(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Status of the records sent so far.
+    pub fn get_status(&self) -> TelemetryStatus {
+        match self.status.lock() {
+            Ok(status) => status.clone(),
+            Err(error) => error.into_inner().clone(),
+        }
+    }
+
+    fn track(&self, result: Result<(), String>) -> Result<(), String> {
+        let mut status = match self.status.lock() {
+            Ok(status) => status,
+            Err(error) => error.into_inner(),
+        };
+        match &result {
+            Ok(()) => status.accepted += 1,
+            Err(error) => status.errors.push(error.clone()),
+        }
+        result
+    }
+}
+
+impl TelemetrySink for TelemetryTracker<'_> {
+    fn send_action(&self, action_data: ActionData) -> Result<(), String> {
+        self.track(self.sink.send_action(action_data))
+    }
+    fn send_summary(&self, summary: Summary) -> Result<(), String> {
+        self.track(self.sink.send_summary(summary))
+    }
+    fn send_run_branch(&self, run_branch: RunBranch) -> Result<(), String> {
+        self.track(self.sink.send_run_branch(run_branch))
+    }
+    fn send_drift(&self, drift: Drift) -> Result<(), String> {
+        self.track(self.sink.send_drift(drift))
+    }
+    fn send_lock_state(&self, lock_state: LockState) -> Result<(), String> {
+        self.track(self.sink.send_lock_state(lock_state))
+    }
+    fn send_metrics(&self, metrics: ActionMetrics) -> Result<(), String> {
+        self.track(self.sink.send_metrics(metrics))
+    }
+    fn is_configured(&self) -> bool {
+        self.sink.is_configured()
+    }
+    #[cfg(feature = "telemetry")]
+    fn get_spool(&self) -> Option<&TelemetrySpool> {
+        self.sink.get_spool()
+    }
+}
+
+/// Keep the telemetry records in memory, e.g. to check the telemetry of an
+/// action in tests.
+///
+/// Records are validated against their schema, as they would be before
+/// being sent to sasquatch.
+#[derive(Debug, Default)]
+pub struct TelemetryRecorder {
+    records: Mutex<Vec<(String, Value)>>,
+}
+
+impl TelemetryRecorder {
+    pub fn new() -> TelemetryRecorder {
+        TelemetryRecorder::default()
+    }
+
+    /// Records sent to `topic_name` so far, in the order they were sent.
+    pub fn get_records(&self, topic_name: &str) -> Vec<Value> {
+        let records = match self.records.lock() {
+            Ok(records) => records,
+            Err(error) => error.into_inner(),
+        };
+        records
+            .iter()
+            .filter(|(record_topic, _)| record_topic == topic_name)
+            .map(|(_, record)| record.clone())
+            .collect()
+    }
+
+    /// Number of records sent so far, to all topics.
+    pub fn len(&self) -> usize {
+        match self.records.lock() {
+            Ok(records) => records.len(),
+            Err(error) => error.into_inner().len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn record<T: AvroSchema + Debug + Serialize>(
+        &self,
+        record: T,
+        topic_name: &str,
+    ) -> Result<(), String> {
+        let payload = get_payload(record);
+        payload
+            .validate()
+            .map_err(|error| format!("Invalid payload for {topic_name}: {error}"))?;
+        let payload = serde_json::to_value(&payload)
+            .map_err(|error| format!("Error serializing payload {payload:?}: {error}"))?;
+        let Some(Value::Array(records)) = payload.get("records") else {
+            return Err(format!("Payload without records: {payload}"));
+        };
+        let mut recorded = match self.records.lock() {
+            Ok(recorded) => recorded,
+            Err(error) => error.into_inner(),
+        };
+        recorded.extend(
+            records
+                .iter()
+                .map(|record| (topic_name.to_owned(), record["value"].clone())),
+        );
+        Ok(())
+    }
+}
+
+impl TelemetrySink for TelemetryRecorder {
+    fn send_action(&self, action_data: ActionData) -> Result<(), String> {
+        self.record(action_data, ActionData::get_topic_name())
+    }
+    fn send_summary(&self, summary: Summary) -> Result<(), String> {
+        self.record(summary, Summary::get_topic_name())
+    }
+    fn send_run_branch(&self, run_branch: RunBranch) -> Result<(), String> {
+        self.record(run_branch, RunBranch::get_topic_name())
+    }
+    fn send_drift(&self, drift: Drift) -> Result<(), String> {
+        self.record(drift, Drift::get_topic_name())
+    }
+    fn send_lock_state(&self, lock_state: LockState) -> Result<(), String> {
+        self.record(lock_state, LockState::get_topic_name())
+    }
+    fn send_metrics(&self, metrics: ActionMetrics) -> Result<(), String> {
+        self.record(metrics, ActionMetrics::get_topic_name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TelemetryRecorder, TelemetrySink};
+    use crate::sasquatch::run_branch::RunBranch;
+
+    #[test]
+    fn test_telemetry_recorder() {
+        let recorder = TelemetryRecorder::new();
+        assert!(recorder.is_empty());
+
+        recorder
+            .send_run_branch(RunBranch::new("tickets/DM-1"))
+            .unwrap();
+        recorder.send_run_branch(RunBranch::new("")).unwrap();
+
+        let records = recorder.get_records(RunBranch::get_topic_name());
+        assert_eq!(recorder.len(), 2);
+        assert_eq!(records[0]["branch_name"], "tickets/DM-1");
+        assert_eq!(records[1]["branch_name"], "");
+        assert!(recorder.get_records("summary").is_empty());
+    }
+}
@@ -0,0 +1,139 @@
+use super::log_summary::Summary;
+#[cfg(feature = "telemetry")]
+use crate::error::ObsEnvError;
+use std::collections::BTreeSet;
+#[cfg(feature = "telemetry")]
+use std::error::Error;
+
+/// Latest summary of the environment of several sites, each from its own
+/// EFD instance, to compare their versions.
+#[derive(Debug, Default)]
+pub struct SiteComparison {
+    sites: Vec<(String, Summary)>,
+}
+
+impl SiteComparison {
+    /// Retrieve the latest summary from each of the EFD instances
+    /// `efd_names`.
+    ///
+    /// Instances that can't be queried are left out of the comparison with
+    /// a warning. An error is returned only if none of them could be.
+    #[cfg(feature = "telemetry")]
+    pub fn retrieve_from_efds(efd_names: &[String]) -> Result<SiteComparison, Box<dyn Error>> {
+        let mut comparison = SiteComparison::default();
+        for efd_name in efd_names {
+            log::debug!("Retrieving summary from {efd_name} instance of the EFD.");
+            match Summary::retrieve_from_efd(efd_name) {
+                Ok(summary) => comparison = comparison.with_site(efd_name, summary),
+                Err(error) => log::warn!("Failed to retrieve summary from {efd_name}: {error}"),
+            }
+        }
+        if comparison.sites.is_empty() {
+            return Err(Box::new(ObsEnvError::ERROR(format!(
+                "Failed to retrieve summary from any of {}.",
+                efd_names.join(", ")
+            ))));
+        }
+        Ok(comparison)
+    }
+
+    /// Add the summary of site `name`.
+    pub fn with_site(mut self, name: &str, summary: Summary) -> Self {
+        self.sites.push((name.to_owned(), summary));
+        self
+    }
+
+    /// Names of the sites, in the order they were added.
+    pub fn get_site_names(&self) -> Vec<&str> {
+        self.sites.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Repositories in the summary of any of the sites, sorted.
+    pub fn get_repositories(&self) -> BTreeSet<&str> {
+        self.sites
+            .iter()
+            .flat_map(|(_, summary)| summary.get_versions().keys())
+            .map(|repository| repository.as_str())
+            .collect()
+    }
+
+    /// Version of `repository` at each site, "" if the site has no version
+    /// for it.
+    pub fn get_versions(&self, repository: &str) -> Vec<&str> {
+        self.sites
+            .iter()
+            .map(|(_, summary)| summary.get_version(repository).unwrap_or_default())
+            .collect()
+    }
+
+    /// Does the version of `repository` differ between the sites?
+    pub fn is_different(&self, repository: &str) -> bool {
+        let versions = self.get_versions(repository);
+        versions.iter().any(|version| *version != versions[0])
+    }
+
+    /// Side-by-side comparison table, one line per repository after a header
+    /// line. Lines of repositories with different versions between sites
+    /// start with "*".
+    pub fn to_table(&self) -> Vec<String> {
+        let repositories = self.get_repositories();
+        let mut rows = vec![[vec!["repository"], self.get_site_names()].concat()];
+        rows.extend(
+            repositories
+                .iter()
+                .map(|repository| [vec![*repository], self.get_versions(repository)].concat()),
+        );
+        let widths: Vec<usize> = (0..rows[0].len())
+            .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+            .collect();
+        rows.iter()
+            .map(|row| {
+                let marker = if self.is_different(row[0]) { "*" } else { " " };
+                let cells: Vec<String> = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, width)| format!("{cell:width$}"))
+                    .collect();
+                format!("{marker} {}", cells.join(" | ").trim_end())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SiteComparison;
+    use crate::sasquatch::log_summary::Summary;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_site_comparison() {
+        let summary = |versions: &[(&str, &str)]| {
+            Summary::from_btree_map(&BTreeMap::from_iter(
+                versions
+                    .iter()
+                    .map(|(name, version)| (name.to_string(), Ok(version.to_string()))),
+            ))
+        };
+        let comparison = SiteComparison::default()
+            .with_site(
+                "summit_efd",
+                summary(&[("ts_wep", "v1.0.0"), ("ts_ofc", "v2.0.0")]),
+            )
+            .with_site(
+                "base_efd",
+                summary(&[("ts_wep", "v1.1.0"), ("ts_ofc", "v2.0.0")]),
+            );
+
+        assert!(comparison.is_different("ts_wep"));
+        assert!(!comparison.is_different("ts_ofc"));
+        assert_eq!(
+            comparison.to_table(),
+            vec![
+                "  repository | summit_efd | base_efd",
+                "  ts_ofc     | v2.0.0     | v2.0.0",
+                "* ts_wep     | v1.0.0     | v1.1.0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use super::sender::QueuedPayload;
+use crate::error::ObsEnvError;
+use chrono::Utc;
+use std::{
+    fs::{create_dir_all, read_dir, read_to_string, remove_file, rename, write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Name of the spool directory, in the environment, used by default.
+pub const SPOOL_DIR_NAME: &str = ".obs_env_telemetry";
+
+const SPOOL_EXTENSION: &str = "json";
+const CLAIMED_EXTENSION: &str = "sending";
+
+static SPOOLED: AtomicUsize = AtomicUsize::new(0);
+
+/// Local journal of telemetry payloads that could not be sent.
+///
+/// Each payload is stored in its own file, named so that sorting the names
+/// gives the order in which they were spooled. Before sending a spooled
+/// payload it is claimed by renaming the file, so concurrent invocations do
+/// not send it twice.
+#[derive(Clone, Debug)]
+pub struct TelemetrySpool {
+    dir: PathBuf,
+}
+
+impl TelemetrySpool {
+    pub fn new(dir: &Path) -> TelemetrySpool {
+        TelemetrySpool {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    pub fn get_dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Store a payload in the spool.
+    pub(super) fn store(&self, queued: &QueuedPayload) -> Result<PathBuf, ObsEnvError> {
+        create_dir_all(&self.dir).map_err(|error| {
+            ObsEnvError::ERROR(format!(
+                "Failed to create telemetry spool {:?}: {error}",
+                self.dir
+            ))
+        })?;
+        let content = serde_json::to_string(queued).map_err(|error| {
+            ObsEnvError::ERROR(format!("Failed to serialize spooled payload: {error}"))
+        })?;
+        let path = self.dir.join(format!(
+            "{:013}-{}-{}-{}.{SPOOL_EXTENSION}",
+            Utc::now().timestamp_millis(),
+            process::id(),
+            SPOOLED.fetch_add(1, Ordering::Relaxed),
+            queued.topic_name
+        ));
+        write(&path, content).map_err(|error| {
+            ObsEnvError::ERROR(format!("Failed to write spooled payload {path:?}: {error}"))
+        })?;
+        Ok(path)
+    }
+
+    /// Paths of the spooled payloads, oldest first.
+    pub fn pending(&self) -> Vec<PathBuf> {
+        let Ok(entries) = read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut pending: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == SPOOL_EXTENSION))
+            .collect();
+        pending.sort();
+        pending
+    }
+
+    /// Claim and load the spooled payload at `path`.
+    ///
+    /// Returns None if the payload was claimed by someone else in the
+    /// meantime. The claimed payload must be either `release`d or
+    /// `complete`d.
+    pub(super) fn claim(&self, path: &Path) -> Option<(PathBuf, QueuedPayload)> {
+        let claimed = path.with_extension(CLAIMED_EXTENSION);
+        rename(path, &claimed).ok()?;
+        match read_to_string(&claimed)
+            .map_err(|error| error.to_string())
+            .and_then(|content| serde_json::from_str(&content).map_err(|error| error.to_string()))
+        {
+            Ok(queued) => Some((claimed, queued)),
+            Err(error) => {
+                log::error!("Discarding unreadable spooled payload {path:?}: {error}");
+                let _ = remove_file(&claimed);
+                None
+            }
+        }
+    }
+
+    /// Put a claimed payload back in the spool.
+    pub(super) fn release(&self, claimed: &Path) {
+        if let Err(error) = rename(claimed, claimed.with_extension(SPOOL_EXTENSION)) {
+            log::error!("Failed to return {claimed:?} to the telemetry spool: {error}");
+        }
+    }
+
+    /// Remove a claimed payload that was sent.
+    pub(super) fn complete(&self, claimed: &Path) {
+        if let Err(error) = remove_file(claimed) {
+            log::error!("Failed to remove sent payload {claimed:?} from the spool: {error}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TelemetrySpool;
+    use crate::sasquatch::sender::QueuedPayload;
+    use std::{env, fs, process};
+
+    #[test]
+    fn test_spool() {
+        let dir = env::temp_dir().join(format!("obs_env_spool_test_{}", process::id()));
+        let spool = TelemetrySpool::new(&dir);
+        assert!(spool.pending().is_empty());
+
+        for topic_name in ["action", "summary"] {
+            spool
+                .store(&QueuedPayload {
+                    topic_name: topic_name.to_owned(),
+                    payload: serde_json::json!({"records": []}),
+                    rest_proxy_url: None,
+                })
+                .unwrap();
+        }
+        let pending = spool.pending();
+        assert_eq!(pending.len(), 2);
+
+        let (claimed, queued) = spool.claim(&pending[0]).unwrap();
+        assert_eq!(queued.topic_name, "action");
+        assert!(spool.claim(&pending[0]).is_none());
+        assert_eq!(spool.pending().len(), 1);
+
+        spool.release(&claimed);
+        assert_eq!(spool.pending(), pending);
+
+        let (claimed, _) = spool.claim(&pending[1]).unwrap();
+        spool.complete(&claimed);
+        assert_eq!(spool.pending().len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+/// Configuration of the topics created by `create_topics`.
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub struct TopicConfig {
+    topic_name: String,
+    partitions_count: usize,
+    replication_factor: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    configs: Vec<ConfigEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+struct ConfigEntry {
+    name: String,
+    value: String,
+}
+
+/// Configuration value of a topic.
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub struct TopicConfigValue {
+    pub name: String,
+    pub value: Option<String>,
+    pub is_default: bool,
+}
+
+impl TopicConfig {
+    pub fn with_topic_name(mut self, topic_name: &str) -> Self {
+        self.topic_name = topic_name.to_owned();
+        self
+    }
+
+    pub fn with_partitions_count(mut self, partitions_count: usize) -> Self {
+        self.partitions_count = partitions_count;
+        self
+    }
+
+    pub fn with_replication_factor(mut self, replication_factor: usize) -> Self {
+        self.replication_factor = replication_factor;
+        self
+    }
+
+    /// Set how long records are kept, in milliseconds (-1 for no limit).
+    pub fn with_retention_ms(self, retention_ms: i64) -> Self {
+        self.with_config("retention.ms", &retention_ms.to_string())
+    }
+
+    /// Set the cleanup policy ("delete" or "compact").
+    pub fn with_cleanup_policy(self, cleanup_policy: &str) -> Self {
+        self.with_config("cleanup.policy", cleanup_policy)
+    }
+
+    pub fn get_topic_name(&self) -> &str {
+        &self.topic_name
+    }
+
+    pub fn get_partitions_count(&self) -> usize {
+        self.partitions_count
+    }
+
+    pub fn get_replication_factor(&self) -> usize {
+        self.replication_factor
+    }
+
+    fn with_config(mut self, name: &str, value: &str) -> Self {
+        self.configs.retain(|config| config.name != name);
+        self.configs.push(ConfigEntry {
+            name: name.to_owned(),
+            value: value.to_owned(),
+        });
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TopicConfig;
+
+    #[test]
+    fn test_topic_config() {
+        let topic_config = TopicConfig::default()
+            .with_topic_name("lsst.obsenv.action")
+            .with_partitions_count(1)
+            .with_replication_factor(1)
+            .with_retention_ms(1000)
+            .with_retention_ms(2000)
+            .with_cleanup_policy("delete");
+
+        assert_eq!(
+            serde_json::to_value(&topic_config).unwrap(),
+            serde_json::json!({
+                "topic_name": "lsst.obsenv.action",
+                "partitions_count": 1,
+                "replication_factor": 1,
+                "configs": [
+                    {"name": "retention.ms", "value": "2000"},
+                    {"name": "cleanup.policy", "value": "delete"},
+                ],
+            })
+        );
+        assert!(serde_json::to_value(TopicConfig::default()).unwrap()["configs"].is_null());
+    }
+}
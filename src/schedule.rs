@@ -0,0 +1,74 @@
+use crate::error::ObsEnvError;
+use chrono::{DateTime, Local};
+use std::str::FromStr;
+
+/// Cron-like schedule used to run actions periodically.
+pub struct Schedule {
+    schedule: cron::Schedule,
+}
+
+impl Schedule {
+    /// Parse a cron expression.
+    ///
+    /// Accepts the usual 5 field crontab format
+    /// (`minute hour day-of-month month day-of-week`), e.g. "0 18 * * *" for
+    /// every day at 18:00 local time. A leading seconds field (6 fields) and
+    /// a trailing year field (7 fields) are also accepted. Prefer names
+    /// (e.g. Mon-Fri) for the day of the week, since numbers start at 1 for
+    /// Sunday.
+    pub fn parse(expression: &str) -> Result<Schedule, ObsEnvError> {
+        let expression = if expression.split_whitespace().count() == 5 {
+            format!("0 {expression}")
+        } else {
+            expression.to_owned()
+        };
+        match cron::Schedule::from_str(&expression) {
+            Ok(schedule) => Ok(Schedule { schedule }),
+            Err(error) => Err(ObsEnvError::ERROR(format!(
+                "Invalid schedule {expression:?}: {error}"
+            ))),
+        }
+    }
+
+    /// Get the next time the schedule fires after `after`.
+    pub fn next_after(&self, after: &DateTime<Local>) -> Option<DateTime<Local>> {
+        self.schedule.after(after).next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Schedule;
+    use chrono::{Local, TimeZone, Timelike};
+
+    #[test]
+    fn test_parse_crontab() {
+        let schedule = Schedule::parse("0 18 * * *").unwrap();
+        let now = Local.with_ymd_and_hms(2024, 6, 1, 12, 30, 0).unwrap();
+
+        let next = schedule.next_after(&now).unwrap();
+
+        assert_eq!(next, Local.with_ymd_and_hms(2024, 6, 1, 18, 0, 0).unwrap());
+        assert_eq!(
+            schedule.next_after(&next).unwrap(),
+            Local.with_ymd_and_hms(2024, 6, 2, 18, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_with_seconds() {
+        let schedule = Schedule::parse("30 */15 * * * *").unwrap();
+        let now = Local.with_ymd_and_hms(2024, 6, 1, 12, 1, 0).unwrap();
+
+        let next = schedule.next_after(&now).unwrap();
+
+        assert_eq!(next.minute(), 15);
+        assert_eq!(next.second(), 30);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(Schedule::parse("not a schedule").is_err());
+        assert!(Schedule::parse("0 25 * * *").is_err());
+    }
+}
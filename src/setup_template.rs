@@ -0,0 +1,869 @@
+use crate::error::ObsEnvError;
+use chrono::Utc;
+use clap::ValueEnum;
+use minijinja::{context, value::ViaDeserialize, Environment, Value};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{read_dir, remove_file, rename, File},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+};
+
+/// Number of backups of the previous setup files kept by default.
+pub const DEFAULT_SETUP_FILE_BACKUPS: usize = 5;
+
+/// Format of the timestamp suffix of the setup file backups, down to the
+/// microsecond so backups made within the same second don't clash.
+const BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%6fZ";
+
+/// Lengths of the timestamp suffix of the setup file backups, with and
+/// without (older backups) the microseconds.
+const BACKUP_TIMESTAMP_LENGTHS: [usize; 2] = [22, 16];
+
+/// Default name of the bash setup file, in the environment.
+pub const SETUP_FILE_NAME: &str = "auto_env_setup.sh";
+
+/// Template used to generate the bash setup file unless one is given.
+///
+/// The setup files of all shells end by sourcing the personal setup file of
+/// the user for the shell, e.g. "~/.obs_env_user_setup" for bash, if it
+/// exists.
+pub const DEFAULT_BASH_TEMPLATE: &str = "#!/usr/bin/env bash
+# This file is auto generated by the manage_obs_env scripts.
+# It is sourced by the ~/notebooks/.user_setups file
+# Do not modify!
+# Created at {{ timestamp }} UTC by {{ user }}
+#
+# Versions at generation time:
+{% for name, version in versions|items -%}
+#   {{ name }} {{ version or 'unknown' }}
+{% endfor %}
+{% for repository in repositories -%}
+{{ repository|setup }}
+{% endfor %}
+# Personal additions, kept when the setup file is regenerated.
+if [ -f \"$HOME/.obs_env_user_setup\" ]; then
+    source \"$HOME/.obs_env_user_setup\"
+fi
+";
+
+/// Template used to generate the csh setup file unless one is given.
+pub const DEFAULT_CSH_TEMPLATE: &str = "#!/bin/csh
+# This file is auto generated by the manage_obs_env scripts.
+# Do not modify!
+# Created at {{ timestamp }} UTC by {{ user }}
+#
+# Versions at generation time:
+{% for name, version in versions|items -%}
+#   {{ name }} {{ version or 'unknown' }}
+{% endfor %}
+{% for repository in repositories -%}
+{{ repository|setup }}
+{% endfor %}
+# Personal additions, kept when the setup file is regenerated.
+if ( -f \"$HOME/.obs_env_user_setup.csh\" ) source \"$HOME/.obs_env_user_setup.csh\"
+";
+
+/// Template used to generate the fish setup file unless one is given.
+///
+/// EUPS does not support fish, so repositories set up with EUPS expect a
+/// `setup` function wrapping it to be defined.
+pub const DEFAULT_FISH_TEMPLATE: &str = "#!/usr/bin/env fish
+# This file is auto generated by the manage_obs_env scripts.
+# Do not modify!
+# Created at {{ timestamp }} UTC by {{ user }}
+#
+# Versions at generation time:
+{% for name, version in versions|items -%}
+#   {{ name }} {{ version or 'unknown' }}
+{% endfor %}
+{% for repository in repositories -%}
+{{ repository|setup }}
+{% endfor %}
+# Personal additions, kept when the setup file is regenerated.
+if test -f \"$HOME/.obs_env_user_setup.fish\"
+    source \"$HOME/.obs_env_user_setup.fish\"
+end
+";
+
+/// Template used to generate the Lmod modulefile unless one is given.
+///
+/// Lmod has no equivalent of EUPS setups and pip installs, so they are run
+/// in the user shell when the module is loaded.
+pub const DEFAULT_LMOD_TEMPLATE: &str = "-- -*- lua -*-
+-- This file is auto generated by the manage_obs_env scripts.
+-- Do not modify!
+-- Created at {{ timestamp }} UTC by {{ user }}
+--
+-- Versions at generation time:
+{% for name, version in versions|items -%}
+--   {{ name }} {{ version or 'unknown' }}
+{% endfor %}
+whatis(\"Observing environment in \" .. {{ destination|quote }})
+
+{% for repository in repositories -%}
+{{ repository|setup }}
+{% endfor %}";
+
+/// Shell the setup file is generated for.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SetupShell {
+    Bash,
+    Csh,
+    Fish,
+    /// Lmod modulefile, to load the environment with `module load`.
+    Lmod,
+}
+
+impl SetupShell {
+    /// Extension of the setup file for the shell.
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            SetupShell::Bash => "sh",
+            SetupShell::Csh => "csh",
+            SetupShell::Fish => "fish",
+            SetupShell::Lmod => "lua",
+        }
+    }
+
+    /// Default name of the setup file for the shell, in the environment.
+    pub fn get_file_name(&self) -> &'static str {
+        match self {
+            SetupShell::Bash => SETUP_FILE_NAME,
+            SetupShell::Csh => "auto_env_setup.csh",
+            SetupShell::Fish => "auto_env_setup.fish",
+            SetupShell::Lmod => "auto_env_setup.lua",
+        }
+    }
+
+    /// Commands setting up `repository` in the shell.
+    pub fn get_setup_command(&self, repository: &SetupRepository) -> String {
+        let path = self.quote(&repository.path);
+        let python_path = self.quote(&format!("{}/python", repository.path));
+        match (self, repository.style) {
+            (_, SetupStyle::None) => String::new(),
+            // EUPS setups and pip installs are run by the user shell.
+            (SetupShell::Lmod, SetupStyle::Eups | SetupStyle::PipEditable) => format!(
+                "execute{{cmd={}, modeA={{\"load\"}}}}",
+                self.quote(&SetupShell::Bash.get_setup_command(repository))
+            ),
+            (_, SetupStyle::Eups) => {
+                format!("setup -j {} -r {path}", self.quote(&repository.product))
+            }
+            (_, SetupStyle::PipEditable) => format!("pip install --quiet --no-deps -e {path}"),
+            (SetupShell::Bash, SetupStyle::Pythonpath) => {
+                format!("export PYTHONPATH={python_path}${{PYTHONPATH:+:${{PYTHONPATH}}}}")
+            }
+            (SetupShell::Csh, SetupStyle::Pythonpath) => format!(
+                "if ($?PYTHONPATH) then\n    \
+                setenv PYTHONPATH {python_path}:${{PYTHONPATH}}\n\
+                else\n    \
+                setenv PYTHONPATH {python_path}\n\
+                endif"
+            ),
+            (SetupShell::Fish, SetupStyle::Pythonpath) => {
+                format!("set -gx --prepend PYTHONPATH {python_path}")
+            }
+            (SetupShell::Lmod, SetupStyle::Pythonpath) => {
+                format!("prepend_path(\"PYTHONPATH\", {python_path})")
+            }
+        }
+    }
+
+    pub fn get_default_template(&self) -> &'static str {
+        match self {
+            SetupShell::Bash => DEFAULT_BASH_TEMPLATE,
+            SetupShell::Csh => DEFAULT_CSH_TEMPLATE,
+            SetupShell::Fish => DEFAULT_FISH_TEMPLATE,
+            SetupShell::Lmod => DEFAULT_LMOD_TEMPLATE,
+        }
+    }
+
+    /// Quote `value` so the shell reads it as a single word, as is.
+    ///
+    /// Values with only characters that are not special to any of the
+    /// shells are not quoted. For Lmod, values are always quoted as Lua
+    /// strings.
+    pub fn quote(&self, value: &str) -> String {
+        let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-./:=@+,%".contains(c);
+        if *self != SetupShell::Lmod && !value.is_empty() && value.chars().all(is_safe) {
+            return value.to_owned();
+        }
+        match self {
+            SetupShell::Bash => format!("'{}'", value.replace('\'', r"'\''")),
+            // History substitution applies even within single quotes.
+            SetupShell::Csh => format!("'{}'", value.replace('\'', r"'\''").replace('!', r"'\!'")),
+            SetupShell::Fish => format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'")),
+            SetupShell::Lmod => format!(
+                "\"{}\"",
+                value
+                    .replace('\\', r"\\")
+                    .replace('"', r#"\""#)
+                    .replace('\n', r"\n")
+                    .replace('\r', r"\r")
+            ),
+        }
+    }
+}
+
+/// Minijinja template of the setup files, given as `[SHELL=]PATH`.
+///
+/// A template with a shell (e.g. "csh=auto_env_setup.csh.j2") is used for
+/// the setup file of that shell. A template without a shell is only used
+/// when generating the setup file of a single shell, as the syntax of the
+/// setup files differs between shells.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct SetupTemplate {
+    shell: Option<SetupShell>,
+    path: String,
+}
+
+impl SetupTemplate {
+    /// Template in the file at `path`, for the only shell.
+    pub fn new(path: &str) -> SetupTemplate {
+        SetupTemplate {
+            shell: None,
+            path: path.to_owned(),
+        }
+    }
+
+    /// Template in the file at `path`, for `shell`.
+    pub fn for_shell(shell: SetupShell, path: &str) -> SetupTemplate {
+        SetupTemplate {
+            shell: Some(shell),
+            path: path.to_owned(),
+        }
+    }
+
+    pub fn get_shell(&self) -> Option<SetupShell> {
+        self.shell
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl FromStr for SetupTemplate {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<SetupTemplate, String> {
+        let template = match spec.split_once('=') {
+            Some((shell, path)) => {
+                match SetupShell::from_str(shell, true) {
+                    Ok(shell) => SetupTemplate::for_shell(shell, path),
+                    // "=" is part of the path.
+                    Err(_) => SetupTemplate::new(spec),
+                }
+            }
+            None => SetupTemplate::new(spec),
+        };
+        if template.path.is_empty() {
+            return Err(format!(
+                "No template path in {spec:?}, expected [SHELL=]PATH"
+            ));
+        }
+        Ok(template)
+    }
+}
+
+impl TryFrom<String> for SetupTemplate {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<SetupTemplate, String> {
+        spec.parse()
+    }
+}
+
+impl fmt::Display for SetupTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.shell {
+            Some(shell) => {
+                // This should never fail because no SetupShell is skipped.
+                let shell = shell.to_possible_value().unwrap();
+                write!(f, "{}={}", shell.get_name(), self.path)
+            }
+            None => write!(f, "{}", self.path),
+        }
+    }
+}
+
+/// How a repository is set up by the setup file.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SetupStyle {
+    /// Not set up.
+    #[default]
+    None,
+    /// EUPS `setup -j`.
+    Eups,
+    /// Editable pip install (`pip install -e`).
+    PipEditable,
+    /// Prepend the "python" directory of the repository to PYTHONPATH.
+    Pythonpath,
+}
+
+/// How the setup file sets up the repositories.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SetupMode {
+    /// Each repository is set up following its setup style.
+    #[default]
+    PerRepository,
+    /// All repositories are set up by prepending their "python" directory
+    /// to PYTHONPATH, for environments without EUPS.
+    Pythonpath,
+}
+
+impl SetupMode {
+    /// Setup style of a repository configured with `style`.
+    pub fn get_style(&self, style: SetupStyle) -> SetupStyle {
+        match (self, style) {
+            (SetupMode::Pythonpath, SetupStyle::Eups | SetupStyle::PipEditable) => {
+                SetupStyle::Pythonpath
+            }
+            _ => style,
+        }
+    }
+}
+
+/// What happens to the setup file after an action that modifies the
+/// environment, other than Setup which always generates it.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SetupFileRefresh {
+    /// Leave the setup file as it is.
+    Never,
+    /// Check the setup file against the environment and warn about stale or
+    /// missing entries.
+    #[default]
+    Validate,
+    /// Generate the setup file again.
+    Regenerate,
+}
+
+/// Repository set up by the setup file.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetupRepository {
+    pub name: String,
+    pub style: SetupStyle,
+    /// Name of the EUPS product.
+    pub product: String,
+    /// Path of the repository clone.
+    pub path: String,
+    /// Current version of the repository.
+    pub version: String,
+}
+
+/// Variables available to the setup file template.
+#[derive(Clone, Debug, Serialize)]
+pub struct SetupContext {
+    /// Path of the environment.
+    pub destination: String,
+    /// Generation time, UTC.
+    pub timestamp: String,
+    /// User generating the file.
+    pub user: String,
+    /// Repositories to set up, in order.
+    pub repositories: Vec<SetupRepository>,
+    /// Current versions of all the repositories in the environment.
+    pub versions: BTreeMap<String, String>,
+}
+
+/// Render the setup file for `shell` from `template` (a minijinja template),
+/// or from the default template of the shell if None.
+///
+/// Besides the variables of `context`, templates have the name of the shell
+/// in `shell`, a `quote` filter quoting values for the shell and a `setup`
+/// filter giving the commands setting up a repository.
+pub fn render_setup_file(
+    template: Option<&str>,
+    shell: SetupShell,
+    context: &SetupContext,
+) -> Result<String, ObsEnvError> {
+    let mut env = Environment::new();
+    env.set_keep_trailing_newline(true);
+    env.add_filter("quote", move |value: String| shell.quote(&value));
+    env.add_filter(
+        "setup",
+        move |repository: ViaDeserialize<SetupRepository>| shell.get_setup_command(&repository),
+    );
+    env.add_template(
+        shell.get_file_name(),
+        template.unwrap_or(shell.get_default_template()),
+    )
+    .map_err(|error| ObsEnvError::ERROR(format!("Invalid setup file template: {error}")))?;
+    env.get_template(shell.get_file_name())
+        .and_then(|template| {
+            template.render(context! { shell => shell, ..Value::from_serialize(context) })
+        })
+        .map_err(|error| ObsEnvError::ERROR(format!("Failed to render the setup file: {error}")))
+}
+
+/// Write the setup file at `path` with `content`, replacing it atomically
+/// if it exists.
+///
+/// The content is written to a temporary file in the same directory, which
+/// is then renamed to `path`, so users always get either the previous or
+/// the new setup file, even if the process dies while writing it.
+pub fn write_setup_file(path: &Path, content: &str) -> Result<(), ObsEnvError> {
+    let file_name = path
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy())
+        .unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(".{file_name}.{}.tmp", process::id()));
+    let result = File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(content.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| rename(&tmp_path, path));
+    if let Err(error) = result {
+        let _ = remove_file(&tmp_path);
+        return Err(ObsEnvError::ERROR(format!(
+            "Failed to write {path:?}: {error}"
+        )));
+    }
+    Ok(())
+}
+
+/// Copy the setup file at `path` to "<path>.<timestamp>", keeping only the
+/// `keep` most recent backups. Existing backups are never overwritten.
+///
+/// Returns the path of the backup, None if there is no setup file or
+/// `keep` is 0.
+pub fn backup_setup_file(path: &Path, keep: usize) -> Result<Option<PathBuf>, ObsEnvError> {
+    if keep == 0 || !path.exists() {
+        return Ok(None);
+    }
+    let backup_path = loop {
+        let backup_path = PathBuf::from(format!(
+            "{}.{}",
+            path.display(),
+            Utc::now().format(BACKUP_TIMESTAMP_FORMAT)
+        ));
+        let result = File::open(path).and_then(|mut setup_file| {
+            File::options()
+                .write(true)
+                .create_new(true)
+                .open(&backup_path)
+                .and_then(|mut backup| io::copy(&mut setup_file, &mut backup))
+        });
+        match result {
+            Ok(_) => break backup_path,
+            // Made within the same microsecond, try again with a later one.
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+            Err(error) => {
+                // The backup may not have been created, so failing to remove
+                // it is expected.
+                let _ = remove_file(&backup_path);
+                return Err(ObsEnvError::ERROR(format!(
+                    "Failed to backup {path:?} to {backup_path:?}: {error}"
+                )));
+            }
+        }
+    };
+    let backups = get_backups(path);
+    for backup in backups.iter().take(backups.len().saturating_sub(keep)) {
+        log::debug!("Removing old setup file backup {backup:?}.");
+        if let Err(error) = remove_file(backup) {
+            log::warn!("Failed to remove old setup file backup {backup:?}: {error}");
+        }
+    }
+    Ok(Some(backup_path))
+}
+
+/// Backups of the setup file at `path`, oldest first.
+pub fn get_backups(path: &Path) -> Vec<PathBuf> {
+    let (Some(dir), Some(file_name)) = (path.parent(), path.file_name()) else {
+        return Vec::new();
+    };
+    let prefix = format!("{}.", file_name.to_string_lossy());
+    let Ok(entries) = read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut backups: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .strip_prefix(&prefix)
+                .is_some_and(|suffix| {
+                    BACKUP_TIMESTAMP_LENGTHS.contains(&suffix.len())
+                        && suffix
+                            .chars()
+                            .all(|c| c.is_ascii_digit() || c == 'T' || c == 'Z')
+                })
+        })
+        .map(|entry| entry.path())
+        .collect();
+    backups.sort();
+    backups
+}
+
+/// Repository path referenced by a line of a setup file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetupEntry {
+    /// Line number, starting at 1.
+    pub line: usize,
+    /// Path of the repository set up.
+    pub path: String,
+}
+
+/// Find the repositories set up by a setup file of `shell`, from its
+/// `setup -r` and `pip install -e` lines, and the lines prepending to
+/// PYTHONPATH.
+pub fn parse_setup_file(shell: SetupShell, content: &str) -> Vec<SetupEntry> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            Some(SetupEntry {
+                line: index + 1,
+                path: parse_setup_line(shell, line)?,
+            })
+        })
+        .collect()
+}
+
+/// Path of the repository set up by a line of a setup file of `shell`.
+fn parse_setup_line(shell: SetupShell, line: &str) -> Option<String> {
+    let python_path = if shell == SetupShell::Lmod {
+        let line = line.trim_start();
+        let strings = get_lua_strings(line);
+        if line.starts_with("execute") {
+            // EUPS setups and pip installs are run as bash commands.
+            return parse_setup_line(SetupShell::Bash, strings.first()?);
+        } else if line.starts_with("prepend_path") && strings.first()? == "PYTHONPATH" {
+            strings.get(1)?.to_owned()
+        } else {
+            return None;
+        }
+    } else {
+        let words = split_words(line);
+        match words.first()?.as_str() {
+            "setup" => return get_option(&words, "-r"),
+            "pip" if words.iter().any(|word| word == "install") => return get_option(&words, "-e"),
+            "export" => words.get(1)?.strip_prefix("PYTHONPATH=")?.to_owned(),
+            "setenv" if words.get(1)? == "PYTHONPATH" => words.get(2)?.to_owned(),
+            "set" => get_option(&words, "PYTHONPATH")?,
+            _ => return None,
+        }
+    };
+    let python_path = python_path.split([':', '$']).next()?;
+    Some(
+        python_path
+            .strip_suffix("/python")
+            .unwrap_or(python_path)
+            .to_owned(),
+    )
+}
+
+/// Value following `option` in `words`.
+fn get_option(words: &[String], option: &str) -> Option<String> {
+    words
+        .iter()
+        .skip_while(|word| *word != option)
+        .nth(1)
+        .cloned()
+}
+
+/// Double quoted strings of a line of a Lmod modulefile, unescaped, up to
+/// its comment.
+fn get_lua_strings(line: &str) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let mut string = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some('n') => string.push('\n'),
+                            Some('r') => string.push('\r'),
+                            c => string.extend(c),
+                        },
+                        c => string.push(c),
+                    }
+                }
+                strings.push(string);
+            }
+            '-' if chars.as_str().starts_with('-') => break,
+            _ => (),
+        }
+    }
+    strings
+}
+
+/// Split a line of a bash script into words, removing the quotes and
+/// comments.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                word.extend(chars.by_ref().take_while(|c| *c != '\''));
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => word.extend(chars.next()),
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            '#' if word.is_none() => break,
+            c if c.is_whitespace() => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        backup_setup_file, get_backups, parse_setup_file, render_setup_file, split_words,
+        write_setup_file, SetupContext, SetupEntry, SetupRepository, SetupShell, SetupStyle,
+        SetupTemplate,
+    };
+    use std::{collections::BTreeMap, env, fs, process};
+
+    fn get_context() -> SetupContext {
+        SetupContext {
+            destination: "/obs-env".to_owned(),
+            timestamp: "2024-01-01 00:00:00".to_owned(),
+            user: "saluser".to_owned(),
+            repositories: vec![SetupRepository {
+                name: "ts_wep".to_owned(),
+                style: SetupStyle::Eups,
+                product: "ts_wep".to_owned(),
+                path: "/obs-env/ts_wep".to_owned(),
+                version: "v9.0.0".to_owned(),
+            }],
+            versions: BTreeMap::from_iter([("ts_wep".to_owned(), "v9.0.0".to_owned())]),
+        }
+    }
+
+    #[test]
+    fn test_render_default_template() {
+        let content = render_setup_file(None, SetupShell::Bash, &get_context()).unwrap();
+
+        assert!(content.starts_with("#!/usr/bin/env bash\n"));
+        assert!(content.contains("# Created at 2024-01-01 00:00:00 UTC by saluser\n"));
+        assert!(content.contains("# Versions at generation time:\n#   ts_wep v9.0.0\n\n"));
+        assert!(content.contains("\nsetup -j ts_wep -r /obs-env/ts_wep\n\n"));
+        assert!(content.ends_with(
+            "\nif [ -f \"$HOME/.obs_env_user_setup\" ]; then\n    \
+            source \"$HOME/.obs_env_user_setup\"\nfi\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_template() {
+        let template = "# {{ destination }}\n\
+            {% for name, version in versions|items %}# {{ name }}={{ version }}\n{% endfor %}";
+
+        assert_eq!(
+            render_setup_file(Some(template), SetupShell::Bash, &get_context()).unwrap(),
+            "# /obs-env\n# ts_wep=v9.0.0\n"
+        );
+        assert_eq!(
+            render_setup_file(Some("{{ shell }}"), SetupShell::Fish, &get_context()).unwrap(),
+            "fish"
+        );
+        assert!(render_setup_file(Some("{% for %}"), SetupShell::Bash, &get_context()).is_err());
+    }
+
+    #[test]
+    fn test_setup_template() {
+        let template: SetupTemplate = "Csh=templates/setup.csh.j2".parse().unwrap();
+        assert_eq!(template.get_shell(), Some(SetupShell::Csh));
+        assert_eq!(template.get_path(), "templates/setup.csh.j2");
+        assert_eq!(template.to_string(), "csh=templates/setup.csh.j2");
+
+        let template: SetupTemplate = "templates/a=b.j2".parse().unwrap();
+        assert_eq!(template, SetupTemplate::new("templates/a=b.j2"));
+        assert!("".parse::<SetupTemplate>().is_err());
+        assert!("bash=".parse::<SetupTemplate>().is_err());
+    }
+
+    #[test]
+    fn test_render_shells() {
+        let mut context = get_context();
+        context.repositories[0].path = "/obs env/it's".to_owned();
+
+        let csh = render_setup_file(None, SetupShell::Csh, &context).unwrap();
+        assert!(csh.starts_with("#!/bin/csh\n"));
+        assert!(csh.contains("\nsetup -j ts_wep -r '/obs env/it'\\''s'\n"));
+        assert!(csh.ends_with(" source \"$HOME/.obs_env_user_setup.csh\"\n"));
+        let fish = render_setup_file(None, SetupShell::Fish, &context).unwrap();
+        assert!(fish.contains("\nsetup -j ts_wep -r '/obs env/it\\'s'\n"));
+        assert!(fish.ends_with("\n    source \"$HOME/.obs_env_user_setup.fish\"\nend\n"));
+        let lmod = render_setup_file(None, SetupShell::Lmod, &context).unwrap();
+        assert!(lmod.starts_with("-- -*- lua -*-\n"));
+        assert!(lmod.contains("\nwhatis(\"Observing environment in \" .. \"/obs-env\")\n"));
+        assert!(lmod.ends_with(
+            "\nexecute{cmd=\"setup -j ts_wep -r '/obs env/it'\\\\''s'\", modeA={\"load\"}}\n"
+        ));
+    }
+
+    #[test]
+    fn test_setup_command() {
+        let mut repository = get_context().repositories.remove(0);
+        let command =
+            |shell: SetupShell, repository: &SetupRepository| shell.get_setup_command(repository);
+
+        repository.style = SetupStyle::PipEditable;
+        assert_eq!(
+            command(SetupShell::Bash, &repository),
+            "pip install --quiet --no-deps -e /obs-env/ts_wep"
+        );
+        repository.style = SetupStyle::Pythonpath;
+        assert_eq!(
+            command(SetupShell::Bash, &repository),
+            "export PYTHONPATH=/obs-env/ts_wep/python${PYTHONPATH:+:${PYTHONPATH}}"
+        );
+        assert!(command(SetupShell::Csh, &repository)
+            .contains("setenv PYTHONPATH /obs-env/ts_wep/python:${PYTHONPATH}\n"));
+        assert_eq!(
+            command(SetupShell::Fish, &repository),
+            "set -gx --prepend PYTHONPATH /obs-env/ts_wep/python"
+        );
+        assert_eq!(
+            command(SetupShell::Lmod, &repository),
+            r#"prepend_path("PYTHONPATH", "/obs-env/ts_wep/python")"#
+        );
+        repository.style = SetupStyle::None;
+        assert_eq!(command(SetupShell::Bash, &repository), "");
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(SetupShell::Bash.quote("/obs-env/ts_wep"), "/obs-env/ts_wep");
+        assert_eq!(SetupShell::Bash.quote(""), "''");
+        assert_eq!(SetupShell::Bash.quote("a b"), "'a b'");
+        assert_eq!(SetupShell::Bash.quote("it's"), r"'it'\''s'");
+        assert_eq!(SetupShell::Csh.quote("a!b"), r"'a'\!'b'");
+        assert_eq!(SetupShell::Fish.quote(r"a\b'c"), r"'a\\b\'c'");
+        assert_eq!(SetupShell::Lmod.quote("a\"b\\c\n"), r#""a\"b\\c\n""#);
+    }
+
+    #[test]
+    fn test_write_setup_file() {
+        let dir = env::temp_dir().join(format!("obs_env_setup_file_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("auto_env_setup.sh");
+
+        write_setup_file(&path, "old").unwrap();
+        write_setup_file(&path, "new").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert!(write_setup_file(&dir.join("missing/auto_env_setup.sh"), "").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backup_setup_file() {
+        let dir = env::temp_dir().join(format!("obs_env_setup_backup_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("auto_env_setup.sh");
+        assert_eq!(backup_setup_file(&path, 2).unwrap(), None);
+        for timestamp in ["20240101T000000Z", "20240102T000000Z"] {
+            fs::write(dir.join(format!("auto_env_setup.sh.{timestamp}")), "").unwrap();
+        }
+        fs::write(dir.join("auto_env_setup.sh.orig"), "").unwrap();
+        fs::write(&path, "current").unwrap();
+
+        let backup = backup_setup_file(&path, 2).unwrap().unwrap();
+
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "current");
+        let backups = get_backups(&path);
+        assert_eq!(backups.len(), 2);
+        assert!(backups[0].ends_with("auto_env_setup.sh.20240102T000000Z"));
+        assert_eq!(backups[1], backup);
+        assert!(dir.join("auto_env_setup.sh.orig").exists());
+        assert_eq!(backup_setup_file(&path, 0).unwrap(), None);
+
+        // Backups made within the same second are all kept.
+        let backups: Vec<_> = (0..3)
+            .map(|_| backup_setup_file(&path, 5).unwrap().unwrap())
+            .collect();
+        assert_eq!(get_backups(&path).len(), 5);
+        assert!(backups.iter().all(|backup| backup.exists()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(
+            split_words(r#"setup -j 'a b' -r "/obs env"\ x # comment"#),
+            vec!["setup", "-j", "a b", "-r", "/obs env x"]
+        );
+        assert!(split_words("# comment").is_empty());
+    }
+
+    #[test]
+    fn test_parse_setup_file() {
+        let mut context = get_context();
+        context.repositories[0].path = "/obs env/ts_wep".to_owned();
+        let mut content = render_setup_file(None, SetupShell::Bash, &context).unwrap();
+        for style in [SetupStyle::PipEditable, SetupStyle::Pythonpath] {
+            context.repositories[0].style = style;
+            content.push_str(&SetupShell::Bash.get_setup_command(&context.repositories[0]));
+            content.push('\n');
+        }
+
+        let entries = parse_setup_file(SetupShell::Bash, &content);
+
+        assert_eq!(entries.len(), 3);
+        assert!(entries.iter().all(|entry| entry.path == "/obs env/ts_wep"));
+        assert_eq!(
+            entries[0],
+            SetupEntry {
+                line: 10,
+                path: "/obs env/ts_wep".to_owned()
+            }
+        );
+
+        // The csh PYTHONPATH setup sets it on two lines.
+        for (shell, count) in [
+            (SetupShell::Csh, 4),
+            (SetupShell::Fish, 3),
+            (SetupShell::Lmod, 3),
+        ] {
+            let content: Vec<String> = [
+                SetupStyle::Eups,
+                SetupStyle::PipEditable,
+                SetupStyle::Pythonpath,
+            ]
+            .into_iter()
+            .map(|style| {
+                context.repositories[0].style = style;
+                shell.get_setup_command(&context.repositories[0])
+            })
+            .collect();
+
+            let entries = parse_setup_file(shell, &content.join("\n"));
+
+            assert_eq!(entries.len(), count, "{shell:?}");
+            assert!(entries.iter().all(|entry| entry.path == "/obs env/ts_wep"));
+        }
+    }
+}
@@ -2,5 +2,5 @@ use ts_observing_environment::observing_environment::ObservingEnvironment;
 
 #[test]
 fn test_observing_environment() {
-    let obs_env = ObservingEnvironment::with_destination("/tmp");
+    let _obs_env = ObservingEnvironment::with_destination("/tmp");
 }